@@ -0,0 +1,156 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use tantivy::tokenizer::{SimpleTokenizer, TextAnalyzer};
+
+use buzuki_search::config::NumberPolicy;
+use buzuki_search::greek_lower_caser::GreekLowerCaser;
+use buzuki_search::search_engine::{Mode, ResultOptions, SearchEngine, Sort};
+use buzuki_search::song::Song;
+use buzuki_search::tokenizer::NgramTokenizer;
+use buzuki_search::transliteration::Transliteration;
+
+const WORDS: &[&str] = &[
+    "Μάρκος",
+    "Φραγκοσυριανή",
+    "καρδιά",
+    "φούντωση",
+    "φλόγα",
+    "μαγεία",
+    "γλυκιά",
+    "παράθυρα",
+    "Πειραιά",
+    "συννεφιασμένη",
+    "Κυριακή",
+    "μαύρα",
+    "φρύδια",
+    "γειτονιά",
+    "τρελός",
+    "γυρίζω",
+    "παράπονο",
+    "δακρύζω",
+    "ζεϊμπέκικο",
+    "μπουζούκι",
+];
+
+/// Deterministic pseudo-random words, so that every run indexes the same corpus.
+fn words(seed: &mut u64, count: usize) -> String {
+    (0..count)
+        .map(|_| {
+            *seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+            WORDS[(*seed >> 33) as usize % WORDS.len()]
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Songs with a two word name, one of ten artists and twenty lines of lyrics.
+fn corpus(size: usize) -> Vec<Song> {
+    let transliteration = Transliteration::new(NumberPolicy::Keep);
+    let mut seed = 0;
+    (0..size)
+        .map(|i| {
+            let name = words(&mut seed, 2);
+            let artist = format!("Καλλιτέχνης {}", i % 10);
+            let body = (0..20)
+                .map(|_| words(&mut seed, 8))
+                .collect::<Vec<_>>()
+                .join("\n");
+            Song::new(name, artist, body, &transliteration)
+        })
+        .collect()
+}
+
+fn count_tokens(analyzer: &TextAnalyzer, text: &str) -> usize {
+    let mut token_stream = analyzer.token_stream(text);
+    let mut count = 0;
+    while token_stream.advance() {
+        count += 1;
+    }
+    count
+}
+
+fn bench_tokenizers(c: &mut Criterion) {
+    let mut seed = 0;
+    let text = words(&mut seed, 1000);
+    let mut group = c.benchmark_group("tokenizers");
+    group.throughput(Throughput::Bytes(text.len() as u64));
+
+    let ngram = TextAnalyzer::from(NgramTokenizer::limit(40));
+    group.bench_function("ngram", |b| b.iter(|| count_tokens(&ngram, &text)));
+
+    let simple = TextAnalyzer::from(SimpleTokenizer);
+    group.bench_function("simple", |b| b.iter(|| count_tokens(&simple, &text)));
+
+    let lower_caser = TextAnalyzer::from(SimpleTokenizer).filter(GreekLowerCaser::default());
+    group.bench_function("greek_lower_caser", |b| {
+        b.iter(|| count_tokens(&lower_caser, &text))
+    });
+
+    let homophones =
+        TextAnalyzer::from(SimpleTokenizer).filter(GreekLowerCaser::folding_homophones());
+    group.bench_function("greek_lower_caser_homophones", |b| {
+        b.iter(|| count_tokens(&homophones, &text))
+    });
+    group.finish();
+}
+
+fn bench_index(c: &mut Criterion) {
+    let songs = corpus(200);
+    let mut group = c.benchmark_group("index");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(songs.len() as u64));
+    group.bench_function("build", |b| {
+        b.iter_batched(
+            || songs.clone(),
+            |songs| SearchEngine::from_songs(songs).unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+fn bench_queries(c: &mut Criterion) {
+    let engine = SearchEngine::from_songs(corpus(1000)).unwrap();
+    let mut group = c.benchmark_group("queries");
+    for &(name, query, mode) in &[
+        ("greek_word", "καρδιά", Mode::Full),
+        ("greek_words", "μαύρα φρύδια", Mode::Full),
+        ("greeklish", "mavra frydia", Mode::Full),
+        ("misspelled", "τρελλος", Mode::Full),
+        ("prefix", "συννεφ", Mode::Ngram),
+        ("exact", "καρδιά", Mode::Exact),
+        ("no_hits", "ρεμπέτικο", Mode::Full),
+    ] {
+        group.bench_function(name, |b| {
+            b.iter(|| engine.hits(query, mode, Sort::Relevance).unwrap())
+        });
+    }
+    group.finish();
+}
+
+/// Building the results of a query that matches every song, at the limit of full searches.
+fn bench_results(c: &mut Criterion) {
+    let engine = SearchEngine::from_songs(corpus(1000)).unwrap();
+    let options = ResultOptions {
+        limit: Some(1000),
+        ..ResultOptions::default()
+    };
+    let mut group = c.benchmark_group("results");
+    group.throughput(Throughput::Elements(1000));
+    group.bench_function("search_1000_hits", |b| {
+        b.iter(|| {
+            engine
+                .search("καρδιά", Mode::Full, Sort::Relevance, &options)
+                .unwrap()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_tokenizers,
+    bench_index,
+    bench_queries,
+    bench_results
+);
+criterion_main!(benches);
@@ -0,0 +1,17 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use tantivy::tokenizer::Tokenizer;
+
+use buzuki_search::tokenizer::NgramTokenizer;
+
+fuzz_target!(|text: &str| {
+    let mut token_stream = NgramTokenizer::limit(40).token_stream(text);
+    while token_stream.advance() {
+        let token = token_stream.token();
+        // Offsets are used to highlight the matches, so they must slice the text.
+        assert_eq!(
+            text.get(token.offset_from..token.offset_to),
+            Some(&*token.text)
+        );
+    }
+});
@@ -0,0 +1,13 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use buzuki_search::config::NumberPolicy;
+use buzuki_search::song::{LyricsFilter, Song};
+use buzuki_search::transliteration::Transliteration;
+
+fuzz_target!(|contents: &str| {
+    let transliteration = Transliteration::new(NumberPolicy::Keep);
+    if let Ok(song) = Song::parse(contents, &transliteration, &LyricsFilter::default()) {
+        song.preview();
+    }
+});
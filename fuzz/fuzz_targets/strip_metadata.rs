@@ -0,0 +1,8 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use buzuki_search::song::strip_metadata;
+
+fuzz_target!(|text: &str| {
+    strip_metadata(text);
+});
@@ -0,0 +1,13 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use buzuki_search::config::NumberPolicy;
+use buzuki_search::transliteration::Transliteration;
+
+fuzz_target!(|text: &str| {
+    let transliteration = Transliteration::new(NumberPolicy::Keep);
+    transliteration.to_greeklish(text);
+    transliteration.to_slug(text);
+    transliteration.to_greek_candidates(text);
+    transliteration.expand_query(text);
+});
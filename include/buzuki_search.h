@@ -0,0 +1,40 @@
+/* C bindings of buzuki-search, implemented in src/ffi.rs and built with `make ffi`.
+ *
+ * Returned strings are owned by the caller and must be freed with buzuki_string_free. On
+ * failure functions return NULL, and buzuki_last_error describes the error. */
+
+#ifndef BUZUKI_SEARCH_H
+#define BUZUKI_SEARCH_H
+
+#include <stdbool.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct BuzukiSearchEngine BuzukiSearchEngine;
+
+/* Message of the last error of the calling thread, or NULL. Owned by the library and valid
+ * until the next call that fails. */
+const char *buzuki_last_error(void);
+
+void buzuki_string_free(char *string);
+
+char *buzuki_to_greeklish(const char *text);
+
+char *buzuki_strip_metadata(const char *text);
+
+/* Index the song files of the directory. Returns NULL on failure. */
+BuzukiSearchEngine *buzuki_search_engine_new(const char *song_dir);
+
+void buzuki_search_engine_free(BuzukiSearchEngine *engine);
+
+/* Search and return the results as a JSON array. Autocomplete searches match prefixes of
+ * words. */
+char *buzuki_search(const BuzukiSearchEngine *engine, const char *query, bool autocomplete);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,118 @@
+//! Cancellation of running searches. Clients that search as the user types supersede their
+//! previous search with every keystroke, so the superseded search is stopped while it collects
+//! its matches instead of running to completion for results that nobody reads.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use tantivy::collector::{Collector, SegmentCollector};
+use tantivy::query::Weight;
+use tantivy::{DocId, Score, SegmentLocalId, SegmentReader, TantivyError, TERMINATED};
+
+/// Flag that a search checks while it runs. Clones share the flag, so the search can be
+/// cancelled from another thread.
+#[derive(Clone, Debug, Default)]
+pub struct CancelFlag(Arc<AtomicBool>);
+
+impl CancelFlag {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Collector that stops the search as soon as the flag is set, checking it before every
+/// segment and every matching document. A cancelled search fails with an error.
+pub struct Cancellable<C> {
+    collector: C,
+    flag: CancelFlag,
+}
+
+impl<C> Cancellable<C> {
+    pub fn new(collector: C, flag: CancelFlag) -> Cancellable<C> {
+        Cancellable { collector, flag }
+    }
+}
+
+impl<C: Collector> Collector for Cancellable<C> {
+    type Fruit = C::Fruit;
+    type Child = C::Child;
+
+    fn for_segment(
+        &self,
+        segment_local_id: SegmentLocalId,
+        segment: &SegmentReader,
+    ) -> tantivy::Result<C::Child> {
+        self.collector.for_segment(segment_local_id, segment)
+    }
+
+    fn requires_scoring(&self) -> bool {
+        self.collector.requires_scoring()
+    }
+
+    fn merge_fruits(
+        &self,
+        segment_fruits: Vec<<C::Child as SegmentCollector>::Fruit>,
+    ) -> tantivy::Result<C::Fruit> {
+        self.collector.merge_fruits(segment_fruits)
+    }
+
+    fn collect_segment(
+        &self,
+        weight: &dyn Weight,
+        segment_ord: u32,
+        reader: &SegmentReader,
+    ) -> tantivy::Result<<C::Child as SegmentCollector>::Fruit> {
+        let cancelled = || TantivyError::SystemError(String::from("The search was cancelled"));
+        if self.flag.is_cancelled() {
+            return Err(cancelled());
+        }
+        let mut segment_collector = self.for_segment(segment_ord, reader)?;
+        let mut scorer = weight.scorer(reader, 1.0)?;
+        let delete_bitset = reader.delete_bitset();
+        let mut doc: DocId = scorer.doc();
+        while doc != TERMINATED {
+            if self.flag.is_cancelled() {
+                return Err(cancelled());
+            }
+            if delete_bitset.is_none_or(|deleted| deleted.is_alive(doc)) {
+                let score: Score = scorer.score();
+                segment_collector.collect(doc, score);
+            }
+            doc = scorer.advance();
+        }
+        Ok(segment_collector.harvest())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tantivy::collector::Count;
+    use tantivy::query::AllQuery;
+    use tantivy::schema::{Schema, STRING};
+    use tantivy::{doc, Index};
+
+    use crate::cancellation::{CancelFlag, Cancellable};
+
+    #[test]
+    fn test_cancellable() {
+        let mut schema = Schema::builder();
+        let name = schema.add_text_field("name", STRING);
+        let index = Index::create_in_ram(schema.build());
+        let mut writer = index.writer_with_num_threads(1, 3_000_000).unwrap();
+        writer.add_document(doc!(name => "a"));
+        writer.add_document(doc!(name => "b"));
+        writer.commit().unwrap();
+        let searcher = index.reader().unwrap().searcher();
+
+        let flag = CancelFlag::default();
+        let collector = Cancellable::new(Count, flag.clone());
+        assert_eq!(searcher.search(&AllQuery, &collector).unwrap(), 2);
+        flag.cancel();
+        assert!(flag.is_cancelled());
+        assert!(searcher.search(&AllQuery, &collector).is_err());
+    }
+}
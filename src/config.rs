@@ -0,0 +1,914 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::error::{Error, Result};
+use crate::ranking::Variant;
+use crate::search_engine::{Mode, Quotas};
+use crate::song::{Dashes, LyricsFilter, MetadataLines, CHORD_PATTERN};
+use crate::transliteration::{fnv1a, Transliteration};
+
+/// Variables that configure the server, keyed by name, e.g. "BUZUKI_SONGDIR".
+pub type Vars = HashMap<String, String>;
+
+/// Runtime configuration, read from the environment and the config file.
+#[derive(Clone)]
+pub struct Config {
+    /// Directory containing the song files.
+    pub song_dir: String,
+    /// Bucket to read the song files from, instead of the song directory.
+    pub song_bucket: Option<S3Location>,
+    /// File of `KEY=value` lines that override the environment variables. It is watched while
+    /// serving, and changes of the score cutoffs, boosts, experiment, query limits, endpoint
+    /// settings and CORS origins are applied without restarting. See [`Effect::of`].
+    pub config_file: Option<PathBuf>,
+    /// Token that grants access to admin-only features. Admin features are disabled if unset.
+    pub admin_token: Option<String>,
+    /// Hits of full mode searches scoring below this are dropped. Searches in ngram mode, e.g.
+    /// autocomplete, and in exact mode, i.e. with accents=exact, are not cut off.
+    pub min_score: Option<f32>,
+    /// Hits of full mode searches scoring below this fraction of the top hit's score are
+    /// dropped. Like `min_score`, it doesn't apply to the other modes.
+    pub min_relative_score: Option<f32>,
+    /// Whether numbers are indexed and kept in slugs and greeklish.
+    pub numbers: NumberPolicy,
+    /// Romanization of the names and lyrics into greeklish.
+    pub transliteration: Transliteration,
+    /// Maximum length of the indexed words of each analyzer.
+    pub word_limits: WordLimits,
+    /// Weights of the ranking signals.
+    pub boosts: Boosts,
+    /// Second ranking, compared with the first one.
+    pub experiment: Option<Experiment>,
+    /// File of song view counts, blended into the ranking by the popularity boost.
+    pub popularity: Option<PathBuf>,
+    /// File of other spellings of the scales, on top of the built in ones.
+    pub scale_aliases: Option<PathBuf>,
+    /// Whether the first lines of the lyrics of each song are stored and returned as its
+    /// preview.
+    pub previews: bool,
+    /// Whether the lyrics of each song file are also stored with their chords.
+    pub raw_bodies: bool,
+    /// Whether the names are also stored and returned romanized, for Latin script frontends.
+    pub greeklish_names: bool,
+    /// Whether the lyrics are also stored, for highlighting them and for substring searches.
+    pub highlights: bool,
+    /// How chords and other metadata are stripped from the lyrics.
+    pub lyrics: LyricsFilter,
+    /// Base url of the site, used to build absolute urls in the sitemap.
+    pub base_url: Option<String>,
+    /// Origins allowed to make cross-origin requests, e.g. "https://buzuki.gr". Any origin is
+    /// allowed if unset.
+    pub cors_origins: Option<Vec<String>>,
+    /// Number of async worker threads, one per core if unset.
+    pub worker_threads: Option<usize>,
+    /// Maximum number of threads running searches and other blocking work.
+    pub blocking_threads: Option<usize>,
+    /// Number of threads that search the index segments in parallel. Segments are searched on
+    /// the calling thread if unset.
+    pub search_threads: Option<usize>,
+    /// Resources used by reindexing.
+    pub indexing: IndexingLimits,
+    /// Whether the index is shared with other instances through snapshots.
+    pub distribution: Distribution,
+    /// Directory that keeps the index across restarts, instead of a temporary one.
+    pub index_dir: Option<PathBuf>,
+    /// Whether to start serving the index kept in the index directory right away, while the
+    /// songs are reindexed in the background.
+    pub lazy_start: bool,
+    /// Interval at which followers check for a new snapshot.
+    pub snapshot_poll: Duration,
+    /// Time after which HTTP requests are aborted.
+    pub request_timeout: Duration,
+    /// Address of the HTTP server.
+    pub http_addr: SocketAddr,
+    /// Address of the gRPC server, if built with the grpc feature. Disabled if unset.
+    pub grpc_addr: Option<SocketAddr>,
+    /// Query length limits of GET /.
+    pub search_limits: QueryLimits,
+    /// Query length limits of GET /autocomplete/.
+    pub autocomplete_limits: QueryLimits,
+    /// Parser and results of GET /.
+    pub search: Endpoint,
+    /// Parser and results of GET /autocomplete/.
+    pub autocomplete: Endpoint,
+}
+
+/// Weights of the ranking signals. Artists and scales only have a short name, which
+/// field-length normalization favours over song names, so they can be boosted less.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Boosts {
+    /// Exponent of the text relevance. 0 ranks by the other signals alone.
+    pub relevance: f32,
+    pub song: f32,
+    pub artist: f32,
+    pub scale: f32,
+    /// Extra score of songs whose file was modified just now, as a fraction of their score. It
+    /// halves every month after that, and 0 disables it. It has no effect on wasm32, which has
+    /// no clock.
+    pub recency: f32,
+    /// Extra score of songs per unit of the logarithm of their view count, as a fraction of
+    /// their score. 0 disables it.
+    pub popularity: f32,
+    /// Extra factor of the hits named exactly like the query, which moves them to the top. 0
+    /// disables it.
+    pub exact_match: f32,
+}
+
+impl Default for Boosts {
+    fn default() -> Boosts {
+        Boosts {
+            relevance: 1.0,
+            song: 1.0,
+            artist: 1.0,
+            scale: 1.0,
+            recency: 0.0,
+            popularity: 0.0,
+            exact_match: 1.0,
+        }
+    }
+}
+
+impl Boosts {
+    /// Read the weights from the variables with the prefix, e.g. BUZUKI_SONG_BOOST for the
+    /// prefix BUZUKI, falling back to the defaults.
+    fn from_vars(vars: &Vars, prefix: &str, defaults: Boosts) -> Result<Boosts> {
+        let weight = |name: &str, default: f32| -> Result<f32> {
+            let key = format!("{}_{}", prefix, name);
+            Ok(parse_var(vars, &key)?.unwrap_or(default))
+        };
+        Ok(Boosts {
+            relevance: weight("RELEVANCE_WEIGHT", defaults.relevance)?,
+            song: weight("SONG_BOOST", defaults.song)?,
+            artist: weight("ARTIST_BOOST", defaults.artist)?,
+            scale: weight("SCALE_BOOST", defaults.scale)?,
+            recency: weight("RECENCY_BOOST", defaults.recency)?,
+            popularity: weight("POPULARITY_BOOST", defaults.popularity)?,
+            exact_match: weight("EXACT_MATCH_BOOST", defaults.exact_match)?,
+        })
+    }
+}
+
+/// A second ranking, served to a share of the clients to compare it with the first one on real
+/// traffic.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Experiment {
+    /// Weights of variant B: those of variant A, overridden by the BUZUKI_B_ variables.
+    pub boosts: Boosts,
+    /// Fraction of the clients that are served variant B.
+    pub share: f32,
+}
+
+impl Experiment {
+    /// Read the experiment, if any BUZUKI_B_ weight is set.
+    fn from_vars(vars: &Vars, boosts: Boosts) -> Result<Option<Experiment>> {
+        let has_weights = vars
+            .keys()
+            .any(|key| key.starts_with("BUZUKI_B_") && key != "BUZUKI_B_SHARE");
+        if !has_weights {
+            return Ok(None);
+        }
+        let share = parse_var(vars, "BUZUKI_B_SHARE")?.unwrap_or(0.5);
+        if !(0.0..=1.0).contains(&share) {
+            let message = format!("BUZUKI_B_SHARE must be between 0 and 1, not {}", share);
+            return Err(Error::Parse(message));
+        }
+        Ok(Some(Experiment {
+            boosts: Boosts::from_vars(vars, "BUZUKI_B", boosts)?,
+            share,
+        }))
+    }
+
+    /// Variant of a request: the requested one, or else one picked by hashing the client id,
+    /// so that each client keeps getting the same one. Anonymous requests get variant A.
+    pub fn variant(&self, requested: Option<Variant>, client: Option<&str>) -> Variant {
+        if let Some(variant) = requested {
+            return variant;
+        }
+        let client = match client {
+            Some(client) => client,
+            None => return Variant::A,
+        };
+        let bucket = (fnv1a(client) % 1000) as f32 / 1000.0;
+        if bucket < self.share {
+            Variant::B
+        } else {
+            Variant::A
+        }
+    }
+}
+
+/// S3-compatible bucket holding the song files, one object per song.
+#[derive(Clone, Debug, PartialEq)]
+pub struct S3Location {
+    pub bucket: String,
+    /// Only objects with keys starting with the prefix are read, e.g. "songs/".
+    pub prefix: String,
+    pub region: String,
+    /// Endpoint of an S3-compatible store, e.g. "http://localhost:9000". AWS if unset.
+    pub endpoint: Option<String>,
+}
+
+/// Resources used while reindexing. Searches keep being served from the previous index until
+/// reindexing finishes, and these keep them from slowing down on small machines.
+#[derive(Clone, Copy)]
+pub struct IndexingLimits {
+    /// Number of indexing threads, one per core up to 8 if unset.
+    pub threads: Option<usize>,
+    /// Memory budget of the indexing threads in bytes, at least 3MB per thread.
+    pub heap_size: usize,
+    /// Pause after indexing each song, to spread the work of reindexing over time.
+    pub pause: Duration,
+}
+
+impl Default for IndexingLimits {
+    fn default() -> IndexingLimits {
+        IndexingLimits {
+            threads: None,
+            heap_size: 50_000_000,
+            pause: Duration::from_millis(0),
+        }
+    }
+}
+
+/// How the index is shared between instances, to scale search traffic horizontally.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Distribution {
+    /// The index is built from the songs and not shared.
+    Standalone,
+    /// The index is built from the songs and published as a snapshot in the directory after
+    /// every reindex.
+    Publish(PathBuf),
+    /// The latest snapshot in the directory is served. The songs are not indexed.
+    Follow(PathBuf),
+}
+
+/// How numbers in names and lyrics, e.g. "30 δραχμές", are handled.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NumberPolicy {
+    /// Numbers are indexed and kept in slugs and greeklish. This is the default, e.g.
+    /// "Μινόρε του 1936" gets the slug "minore_tou_1936".
+    Keep,
+    /// Numbers are not indexed and are removed from slugs and greeklish, which keeps the urls
+    /// that songs with numbers in their names had before numbers were kept.
+    Drop,
+}
+
+impl FromStr for NumberPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<NumberPolicy, String> {
+        match s {
+            "keep" => Ok(NumberPolicy::Keep),
+            "drop" => Ok(NumberPolicy::Drop),
+            _ => Err(format!("Unknown number policy {}", s)),
+        }
+    }
+}
+
+/// Length in bytes from which words are not indexed, for each analyzer.
+#[derive(Clone)]
+pub struct WordLimits {
+    pub max_len: usize,
+    /// Per analyzer overrides, keyed by analyzer name, e.g. "el_ngram".
+    pub overrides: HashMap<String, usize>,
+}
+
+impl Default for WordLimits {
+    fn default() -> WordLimits {
+        WordLimits {
+            max_len: 40,
+            overrides: HashMap::new(),
+        }
+    }
+}
+
+impl WordLimits {
+    /// Read the default limit from `BUZUKI_MAX_WORD_LEN` and the overrides from variables
+    /// named after the analyzer, e.g. `BUZUKI_MAX_WORD_LEN_EL_NGRAM`.
+    fn from_vars(vars: &Vars) -> Result<WordLimits> {
+        let prefix = "BUZUKI_MAX_WORD_LEN_";
+        let mut overrides = HashMap::new();
+        for key in vars.keys().filter(|key| key.starts_with(prefix)) {
+            if let Some(max_len) = parse_var(vars, key)? {
+                overrides.insert(key[prefix.len()..].to_lowercase(), max_len);
+            }
+        }
+        Ok(WordLimits {
+            max_len: parse_var(vars, "BUZUKI_MAX_WORD_LEN")?
+                .unwrap_or(WordLimits::default().max_len),
+            overrides,
+        })
+    }
+
+    pub fn get(&self, analyzer: &str) -> usize {
+        self.overrides
+            .get(analyzer)
+            .copied()
+            .unwrap_or(self.max_len)
+    }
+}
+
+/// Limits on the length of a query, in characters and in whitespace separated words.
+#[derive(Clone)]
+pub struct QueryLimits {
+    pub max_chars: Option<usize>,
+    pub max_tokens: Option<usize>,
+    /// Truncate queries that exceed the limits instead of rejecting them.
+    pub truncate: bool,
+}
+
+impl QueryLimits {
+    /// Read the limits from the variables with the given prefix, e.g.
+    /// `BUZUKI_AUTOCOMPLETE_MAX_QUERY_CHARS`, falling back to the given defaults.
+    fn from_vars(
+        vars: &Vars,
+        prefix: &str,
+        max_chars: usize,
+        max_tokens: usize,
+    ) -> Result<QueryLimits> {
+        let key = format!("{}_QUERY_LENGTH_POLICY", prefix);
+        let truncate = match parse_var::<String>(vars, &key)?.as_deref() {
+            Some("truncate") | None => true,
+            Some("reject") => false,
+            Some(policy) => {
+                return Err(Error::Parse(format!(
+                    "Invalid value for {}: {}",
+                    key, policy
+                )));
+            }
+        };
+        Ok(QueryLimits {
+            max_chars: parse_var(vars, &format!("{}_MAX_QUERY_CHARS", prefix))?.or(Some(max_chars)),
+            max_tokens: parse_var(vars, &format!("{}_MAX_QUERY_TOKENS", prefix))?
+                .or(Some(max_tokens)),
+            truncate,
+        })
+    }
+
+    /// Return the query, truncated if it is too long and truncation is enabled, or an error
+    /// message if it is too long and truncation is disabled.
+    pub fn apply<'a>(&self, query: &'a str) -> Result<Cow<'a, str>, String> {
+        let mut query = Cow::Borrowed(query);
+        if let Some(max_tokens) = self.max_tokens {
+            if query.split_whitespace().count() > max_tokens {
+                if !self.truncate {
+                    return Err(format!("Query is longer than {} words", max_tokens));
+                }
+                let tokens: Vec<&str> = query.split_whitespace().take(max_tokens).collect();
+                query = Cow::Owned(tokens.join(" "));
+            }
+        }
+        if let Some(max_chars) = self.max_chars {
+            if query.chars().count() > max_chars {
+                if !self.truncate {
+                    return Err(format!("Query is longer than {} characters", max_chars));
+                }
+                query = Cow::Owned(query.chars().take(max_chars).collect());
+            }
+        }
+        Ok(query)
+    }
+}
+
+/// How a change of a variable is applied while serving.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Effect {
+    /// Applied to the following requests.
+    Reload,
+    /// Applied on restart, when the songs are indexed with it.
+    Reindex,
+    /// Applied on restart.
+    Restart,
+}
+
+impl Effect {
+    /// How a change of the variable is applied. The analyzers have no stopword or synonym
+    /// lists, so the settings of the analyzers are only the reindexed ones.
+    pub fn of(key: &str) -> Effect {
+        // Settings of the search endpoints, read per request.
+        let endpoint = key
+            .strip_prefix("BUZUKI_SEARCH_")
+            .or_else(|| key.strip_prefix("BUZUKI_AUTOCOMPLETE_"));
+        if let Some(
+            "MAX_QUERY_CHARS"
+            | "MAX_QUERY_TOKENS"
+            | "QUERY_LENGTH_POLICY"
+            | "MODE"
+            | "LIMIT"
+            | "MAX_LIMIT"
+            | "FIELDS"
+            | "QUOTAS",
+        ) = endpoint
+        {
+            return Effect::Reload;
+        }
+        match key {
+            "BUZUKI_MIN_SCORE"
+            | "BUZUKI_MIN_RELATIVE_SCORE"
+            | "BUZUKI_SONG_BOOST"
+            | "BUZUKI_ARTIST_BOOST"
+            | "BUZUKI_SCALE_BOOST"
+            | "BUZUKI_RECENCY_BOOST"
+            | "BUZUKI_POPULARITY_BOOST"
+            | "BUZUKI_RELEVANCE_WEIGHT"
+            | "BUZUKI_EXACT_MATCH_BOOST"
+            | "BUZUKI_CORS_ORIGINS" => Effect::Reload,
+            "BUZUKI_NUMBERS"
+            | "BUZUKI_TRANSLITERATION"
+            | "BUZUKI_GREEKLISH_CHARS"
+            | "BUZUKI_POPULARITY_FILE"
+            | "BUZUKI_SCALE_ALIASES"
+            | "BUZUKI_PREVIEWS"
+            | "BUZUKI_RAW_BODIES"
+            | "BUZUKI_GREEKLISH_NAMES"
+            | "BUZUKI_HIGHLIGHTS"
+            | "BUZUKI_METADATA_LINES"
+            | "BUZUKI_CHORD_PATTERN"
+            | "BUZUKI_REPETITION_PATTERN"
+            | "BUZUKI_DASHES"
+            | "BUZUKI_PERFORMANCE_NOTES" => Effect::Reindex,
+            _ if key.starts_with("BUZUKI_B_") => Effect::Reload,
+            _ if key.starts_with("BUZUKI_MAX_WORD_LEN") => Effect::Reindex,
+            _ => Effect::Restart,
+        }
+    }
+}
+
+/// Names of the variables that differ between the two sets, sorted.
+pub fn changed_vars<'a>(old: &'a Vars, new: &'a Vars) -> Vec<&'a str> {
+    let mut keys: Vec<&str> = old
+        .keys()
+        .chain(new.keys())
+        .filter(|key| old.get(*key) != new.get(*key))
+        .map(String::as_str)
+        .collect();
+    keys.sort_unstable();
+    keys.dedup();
+    keys
+}
+
+/// Read a config file of `KEY=value` lines. Empty lines and lines starting with # are skipped.
+pub fn read_config_file(path: &Path) -> Result<Vars> {
+    let mut vars = Vars::new();
+    for (number, line) in std::fs::read_to_string(path)?.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.split_once('=') {
+            Some((key, val)) => vars.insert(key.trim().to_string(), val.trim().to_string()),
+            None => {
+                let message = format!("{}:{}: Expected KEY=value", path.display(), number + 1);
+                return Err(Error::Parse(message));
+            }
+        };
+    }
+    Ok(vars)
+}
+
+/// How a search endpoint parses queries and which results it returns.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Endpoint {
+    /// Query parser. Full searches switch to the exact parser for `accents=exact`.
+    pub mode: Mode,
+    /// Number of results, unless the request sets `limit`.
+    pub default_limit: usize,
+    /// Largest `limit` a request may set.
+    pub max_limit: usize,
+    /// Stored fields of the results, all of them if unset.
+    pub fields: Option<Vec<String>>,
+    /// Maximum number of results of each type.
+    pub quotas: Option<Quotas>,
+}
+
+impl Endpoint {
+    pub fn new(mode: Mode, default_limit: usize) -> Endpoint {
+        Endpoint {
+            mode,
+            default_limit,
+            max_limit: default_limit,
+            fields: None,
+            quotas: None,
+        }
+    }
+
+    /// Read the settings from the variables with the given prefix, e.g. `BUZUKI_SEARCH_MODE`,
+    /// `BUZUKI_SEARCH_LIMIT`, `BUZUKI_SEARCH_MAX_LIMIT`, `BUZUKI_SEARCH_FIELDS`, a comma
+    /// separated list of field names, and `BUZUKI_SEARCH_QUOTAS`, e.g. "songs:10,artists:3".
+    /// The maximum limit defaults to the default limit.
+    fn from_vars(vars: &Vars, prefix: &str, defaults: Endpoint) -> Result<Endpoint> {
+        let default_limit =
+            parse_var(vars, &format!("{}_LIMIT", prefix))?.unwrap_or(defaults.default_limit);
+        let max_limit = parse_var(vars, &format!("{}_MAX_LIMIT", prefix))?.unwrap_or(default_limit);
+        if default_limit == 0 || default_limit > max_limit {
+            return Err(Error::Parse(format!(
+                "{}_LIMIT must be between 1 and {}_MAX_LIMIT",
+                prefix, prefix
+            )));
+        }
+        let fields = parse_var::<String>(vars, &format!("{}_FIELDS", prefix))?.map(|fields| {
+            fields
+                .split(',')
+                .map(str::trim)
+                .filter(|field| !field.is_empty())
+                .map(String::from)
+                .collect()
+        });
+        Ok(Endpoint {
+            mode: parse_var(vars, &format!("{}_MODE", prefix))?.unwrap_or(defaults.mode),
+            default_limit,
+            max_limit,
+            fields,
+            quotas: parse_var(vars, &format!("{}_QUOTAS", prefix))?,
+        })
+    }
+
+    /// Return the number of results for the requested limit, or an error message if it is out
+    /// of range.
+    pub fn limit(&self, limit: Option<usize>) -> Result<usize, String> {
+        match limit {
+            None => Ok(self.default_limit),
+            Some(limit) if limit >= 1 && limit <= self.max_limit => Ok(limit),
+            Some(_) => Err(format!("limit must be between 1 and {}", self.max_limit)),
+        }
+    }
+}
+
+/// Parse an optional variable, failing if it is set but invalid.
+fn parse_var<T: FromStr>(vars: &Vars, key: &str) -> Result<Option<T>> {
+    let val = match vars.get(key).filter(|val| !val.is_empty()) {
+        Some(val) => val,
+        None => return Ok(None),
+    };
+    match val.parse() {
+        Ok(val) => Ok(Some(val)),
+        Err(_) => Err(Error::Parse(format!("Invalid value for {}: {}", key, val))),
+    }
+}
+
+impl Config {
+    /// Read the configuration from the environment and the config file named by
+    /// `BUZUKI_CONFIG`, if set.
+    pub fn from_env() -> Result<Config> {
+        Config::from_vars(&Config::read_vars()?)
+    }
+
+    /// Variables of the environment, overridden by those of the config file.
+    pub fn read_vars() -> Result<Vars> {
+        let mut vars: Vars = std::env::vars().collect();
+        if let Some(path) = parse_var::<PathBuf>(&vars, "BUZUKI_CONFIG")? {
+            vars.extend(read_config_file(&path)?);
+        }
+        Ok(vars)
+    }
+
+    pub fn from_vars(vars: &Vars) -> Result<Config> {
+        let song_bucket = match parse_var::<String>(vars, "BUZUKI_S3_BUCKET")? {
+            Some(bucket) => Some(S3Location {
+                bucket,
+                prefix: parse_var(vars, "BUZUKI_S3_PREFIX")?.unwrap_or_default(),
+                region: parse_var(vars, "BUZUKI_S3_REGION")?
+                    .unwrap_or_else(|| "us-east-1".to_string()),
+                endpoint: parse_var(vars, "BUZUKI_S3_ENDPOINT")?,
+            }),
+            None => None,
+        };
+
+        // The song directory isn't needed if the songs are read from a bucket.
+        let key = "BUZUKI_SONGDIR";
+        let song_dir = match vars.get(key) {
+            Some(song_dir) => song_dir.clone(),
+            None if song_bucket.is_some() => String::new(),
+            None => return Err(Error::Parse(format!("Couldn't get {}: not set", key))),
+        };
+
+        let admin_token = vars
+            .get("BUZUKI_ADMIN_TOKEN")
+            .filter(|token| !token.is_empty())
+            .cloned();
+
+        let numbers = parse_var(vars, "BUZUKI_NUMBERS")?.unwrap_or(NumberPolicy::Keep);
+        let mut transliteration = match parse_var::<String>(vars, "BUZUKI_TRANSLITERATION")? {
+            Some(path) => Transliteration::from_file(&path, numbers)?,
+            None => Transliteration::new(numbers),
+        };
+        if let Some(chars) = parse_var::<String>(vars, "BUZUKI_GREEKLISH_CHARS")? {
+            transliteration = transliteration.with_allowed_chars(&chars)?;
+        }
+
+        let distribution = match (
+            parse_var(vars, "BUZUKI_PUBLISH_DIR")?,
+            parse_var(vars, "BUZUKI_FOLLOW_DIR")?,
+        ) {
+            (None, None) => Distribution::Standalone,
+            (Some(dir), None) => Distribution::Publish(dir),
+            (None, Some(dir)) => Distribution::Follow(dir),
+            (Some(_), Some(_)) => {
+                let message = "BUZUKI_PUBLISH_DIR and BUZUKI_FOLLOW_DIR are mutually exclusive";
+                return Err(Error::Parse(String::from(message)));
+            }
+        };
+
+        let index_dir = parse_var(vars, "BUZUKI_INDEX_DIR")?;
+        let lazy_start = parse_var(vars, "BUZUKI_LAZY_START")?.unwrap_or(false);
+        if lazy_start && index_dir.is_none() {
+            let message = "BUZUKI_LAZY_START requires BUZUKI_INDEX_DIR";
+            return Err(Error::Parse(String::from(message)));
+        }
+
+        let mut lyrics = LyricsFilter::new(
+            parse_var(vars, "BUZUKI_METADATA_LINES")?.unwrap_or(MetadataLines::Chords),
+            parse_var::<String>(vars, "BUZUKI_CHORD_PATTERN")?
+                .as_deref()
+                .unwrap_or(CHORD_PATTERN),
+        )?;
+        if let Some(pattern) = parse_var::<String>(vars, "BUZUKI_REPETITION_PATTERN")? {
+            lyrics = lyrics.with_repetition_pattern(&pattern)?;
+        }
+        lyrics.dashes = parse_var(vars, "BUZUKI_DASHES")?.unwrap_or(Dashes::Syllables);
+        if let Some(notes) = parse_var::<String>(vars, "BUZUKI_PERFORMANCE_NOTES")? {
+            let words: Vec<&str> = notes
+                .split(',')
+                .map(str::trim)
+                .filter(|word| !word.is_empty())
+                .collect();
+            lyrics = lyrics.with_notes(&words);
+        }
+
+        let boosts = Boosts::from_vars(vars, "BUZUKI", Boosts::default())?;
+
+        Ok(Config {
+            song_dir,
+            song_bucket,
+            config_file: parse_var(vars, "BUZUKI_CONFIG")?,
+            admin_token,
+            min_score: parse_var(vars, "BUZUKI_MIN_SCORE")?,
+            min_relative_score: parse_var(vars, "BUZUKI_MIN_RELATIVE_SCORE")?,
+            numbers,
+            transliteration,
+            word_limits: WordLimits::from_vars(vars)?,
+            boosts,
+            experiment: Experiment::from_vars(vars, boosts)?,
+            popularity: parse_var(vars, "BUZUKI_POPULARITY_FILE")?,
+            scale_aliases: parse_var(vars, "BUZUKI_SCALE_ALIASES")?,
+            previews: parse_var(vars, "BUZUKI_PREVIEWS")?.unwrap_or(true),
+            raw_bodies: parse_var(vars, "BUZUKI_RAW_BODIES")?.unwrap_or(false),
+            greeklish_names: parse_var(vars, "BUZUKI_GREEKLISH_NAMES")?.unwrap_or(false),
+            highlights: parse_var(vars, "BUZUKI_HIGHLIGHTS")?.unwrap_or(false),
+            lyrics,
+            base_url: parse_var(vars, "BUZUKI_BASE_URL")?,
+            cors_origins: parse_var::<String>(vars, "BUZUKI_CORS_ORIGINS")?.map(|origins| {
+                origins
+                    .split(',')
+                    .map(|origin| origin.trim().trim_end_matches('/').to_string())
+                    .filter(|origin| !origin.is_empty())
+                    .collect()
+            }),
+            worker_threads: parse_var(vars, "BUZUKI_WORKER_THREADS")?.map(NonZeroUsize::get),
+            blocking_threads: parse_var(vars, "BUZUKI_BLOCKING_THREADS")?.map(NonZeroUsize::get),
+            search_threads: parse_var(vars, "BUZUKI_SEARCH_THREADS")?.map(NonZeroUsize::get),
+            indexing: IndexingLimits {
+                threads: parse_var(vars, "BUZUKI_INDEX_THREADS")?.map(NonZeroUsize::get),
+                heap_size: parse_var(vars, "BUZUKI_INDEX_HEAP_SIZE")?
+                    .unwrap_or(IndexingLimits::default().heap_size),
+                pause: parse_var(vars, "BUZUKI_INDEX_PAUSE_MS")?
+                    .map(Duration::from_millis)
+                    .unwrap_or(IndexingLimits::default().pause),
+            },
+            distribution,
+            index_dir,
+            lazy_start,
+            snapshot_poll: Duration::from_secs(
+                parse_var(vars, "BUZUKI_SNAPSHOT_POLL")?.unwrap_or(30),
+            ),
+            request_timeout: Duration::from_secs(
+                parse_var(vars, "BUZUKI_REQUEST_TIMEOUT")?.unwrap_or(30),
+            ),
+            http_addr: parse_var(vars, "BUZUKI_HTTP_ADDR")?
+                .unwrap_or_else(|| SocketAddr::from(([127, 0, 0, 1], 1337))),
+            grpc_addr: parse_var(vars, "BUZUKI_GRPC_ADDR")?,
+            search_limits: QueryLimits::from_vars(vars, "BUZUKI_SEARCH", 1000, 100)?,
+            autocomplete_limits: QueryLimits::from_vars(vars, "BUZUKI_AUTOCOMPLETE", 100, 10)?,
+            search: Endpoint::from_vars(vars, "BUZUKI_SEARCH", Endpoint::new(Mode::Full, 1000))?,
+            autocomplete: Endpoint::from_vars(
+                vars,
+                "BUZUKI_AUTOCOMPLETE",
+                Endpoint::new(Mode::Ngram, 15),
+            )?,
+        })
+    }
+
+    /// Return this configuration with the settings that are reloaded while serving replaced by
+    /// those of the other one.
+    pub fn reload(&self, other: &Config) -> Config {
+        Config {
+            min_score: other.min_score,
+            min_relative_score: other.min_relative_score,
+            boosts: other.boosts,
+            experiment: other.experiment,
+            search_limits: other.search_limits.clone(),
+            autocomplete_limits: other.autocomplete_limits.clone(),
+            search: other.search.clone(),
+            autocomplete: other.autocomplete.clone(),
+            cors_origins: other.cors_origins.clone(),
+            ..self.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    use crate::config::{
+        changed_vars, read_config_file, Boosts, Config, Effect, Endpoint, Experiment, NumberPolicy,
+        QueryLimits, Vars,
+    };
+    use crate::ranking::Variant;
+    use crate::search_engine::Mode;
+
+    #[test]
+    fn test_query_limits() {
+        let mut limits = QueryLimits {
+            max_chars: Some(10),
+            max_tokens: Some(2),
+            truncate: true,
+        };
+        assert_eq!(limits.apply("τα μπλε").unwrap(), "τα μπλε");
+        assert_eq!(limits.apply("τα  μπλε παράθυρα").unwrap(), "τα μπλε");
+        assert_eq!(limits.apply("παράθυρά σου").unwrap(), "παράθυρά σ");
+
+        limits.truncate = false;
+        assert!(limits.apply("τα μπλε").is_ok());
+        assert!(limits.apply("τα μπλε παράθυρα").is_err());
+        assert!(limits.apply("παράθυρά σου").is_err());
+    }
+
+    #[test]
+    fn test_reload() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "# Tuned for the phone app\nBUZUKI_SONG_BOOST = 0.5\n").unwrap();
+        writeln!(
+            file,
+            "BUZUKI_NUMBERS=drop\nBUZUKI_AUTOCOMPLETE_MAX_QUERY_CHARS=50"
+        )
+        .unwrap();
+        let file_vars = read_config_file(file.path()).unwrap();
+        assert_eq!(file_vars["BUZUKI_SONG_BOOST"], "0.5");
+
+        let mut vars = Vars::new();
+        vars.insert(String::from("BUZUKI_SONGDIR"), String::from("/srv/songs"));
+        vars.insert(String::from("BUZUKI_NUMBERS"), String::from("keep"));
+        let config = Config::from_vars(&vars).unwrap();
+        let mut new_vars = vars.clone();
+        new_vars.extend(file_vars);
+        let new_config = Config::from_vars(&new_vars).unwrap();
+
+        let changed = changed_vars(&vars, &new_vars);
+        assert_eq!(
+            changed,
+            vec![
+                "BUZUKI_AUTOCOMPLETE_MAX_QUERY_CHARS",
+                "BUZUKI_NUMBERS",
+                "BUZUKI_SONG_BOOST"
+            ]
+        );
+        let effects: Vec<Effect> = changed.into_iter().map(Effect::of).collect();
+        assert_eq!(
+            effects,
+            vec![Effect::Reload, Effect::Reindex, Effect::Reload]
+        );
+        assert_eq!(Effect::of("BUZUKI_HTTP_ADDR"), Effect::Restart);
+        assert_eq!(Effect::of("BUZUKI_CORS_ORIGINS"), Effect::Reload);
+
+        let reloaded = config.reload(&new_config);
+        assert_eq!(reloaded.boosts.song, 0.5);
+        assert_eq!(reloaded.autocomplete_limits.max_chars, Some(50));
+        assert_eq!(reloaded.numbers, config.numbers);
+
+        writeln!(file, "BUZUKI_SCALE_BOOST").unwrap();
+        assert!(read_config_file(file.path()).is_err());
+    }
+
+    #[test]
+    fn test_numbers() {
+        let mut vars = Vars::new();
+        vars.insert(String::from("BUZUKI_SONGDIR"), String::from("/srv/songs"));
+        let config = Config::from_vars(&vars).unwrap();
+        assert_eq!(config.numbers, NumberPolicy::Keep);
+        assert_eq!(config.transliteration.to_slug("30 δραχμές"), "30_draxmes");
+
+        vars.insert(String::from("BUZUKI_NUMBERS"), String::from("drop"));
+        let config = Config::from_vars(&vars).unwrap();
+        assert_eq!(config.numbers, NumberPolicy::Drop);
+        assert_eq!(config.transliteration.to_slug("30 δραχμές"), "draxmes");
+    }
+
+    #[test]
+    fn test_lazy_start() {
+        let mut vars = Vars::new();
+        vars.insert(String::from("BUZUKI_SONGDIR"), String::from("/srv/songs"));
+        vars.insert(String::from("BUZUKI_LAZY_START"), String::from("true"));
+        assert!(Config::from_vars(&vars).is_err());
+
+        vars.insert(String::from("BUZUKI_INDEX_DIR"), String::from("/srv/index"));
+        let config = Config::from_vars(&vars).unwrap();
+        assert!(config.lazy_start);
+        assert_eq!(config.index_dir, Some(PathBuf::from("/srv/index")));
+    }
+
+    #[test]
+    fn test_endpoint() {
+        let defaults = Endpoint::new(Mode::Ngram, 15);
+        let mut vars = Vars::new();
+        let endpoint = Endpoint::from_vars(&vars, "BUZUKI_AUTOCOMPLETE", defaults.clone()).unwrap();
+        assert_eq!(endpoint, defaults);
+        assert_eq!(endpoint.limit(None), Ok(15));
+        assert!(endpoint.limit(Some(16)).is_err());
+
+        vars.insert(
+            String::from("BUZUKI_AUTOCOMPLETE_MODE"),
+            String::from("full"),
+        );
+        vars.insert(
+            String::from("BUZUKI_AUTOCOMPLETE_MAX_LIMIT"),
+            String::from("50"),
+        );
+        vars.insert(
+            String::from("BUZUKI_AUTOCOMPLETE_FIELDS"),
+            String::from("name, url"),
+        );
+        let endpoint = Endpoint::from_vars(&vars, "BUZUKI_AUTOCOMPLETE", defaults.clone()).unwrap();
+        assert_eq!(endpoint.mode, Mode::Full);
+        assert_eq!(endpoint.limit(Some(50)), Ok(50));
+        assert!(endpoint.limit(Some(0)).is_err());
+        assert_eq!(
+            endpoint.fields,
+            Some(vec![String::from("name"), String::from("url")])
+        );
+
+        vars.insert(
+            String::from("BUZUKI_AUTOCOMPLETE_LIMIT"),
+            String::from("60"),
+        );
+        assert!(Endpoint::from_vars(&vars, "BUZUKI_AUTOCOMPLETE", defaults.clone()).is_err());
+
+        vars.remove("BUZUKI_AUTOCOMPLETE_LIMIT");
+        vars.insert(
+            String::from("BUZUKI_AUTOCOMPLETE_QUOTAS"),
+            String::from("songs:10, artists:3"),
+        );
+        let endpoint = Endpoint::from_vars(&vars, "BUZUKI_AUTOCOMPLETE", defaults.clone()).unwrap();
+        let quotas = endpoint.quotas.unwrap();
+        assert_eq!(
+            (quotas.songs, quotas.artists, quotas.scales),
+            (Some(10), Some(3), None)
+        );
+        vars.insert(
+            String::from("BUZUKI_AUTOCOMPLETE_QUOTAS"),
+            String::from("songs:10,lyrics:3"),
+        );
+        assert!(Endpoint::from_vars(&vars, "BUZUKI_AUTOCOMPLETE", defaults).is_err());
+    }
+
+    #[test]
+    fn test_experiment() {
+        let mut vars = Vars::new();
+        vars.insert(String::from("BUZUKI_B_SHARE"), String::from("0.2"));
+        assert!(Experiment::from_vars(&vars, Boosts::default())
+            .unwrap()
+            .is_none());
+
+        vars.insert(String::from("BUZUKI_B_SONG_BOOST"), String::from("2"));
+        let experiment = Experiment::from_vars(&vars, Boosts::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(experiment.boosts.song, 2.0);
+        assert_eq!(experiment.boosts.artist, Boosts::default().artist);
+        assert_eq!(experiment.share, 0.2);
+
+        assert_eq!(experiment.variant(None, None), Variant::A);
+        assert_eq!(experiment.variant(Some(Variant::B), None), Variant::B);
+        // Clients keep their variant across restarts and releases.
+        assert_eq!(experiment.variant(None, Some("phone-app")), Variant::A);
+        assert_eq!(experiment.variant(None, Some("b")), Variant::B);
+        let everyone = Experiment {
+            share: 1.0,
+            ..experiment
+        };
+        assert_eq!(everyone.variant(None, Some("phone-app")), Variant::B);
+        assert_eq!(
+            everyone.variant(Some(Variant::A), Some("phone-app")),
+            Variant::A
+        );
+
+        vars.insert(String::from("BUZUKI_B_SHARE"), String::from("1.5"));
+        assert!(Experiment::from_vars(&vars, Boosts::default()).is_err());
+    }
+}
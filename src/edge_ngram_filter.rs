@@ -0,0 +1,132 @@
+use tantivy::tokenizer::BoxTokenStream;
+use tantivy::tokenizer::{Token, TokenFilter, TokenStream};
+
+impl TokenFilter for EdgeNgramFilter {
+    fn transform<'a>(&self, token_stream: BoxTokenStream<'a>) -> BoxTokenStream<'a> {
+        BoxTokenStream::from(EdgeNgramTokenStream {
+            tail: token_stream,
+            max_len: self.max_len,
+            text: String::new(),
+            len: 0,
+            ends: Vec::new(),
+            token: Token::default(),
+        })
+    }
+}
+
+/// Token filter that joins the terms into a single text, separated by spaces, and emits every
+/// prefix of it, e.g. "ο", "ο μ", "ο μα" and so on for "ο μαρκος". Prefixes that end with the
+/// separating space are skipped, since queries are trimmed.
+#[derive(Clone)]
+pub struct EdgeNgramFilter {
+    /// Maximum length of the prefixes in characters. Later terms are dropped.
+    max_len: usize,
+}
+
+impl EdgeNgramFilter {
+    pub fn limit(max_len: usize) -> EdgeNgramFilter {
+        EdgeNgramFilter { max_len }
+    }
+}
+
+pub struct EdgeNgramTokenStream<'a> {
+    tail: BoxTokenStream<'a>,
+    max_len: usize,
+    /// The terms so far, separated by spaces.
+    text: String,
+    /// Length of the text in characters.
+    len: usize,
+    /// Byte offsets in the text of the ends of the pending prefixes, in reverse order.
+    ends: Vec<usize>,
+    token: Token,
+}
+
+impl<'a> EdgeNgramTokenStream<'a> {
+    /// Append the current term to the text and queue its prefixes.
+    fn push_term(&mut self) {
+        if !self.text.is_empty() {
+            self.text.push(' ');
+            self.len += 1;
+        }
+        let start = self.text.len();
+        self.text.push_str(&self.tail.token().text);
+        for (offset, c) in self.text[start..].char_indices() {
+            if self.len == self.max_len {
+                break;
+            }
+            self.len += 1;
+            self.ends.push(start + offset + c.len_utf8());
+        }
+        self.ends.reverse();
+        // All the prefixes take the position of the first term.
+        if start == 0 {
+            self.token.offset_from = self.tail.token().offset_from;
+            self.token.position = self.tail.token().position;
+        }
+        self.token.offset_to = self.tail.token().offset_to;
+    }
+}
+
+impl<'a> TokenStream for EdgeNgramTokenStream<'a> {
+    fn advance(&mut self) -> bool {
+        loop {
+            if let Some(end) = self.ends.pop() {
+                self.token.text.clear();
+                self.token.text.push_str(&self.text[..end]);
+                return true;
+            }
+            if self.len >= self.max_len || !self.tail.advance() {
+                return false;
+            }
+            self.push_term();
+        }
+    }
+
+    fn token(&self) -> &Token {
+        &self.token
+    }
+
+    fn token_mut(&mut self) -> &mut Token {
+        &mut self.token
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tantivy::tokenizer::{SimpleTokenizer, TextAnalyzer};
+
+    use crate::edge_ngram_filter::EdgeNgramFilter;
+
+    fn prefixes(text: &str, max_len: usize) -> Vec<String> {
+        let analyzer = TextAnalyzer::from(SimpleTokenizer).filter(EdgeNgramFilter::limit(max_len));
+        let mut token_stream = analyzer.token_stream(text);
+        let mut prefixes = Vec::new();
+        while token_stream.advance() {
+            assert_eq!(token_stream.token().position, 0);
+            prefixes.push(token_stream.token().text.clone());
+        }
+        prefixes
+    }
+
+    #[test]
+    fn test_edge_ngram_filter() {
+        assert_eq!(
+            prefixes("Ο Μάρκος, στον", 40),
+            vec![
+                "Ο",
+                "Ο Μ",
+                "Ο Μά",
+                "Ο Μάρ",
+                "Ο Μάρκ",
+                "Ο Μάρκο",
+                "Ο Μάρκος",
+                "Ο Μάρκος σ",
+                "Ο Μάρκος στ",
+                "Ο Μάρκος στο",
+                "Ο Μάρκος στον",
+            ]
+        );
+        assert_eq!(prefixes("Ο Μάρκος", 4), vec!["Ο", "Ο Μ", "Ο Μά"]);
+        assert!(prefixes("", 40).is_empty());
+    }
+}
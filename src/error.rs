@@ -0,0 +1,90 @@
+use serde_json::json;
+use tantivy::query::QueryParserError;
+use tantivy::TantivyError;
+use thiserror::Error;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Errors of the search engine and the servers, classified by their cause.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// Reading the songs or a configuration file failed.
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+    /// A song file or a configuration value is malformed.
+    #[error("{0}")]
+    Parse(String),
+    /// Building, updating or searching the index failed.
+    #[error("{0}")]
+    Index(#[from] TantivyError),
+    /// The query or a request parameter is invalid.
+    #[error("{0}")]
+    Query(String),
+    /// A newer search of the same client superseded the search while it ran.
+    #[error("The search was cancelled")]
+    Cancelled,
+}
+
+impl From<QueryParserError> for Error {
+    fn from(e: QueryParserError) -> Error {
+        Error::Query(format!("Invalid query: {}", e))
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Error {
+        // Only failures to read or write the JSON are I/O errors.
+        if e.is_io() {
+            Error::Io(e.into())
+        } else {
+            Error::Parse(format!("Invalid JSON: {}", e))
+        }
+    }
+}
+
+impl Error {
+    /// Name of the error class, e.g. "query".
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::Io(_) => "io",
+            Error::Parse(_) => "parse",
+            Error::Index(_) => "index",
+            Error::Query(_) => "query",
+            Error::Cancelled => "cancelled",
+        }
+    }
+
+    /// Whether the error is caused by the request rather than the server.
+    pub fn is_client_error(&self) -> bool {
+        matches!(self, Error::Query(_))
+    }
+
+    /// Return the error as a JSON object with its message and class.
+    pub fn to_json(&self) -> String {
+        json!({ "error": self.to_string(), "kind": self.kind() }).to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+    use tantivy::TantivyError;
+
+    use crate::error::Error;
+
+    #[test]
+    fn test_to_json() {
+        let e = Error::Query(String::from("Unknown sort \"popularity\""));
+        let json: Value = serde_json::from_str(&e.to_json()).unwrap();
+        assert_eq!(json["error"], "Unknown sort \"popularity\"");
+        assert_eq!(json["kind"], "query");
+        assert!(e.is_client_error());
+
+        let e = Error::from(TantivyError::InvalidArgument(String::from("test")));
+        assert_eq!(e.kind(), "index");
+        assert!(!e.is_client_error());
+
+        let e = Error::from(serde_json::from_str::<Value>("{").unwrap_err());
+        assert_eq!(e.kind(), "parse");
+    }
+}
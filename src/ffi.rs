@@ -0,0 +1,193 @@
+//! C bindings of the transliteration and the search engine, built as a shared library by
+//! `make ffi`. See include/buzuki_search.h for the declarations.
+//!
+//! Returned strings are owned by the caller and must be freed with `buzuki_string_free`. On
+//! failure functions return NULL, and `buzuki_last_error` describes the error.
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::ptr;
+
+use lazy_static::lazy_static;
+
+use crate::config::NumberPolicy;
+use crate::error::{Error, Result};
+use crate::search_engine::{Mode, ResultOptions, SearchEngine, Sort};
+use crate::song::strip_metadata;
+use crate::transliteration::Transliteration;
+
+lazy_static! {
+    static ref TRANSLITERATION: Transliteration = Transliteration::new(NumberPolicy::Keep);
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Opaque handle of a search engine.
+pub struct BuzukiSearchEngine {
+    engine: SearchEngine,
+}
+
+/// Read a string argument, failing on NULL and invalid UTF-8.
+unsafe fn read_str<'a>(string: *const c_char) -> Result<&'a str> {
+    if string.is_null() {
+        return Err(Error::Query(String::from("Unexpected NULL string")));
+    }
+    CStr::from_ptr(string)
+        .to_str()
+        .map_err(|e| Error::Query(format!("Invalid UTF-8: {}", e)))
+}
+
+fn set_last_error(error: String) {
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = CString::new(error).ok());
+}
+
+/// Hand the string over to the caller, or record the error and return NULL.
+fn into_raw(result: Result<String>) -> *mut c_char {
+    match result.map(CString::new) {
+        Ok(Ok(string)) => string.into_raw(),
+        Ok(Err(e)) => {
+            set_last_error(e.to_string());
+            ptr::null_mut()
+        }
+        Err(e) => {
+            set_last_error(e.to_string());
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Message of the last error of the calling thread, or NULL. Owned by the library and valid
+/// until the next call that fails.
+#[no_mangle]
+pub extern "C" fn buzuki_last_error() -> *const c_char {
+    LAST_ERROR.with(|last_error| match &*last_error.borrow() {
+        Some(error) => error.as_ptr(),
+        None => ptr::null(),
+    })
+}
+
+/// # Safety
+///
+/// The string must have been returned by this library and not freed already.
+#[no_mangle]
+pub unsafe extern "C" fn buzuki_string_free(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
+
+/// # Safety
+///
+/// The text must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn buzuki_to_greeklish(text: *const c_char) -> *mut c_char {
+    into_raw(read_str(text).map(|text| TRANSLITERATION.to_greeklish(text)))
+}
+
+/// # Safety
+///
+/// The text must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn buzuki_strip_metadata(text: *const c_char) -> *mut c_char {
+    into_raw(read_str(text).map(strip_metadata))
+}
+
+/// Index the song files of the directory. Returns NULL on failure.
+///
+/// # Safety
+///
+/// The song directory must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn buzuki_search_engine_new(
+    song_dir: *const c_char,
+) -> *mut BuzukiSearchEngine {
+    let result = read_str(song_dir).and_then(|song_dir| SearchEngine::builder(song_dir).build());
+    match result {
+        Ok(engine) => Box::into_raw(Box::new(BuzukiSearchEngine { engine })),
+        Err(e) => {
+            set_last_error(e.to_string());
+            ptr::null_mut()
+        }
+    }
+}
+
+/// # Safety
+///
+/// The engine must have been returned by `buzuki_search_engine_new` and not freed already.
+#[no_mangle]
+pub unsafe extern "C" fn buzuki_search_engine_free(engine: *mut BuzukiSearchEngine) {
+    if !engine.is_null() {
+        drop(Box::from_raw(engine));
+    }
+}
+
+/// Search like GET / does and return the results as a JSON array. Autocomplete searches match
+/// prefixes of words, like GET /autocomplete/ does.
+///
+/// # Safety
+///
+/// The engine must be a live handle and the query a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn buzuki_search(
+    engine: *const BuzukiSearchEngine,
+    query: *const c_char,
+    autocomplete: bool,
+) -> *mut c_char {
+    let engine = match engine.as_ref() {
+        Some(engine) => &engine.engine,
+        None => return into_raw(Err(Error::Query(String::from("Unexpected NULL engine")))),
+    };
+    let mode = if autocomplete {
+        Mode::Ngram
+    } else {
+        Mode::Full
+    };
+    let options = ResultOptions::default();
+    into_raw(
+        read_str(query).and_then(|query| engine.search(query, mode, Sort::Relevance, &options)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::{CStr, CString};
+    use std::os::raw::c_char;
+    use std::ptr;
+
+    use crate::ffi::*;
+
+    unsafe fn take_string(string: *mut c_char) -> String {
+        assert!(!string.is_null());
+        let owned = CStr::from_ptr(string).to_str().unwrap().to_string();
+        buzuki_string_free(string);
+        owned
+    }
+
+    #[test]
+    fn test_ffi() {
+        let text = CString::new("Μινόρε του 1936").unwrap();
+        let fixtures = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/songs");
+        let song_dir = CString::new(fixtures).unwrap();
+        let query = CString::new("fragk").unwrap();
+        unsafe {
+            let greeklish = take_string(buzuki_to_greeklish(text.as_ptr()));
+            assert_eq!(greeklish, "minore_tou_1936");
+            assert!(buzuki_to_greeklish(ptr::null()).is_null());
+            assert!(!buzuki_last_error().is_null());
+
+            let engine = buzuki_search_engine_new(song_dir.as_ptr());
+            assert!(!engine.is_null());
+            let results = take_string(buzuki_search(engine, query.as_ptr(), true));
+            assert!(results.contains("/songs/fragkosyriani/"));
+            buzuki_search_engine_free(engine);
+
+            let song_dir = CString::new("/nonexistent").unwrap();
+            assert!(buzuki_search_engine_new(song_dir.as_ptr()).is_null());
+            let error = CStr::from_ptr(buzuki_last_error()).to_str().unwrap();
+            assert!(!error.is_empty());
+        }
+    }
+}
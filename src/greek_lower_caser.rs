@@ -8,24 +8,61 @@ impl TokenFilter for GreekLowerCaser {
         BoxTokenStream::from(GreekLowerCaserTokenStream {
             tail: token_stream,
             buffer: String::with_capacity(100),
+            fold_homophones: self.fold_homophones,
         })
     }
 }
 
 /// Token filter that lowercase terms.
-#[derive(Clone)]
-pub struct GreekLowerCaser;
+#[derive(Clone, Default)]
+pub struct GreekLowerCaser {
+    fold_homophones: bool,
+}
+
+impl GreekLowerCaser {
+    /// Also spell letters that sound the same with the same letter: η, υ and ι as ι, ω as ο
+    /// and αι as ε. Meant for autocomplete, where misspellings are common.
+    pub fn folding_homophones() -> GreekLowerCaser {
+        GreekLowerCaser {
+            fold_homophones: true,
+        }
+    }
+}
 
 pub struct GreekLowerCaserTokenStream<'a> {
     buffer: String,
     tail: BoxTokenStream<'a>,
+    fold_homophones: bool,
+}
+
+/// Return the letter that sounds the same as c and is used in its place when folding
+/// homophones, given the lowercased output so far. The υ of αυ, ευ and ου and the ι of αϊ
+/// don't sound like ι and ε, so they are kept.
+fn fold_homophone(c: char, output: &mut String) -> char {
+    match c {
+        'η' | 'ή' | 'ϋ' | 'ΰ' => 'ι',
+        'υ' | 'ύ' if !(output.ends_with('α') || output.ends_with('ε') || output.ends_with('ο')) => {
+            'ι'
+        }
+        'ι' | 'ί' if output.ends_with('α') => {
+            output.pop();
+            'ε'
+        }
+        'ω' | 'ώ' => 'ο',
+        c => c,
+    }
 }
 
 /// Writes a lowercased version of text into output.
-fn to_greek_lowercase_unicode(text: &mut String, output: &mut String) {
+fn to_greek_lowercase_unicode(text: &str, output: &mut String, fold_homophones: bool) {
     output.clear();
     for c in text.chars() {
         for c in c.to_lowercase() {
+            let c = if fold_homophones {
+                fold_homophone(c, output)
+            } else {
+                c
+            };
             output.push(match c {
                 'ά' => 'α',
                 'έ' => 'ε',
@@ -45,6 +82,13 @@ fn to_greek_lowercase_unicode(text: &mut String, output: &mut String) {
     }
 }
 
+/// Return a lowercased version of text without accents, suitable for comparing Greek strings.
+pub fn to_greek_lowercase(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    to_greek_lowercase_unicode(text, &mut output, false);
+    output
+}
+
 impl<'a> TokenStream for GreekLowerCaserTokenStream<'a> {
     fn advance(&mut self) -> bool {
         if !self.tail.advance() {
@@ -54,7 +98,11 @@ impl<'a> TokenStream for GreekLowerCaserTokenStream<'a> {
             // fast track for ascii.
             self.token_mut().text.make_ascii_lowercase();
         } else {
-            to_greek_lowercase_unicode(&mut self.tail.token_mut().text, &mut self.buffer);
+            to_greek_lowercase_unicode(
+                &self.tail.token().text,
+                &mut self.buffer,
+                self.fold_homophones,
+            );
             mem::swap(&mut self.tail.token_mut().text, &mut self.buffer);
         }
         true
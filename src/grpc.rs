@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use log::{info, warn};
+use tokio::sync::broadcast;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+
+use buzuki_search::config::Config;
+use buzuki_search::error::Error;
+use buzuki_search::search_engine::{Mode, SearchEngine, Sort};
+
+mod proto {
+    tonic::include_proto!("buzuki");
+}
+
+use proto::search_server::{Search, SearchServer};
+use proto::{Hit, ReindexRequest, ReindexResponse, SearchRequest, SearchResponse};
+
+struct SearchService {
+    search_engine: SearchEngine,
+    config: Arc<Config>,
+    events: broadcast::Sender<String>,
+}
+
+impl SearchService {
+    async fn search(&self, query: String, mode: Mode) -> Result<Response<SearchResponse>, Status> {
+        let search_engine = self.search_engine.clone();
+        let hits =
+            tokio::task::spawn_blocking(move || search_engine.hits(&query, mode, Sort::Relevance))
+                .await
+                .map_err(|e| Status::internal(e.to_string()))?
+                .map_err(to_status)?;
+        let hits = hits.into_iter().map(to_hit).collect();
+        Ok(Response::new(SearchResponse { hits }))
+    }
+}
+
+/// Map invalid queries to INVALID_ARGUMENT and every other error to INTERNAL.
+fn to_status(e: Error) -> Status {
+    if e.is_client_error() {
+        Status::invalid_argument(e.to_string())
+    } else {
+        Status::internal(e.to_string())
+    }
+}
+
+fn to_hit(mut entry: HashMap<String, String>) -> Hit {
+    let mut take = |key: &str| entry.remove(key).unwrap_or_default();
+    Hit {
+        name: take("name"),
+        slug: take("slug"),
+        url: take("url"),
+        artist: take("artist"),
+        name_greeklish: take("name_greeklish"),
+        artist_greeklish: take("artist_greeklish"),
+    }
+}
+
+#[tonic::async_trait]
+impl Search for SearchService {
+    async fn search(
+        &self,
+        request: Request<SearchRequest>,
+    ) -> Result<Response<SearchResponse>, Status> {
+        SearchService::search(self, request.into_inner().query, Mode::Full).await
+    }
+
+    async fn autocomplete(
+        &self,
+        request: Request<SearchRequest>,
+    ) -> Result<Response<SearchResponse>, Status> {
+        SearchService::search(self, request.into_inner().query, Mode::Ngram).await
+    }
+
+    async fn reindex(
+        &self,
+        request: Request<ReindexRequest>,
+    ) -> Result<Response<ReindexResponse>, Status> {
+        let token = request.into_inner().token;
+        if self.config.admin_token.as_deref() != Some(token.as_str()) {
+            return Err(Status::permission_denied("Invalid token"));
+        }
+        let search_engine = self.search_engine.clone();
+        let documents = tokio::task::spawn_blocking(move || search_engine.reindex())
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+            .map_err(to_status)?;
+        info!("Reindexed {} documents", documents);
+        let event = format!("{{\"documents\": {}}}", documents);
+        // Sending only fails if there are no subscribers.
+        let _ = self
+            .events
+            .send(format!("event: reindex\ndata: {}\n\n", event));
+        Ok(Response::new(ReindexResponse { documents }))
+    }
+}
+
+/// Serve the gRPC service on the given address until the process exits.
+pub async fn serve(
+    addr: SocketAddr,
+    search_engine: SearchEngine,
+    config: Arc<Config>,
+    events: broadcast::Sender<String>,
+) {
+    let service = SearchService {
+        search_engine,
+        config,
+        events,
+    };
+    info!("Listening for gRPC on http://{}", addr);
+    let result = Server::builder()
+        .add_service(SearchServer::new(service))
+        .serve(addr)
+        .await;
+    if let Err(e) = result {
+        warn!("gRPC server error: {}", e);
+    }
+}
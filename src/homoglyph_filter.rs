@@ -0,0 +1,104 @@
+use std::mem;
+
+use tantivy::tokenizer::BoxTokenStream;
+use tantivy::tokenizer::{Token, TokenFilter, TokenStream};
+
+use crate::utils::is_greek;
+
+/// Pairs of Latin and Greek letters that look the same.
+const HOMOGLYPHS: &[(char, char)] = &[
+    ('A', 'Α'),
+    ('B', 'Β'),
+    ('E', 'Ε'),
+    ('H', 'Η'),
+    ('I', 'Ι'),
+    ('K', 'Κ'),
+    ('M', 'Μ'),
+    ('N', 'Ν'),
+    ('O', 'Ο'),
+    ('P', 'Ρ'),
+    ('T', 'Τ'),
+    ('X', 'Χ'),
+    ('Y', 'Υ'),
+    ('Z', 'Ζ'),
+    ('i', 'ι'),
+    ('k', 'κ'),
+    ('o', 'ο'),
+    ('p', 'ρ'),
+    ('u', 'υ'),
+    ('v', 'ν'),
+    ('x', 'χ'),
+];
+
+impl TokenFilter for HomoglyphFilter {
+    fn transform<'a>(&self, token_stream: BoxTokenStream<'a>) -> BoxTokenStream<'a> {
+        BoxTokenStream::from(HomoglyphFilterTokenStream {
+            tail: token_stream,
+            buffer: String::with_capacity(100),
+        })
+    }
+}
+
+/// Token filter that fixes terms mixing Greek and Latin letters, usually typos like
+/// "Bαμβακάρης" with a Latin B, by replacing the letters of the less used script with the
+/// letters of the other script that look the same. Ties are resolved in favour of Greek.
+#[derive(Clone)]
+pub struct HomoglyphFilter;
+
+pub struct HomoglyphFilterTokenStream<'a> {
+    buffer: String,
+    tail: BoxTokenStream<'a>,
+}
+
+/// Writes the text with the homoglyphs replaced into output. Returns false, leaving output
+/// untouched, if the text doesn't mix the two scripts.
+pub fn replace_homoglyphs(text: &str, output: &mut String) -> bool {
+    let (mut greek, mut latin) = (0, 0);
+    for c in text.chars().filter(|c| c.is_alphabetic()) {
+        if is_greek(c) {
+            greek += 1;
+        } else {
+            latin += 1;
+        }
+    }
+    if greek == 0 || latin == 0 {
+        return false;
+    }
+    let to_greek = greek >= latin;
+    output.clear();
+    for c in text.chars() {
+        let replacement = HOMOGLYPHS.iter().find_map(|&(latin, greek)| {
+            if to_greek && c == latin {
+                Some(greek)
+            } else if !to_greek && c == greek {
+                Some(latin)
+            } else {
+                None
+            }
+        });
+        output.push(replacement.unwrap_or(c));
+    }
+    true
+}
+
+impl<'a> TokenStream for HomoglyphFilterTokenStream<'a> {
+    fn advance(&mut self) -> bool {
+        if !self.tail.advance() {
+            return false;
+        }
+        if !self.tail.token().text.is_ascii()
+            && replace_homoglyphs(&self.tail.token().text, &mut self.buffer)
+        {
+            mem::swap(&mut self.tail.token_mut().text, &mut self.buffer);
+        }
+        true
+    }
+
+    fn token(&self) -> &Token {
+        self.tail.token()
+    }
+
+    fn token_mut(&mut self) -> &mut Token {
+        self.tail.token_mut()
+    }
+}
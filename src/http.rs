@@ -0,0 +1,675 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::async_trait;
+use axum::body::{Body, StreamBody};
+use axum::extract::{FromRequestParts, Query, State};
+use axum::http::request::Parts;
+use axum::http::{header, HeaderMap, HeaderValue, Request, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Router, Server};
+use futures::channel::mpsc;
+use futures::executor::block_on;
+use futures::{stream, SinkExt};
+use log::{info, warn};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::json;
+use tantivy::TantivyError;
+use tokio::sync::{broadcast, watch};
+use tower_http::compression::predicate::{DefaultPredicate, NotForContentType, Predicate};
+use tower_http::compression::CompressionLayer;
+use tower_http::cors::CorsLayer;
+use tower_http::timeout::TimeoutLayer;
+use tower_http::trace::TraceLayer;
+
+use crate::cancellation::CancelFlag;
+use crate::config::Config;
+use crate::error::Error;
+use crate::ranking::Variant;
+use crate::search_engine::{Filters, Mode, ResultOptions, Scope, SearchEngine, Sort};
+use crate::websocket;
+
+/// Delay before retrying a failed background reindex, doubled after every failure up to the
+/// maximum.
+const REINDEX_RETRY_DELAY: Duration = Duration::from_secs(5);
+const MAX_REINDEX_RETRY_DELAY: Duration = Duration::from_secs(10 * 60);
+
+/// Shared by all the handlers.
+#[derive(Clone)]
+struct AppState {
+    search_engine: SearchEngine,
+    // Updated when the config file is reloaded.
+    config: watch::Receiver<Arc<Config>>,
+    events: broadcast::Sender<String>,
+    autocompletes: PendingSearches,
+}
+
+impl AppState {
+    fn config(&self) -> Arc<Config> {
+        self.config.borrow().clone()
+    }
+}
+
+/// The latest autocomplete search of every client, keyed by the client token. Clients send a
+/// request per keystroke, so a new search cancels the previous search of the client, whether it
+/// is still waiting for a thread or already collecting its matches.
+#[derive(Clone, Default)]
+struct PendingSearches {
+    next_id: Arc<AtomicU64>,
+    latest: Arc<Mutex<HashMap<String, (u64, CancelFlag)>>>,
+}
+
+impl PendingSearches {
+    /// Register a new search of the client, cancelling its previous one, and return its id.
+    fn start(&self, client: &str, cancel: &CancelFlag) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let previous = self
+            .latest
+            .lock()
+            .unwrap()
+            .insert(client.to_string(), (id, cancel.clone()));
+        if let Some((_, previous)) = previous {
+            previous.cancel();
+        }
+        id
+    }
+
+    /// Forget the search, unless a newer one has superseded it. Returns whether it was the latest.
+    fn finish(&self, client: &str, id: u64) -> bool {
+        let mut latest = self.latest.lock().unwrap();
+        if latest.get(client).map(|(latest, _)| *latest) == Some(id) {
+            latest.remove(client);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Serve the HTTP API on the listener until the server fails. Index events, e.g. reindexing,
+/// are published to the subscribers of GET /events/ through the events channel. Requests use
+/// the latest configuration of the config channel.
+pub async fn serve(
+    listener: TcpListener,
+    search_engine: SearchEngine,
+    config: watch::Receiver<Arc<Config>>,
+    events: broadcast::Sender<String>,
+) -> hyper::Result<()> {
+    let app = router(search_engine, config, events);
+    Server::from_tcp(listener)?
+        .serve(app.into_make_service())
+        .await
+}
+
+/// Build the routes of the API. The admin and debug routes require the admin token.
+pub fn router(
+    search_engine: SearchEngine,
+    config: watch::Receiver<Arc<Config>>,
+    events: broadcast::Sender<String>,
+) -> Router {
+    let timeout = config.borrow().request_timeout;
+    let state = AppState {
+        search_engine,
+        config,
+        events,
+        autocompletes: PendingSearches::default(),
+    };
+
+    let admin = Router::new()
+        .route("/admin/reindex/", post(reindex))
+        .route("/admin/export/", get(export))
+        .route("/admin/terms/", get(terms))
+        .route("/debug/tokenize/", get(tokenize))
+        .route("/debug/parse-query/", get(parse_query))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_admin));
+
+    Router::new()
+        .route("/", get(search))
+        .route("/autocomplete/", get(autocomplete))
+        .route("/schema/", get(schema))
+        .route("/stats/", get(stats))
+        .route("/health/", get(health))
+        .route("/ws/", get(live_search))
+        .route("/sitemap.xml", get(sitemap))
+        .route("/events/", get(subscribe))
+        .route("/ui", get(ui))
+        .merge(admin)
+        .fallback(not_found)
+        .with_state(state.clone())
+        .layer(TimeoutLayer::new(timeout))
+        // Compressed events would be buffered by the encoder instead of reaching the
+        // subscribers.
+        .layer(CompressionLayer::new().compress_when(
+            DefaultPredicate::new().and(NotForContentType::const_new("text/event-stream")),
+        ))
+        .layer(CorsLayer::permissive())
+        .layer(middleware::from_fn_with_state(state, restrict_origins))
+        .layer(TraceLayer::new_for_http())
+}
+
+/// Run searches and other CPU-bound index work on the blocking thread pool, so that they don't
+/// stall the async workers that accept connections.
+async fn blocking<T, F>(f: F) -> Result<T, Error>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, Error> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .unwrap_or_else(|e| Err(TantivyError::ErrorInThread(e.to_string()).into()))
+}
+
+fn json_response(status: StatusCode, body: String) -> Response {
+    (status, [(header::CONTENT_TYPE, "application/json")], body).into_response()
+}
+
+/// Only invalid queries and parameters are blamed on the client.
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let status = if self.is_client_error() {
+            StatusCode::BAD_REQUEST
+        } else {
+            StatusCode::INTERNAL_SERVER_ERROR
+        };
+        json_response(status, self.to_json())
+    }
+}
+
+/// Query string extractor that rejects invalid parameters with a JSON error.
+struct Params<T>(T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for Params<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Params<T>, Error> {
+        match Query::<T>::from_request_parts(parts, state).await {
+            Ok(Query(params)) => Ok(Params(params)),
+            Err(rejection) => Err(Error::Query(rejection.body_text())),
+        }
+    }
+}
+
+/// Check the bearer token of the request against the configured admin token.
+fn is_admin(headers: &HeaderMap, config: &Config) -> bool {
+    let token = match &config.admin_token {
+        Some(token) => token,
+        None => return false,
+    };
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|value| value == token)
+}
+
+async fn require_admin<B>(
+    State(state): State<AppState>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if !is_admin(request.headers(), &state.config()) {
+        return json_response(StatusCode::FORBIDDEN, String::from("[]"));
+    }
+    next.run(request).await
+}
+
+/// Only allow the configured CORS origins, if any, read per request so that reloading the
+/// config file applies them. The permissive CORS layer allows any origin, so its header is
+/// replaced by the origin of the request if it is allowed, and removed otherwise.
+async fn restrict_origins<B>(
+    State(state): State<AppState>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let origins = match state.config().cors_origins.clone() {
+        Some(origins) => origins,
+        None => return next.run(request).await,
+    };
+    let origin = request.headers().get(header::ORIGIN).cloned();
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    headers.remove(header::ACCESS_CONTROL_ALLOW_ORIGIN);
+    headers.append(header::VARY, HeaderValue::from_static("origin"));
+    if let Some(origin) = origin {
+        let allowed = origin
+            .to_str()
+            .is_ok_and(|origin| origins.iter().any(|allowed| allowed == origin));
+        if allowed {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+        }
+    }
+    response
+}
+
+async fn not_found() -> Response {
+    json_response(StatusCode::NOT_FOUND, String::from("[]"))
+}
+
+#[derive(Deserialize)]
+struct SearchParams {
+    q: Option<String>,
+    /// Order of the results, "relevance", "name", "artist", "year" or "popularity". Only
+    /// accepted by full searches, since autocomplete results are always ranked by relevance.
+    sort: Option<String>,
+    /// Version of the response format. Version 2 wraps the results in an envelope.
+    v: Option<u32>,
+    accents: Option<String>,
+    #[serde(default)]
+    explain: bool,
+    #[serde(default)]
+    count: bool,
+    #[serde(default)]
+    highlight: bool,
+    /// Number of results, up to the maximum limit of the endpoint.
+    limit: Option<usize>,
+    /// Maximum number of songs of each artist.
+    per_artist: Option<usize>,
+    /// Ranking variant of the experiment, "a" or "b". Picked by hashing the client token if
+    /// unset.
+    variant: Option<String>,
+    /// Token of the client, used to skip the stale autocomplete searches of the same client.
+    client: Option<String>,
+    /// Artist of the songs, e.g. "markos_vamvakaris".
+    artist: Option<String>,
+    /// Scale of the songs, e.g. "ousak".
+    scale: Option<String>,
+    /// Rhythm of the songs, e.g. "zeimpekiko".
+    rhythm: Option<String>,
+    /// Fields that the query may match, "all" or "name".
+    #[serde(rename = "in")]
+    scope: Option<String>,
+    /// Scan the lyrics for the query as a substring, e.g. for punctuation and elisions.
+    #[serde(default)]
+    substring: bool,
+    /// Return which parts of each hit match the query.
+    #[serde(default)]
+    matched: bool,
+}
+
+async fn search(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Params(params): Params<SearchParams>,
+) -> Result<Response, Error> {
+    run_search(state, &headers, params, true).await
+}
+
+async fn autocomplete(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Params(params): Params<SearchParams>,
+) -> Result<Response, Error> {
+    run_search(state, &headers, params, false).await
+}
+
+async fn run_search(
+    state: AppState,
+    headers: &HeaderMap,
+    params: SearchParams,
+    simple: bool,
+) -> Result<Response, Error> {
+    let config = state.config();
+    let filters = Filters {
+        artist: params.artist.clone().filter(|artist| !artist.is_empty()),
+        scale: params.scale.clone().filter(|scale| !scale.is_empty()),
+        rhythm: params.rhythm.clone().filter(|rhythm| !rhythm.is_empty()),
+    };
+    // Filtered searches may leave out the query, to browse the songs that pass the filters.
+    let query = match &params.q {
+        Some(query) => query,
+        None if !filters.is_empty() => "",
+        None => return Ok(json_response(StatusCode::NOT_FOUND, String::from("[]"))),
+    };
+    let (limits, endpoint) = if simple {
+        (&config.search_limits, &config.search)
+    } else {
+        (&config.autocomplete_limits, &config.autocomplete)
+    };
+    let query = limits.apply(query).map_err(Error::Query)?.into_owned();
+    if params.explain && !is_admin(headers, &config) {
+        return Ok(json_response(StatusCode::FORBIDDEN, String::from("[]")));
+    }
+    // Browsing has no relevance to rank by.
+    let sort = match &params.sort {
+        Some(_) if !simple => {
+            let message = "sort is not supported by autocomplete";
+            return Err(Error::Query(String::from(message)));
+        }
+        Some(sort) => sort.parse().map_err(Error::Query)?,
+        None if query.trim().is_empty() => Sort::Name,
+        None => Sort::Relevance,
+    };
+    let scope = match &params.scope {
+        Some(scope) => scope.parse().map_err(Error::Query)?,
+        None => Scope::All,
+    };
+    let exact = params.accents.as_deref() == Some("exact");
+    let mode = match (endpoint.mode, exact) {
+        (Mode::Full, true) => Mode::Exact,
+        (mode, _) => mode,
+    };
+    let requested: Option<Variant> = match &params.variant {
+        Some(variant) => Some(variant.parse().map_err(Error::Query)?),
+        None => None,
+    };
+    // Only the searches of a running experiment are assigned a variant.
+    let variant = config
+        .experiment
+        .map(|experiment| experiment.variant(requested, params.client.as_deref()));
+    if let Some(variant) = variant {
+        info!("Ranking variant {} serves {:?}", variant, query);
+    }
+    let options = ResultOptions {
+        limit: Some(endpoint.limit(params.limit).map_err(Error::Query)?),
+        fields: endpoint.fields.clone(),
+        highlight: params.highlight,
+        per_artist: params.per_artist,
+        variant: variant.unwrap_or_default(),
+        filters,
+        quotas: endpoint.quotas,
+        scope,
+        substring: params.substring,
+        matched: params.matched,
+        endpoint: Some(if simple { "search" } else { "autocomplete" }),
+        cancel: CancelFlag::default(),
+    };
+    // Only autocomplete requests are superseded, full searches are always answered.
+    let pending = match &params.client {
+        Some(client) if !simple => {
+            let id = state.autocompletes.start(client, &options.cancel);
+            Some((state.autocompletes.clone(), client.clone(), id))
+        }
+        _ => None,
+    };
+    let search_engine = state.search_engine;
+    let results = blocking(move || {
+        if options.cancel.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        let results = if params.explain {
+            search_engine.explain(&query, mode)
+        } else if params.count {
+            search_engine.count(&query, mode, &options)
+        } else if params.v == Some(2) {
+            search_engine.search_envelope(&query, mode, sort, &options)
+        } else {
+            search_engine.search(&query, mode, sort, &options)
+        };
+        results.map_err(|e| {
+            if !matches!(e, Error::Cancelled) {
+                warn!("error: {}\nquery: {}", e, query);
+            }
+            e
+        })
+    })
+    .await;
+    let latest = match pending {
+        Some((autocompletes, client, id)) => autocompletes.finish(&client, id),
+        None => true,
+    };
+    // The client has already moved on from a superseded search, so it gets no results.
+    match results {
+        Ok(results) if latest => {
+            let mut response = json_response(StatusCode::OK, results);
+            if let Some(variant) = variant {
+                let value = HeaderValue::from_str(&variant.to_string()).unwrap();
+                response.headers_mut().insert("x-ranking-variant", value);
+            }
+            Ok(response)
+        }
+        Ok(_) | Err(Error::Cancelled) => Ok(json_response(StatusCode::OK, String::from("[]"))),
+        Err(e) => Err(e),
+    }
+}
+
+/// Search page for smoke-testing deployments, built into the binary.
+async fn ui() -> Response {
+    let html = include_str!("ui.html");
+    ([(header::CONTENT_TYPE, "text/html; charset=utf-8")], html).into_response()
+}
+
+async fn schema(State(state): State<AppState>) -> Response {
+    json_response(StatusCode::OK, state.search_engine.schema_info())
+}
+
+/// Document counts and the size of the index, to track what the stored fields cost.
+async fn stats(State(state): State<AppState>) -> Result<Response, Error> {
+    let search_engine = state.search_engine;
+    let stats = blocking(move || search_engine.stats()).await?;
+    Ok(json_response(StatusCode::OK, stats))
+}
+
+async fn live_search(State(state): State<AppState>, request: Request<Body>) -> Response {
+    websocket::upgrade(request, state.search_engine).into_response()
+}
+
+#[derive(Deserialize)]
+struct TokenizeParams {
+    text: Option<String>,
+    tokenizer: Option<String>,
+}
+
+async fn tokenize(
+    State(state): State<AppState>,
+    Params(params): Params<TokenizeParams>,
+) -> Result<Response, Error> {
+    let (text, tokenizer) = match (params.text, params.tokenizer) {
+        (Some(text), Some(tokenizer)) => (text, tokenizer),
+        _ => {
+            let message = String::from("text and tokenizer are required");
+            return Err(Error::Query(message));
+        }
+    };
+    match state.search_engine.tokenize(&text, &tokenizer) {
+        Some(response) => Ok(json_response(StatusCode::OK, response)),
+        None => Err(Error::Query(format!("Unknown tokenizer {}", tokenizer))),
+    }
+}
+
+#[derive(Deserialize)]
+struct ParseQueryParams {
+    q: Option<String>,
+    parser: Option<String>,
+}
+
+async fn parse_query(
+    State(state): State<AppState>,
+    Params(params): Params<ParseQueryParams>,
+) -> Result<Response, Error> {
+    let query = params
+        .q
+        .ok_or_else(|| Error::Query(String::from("q is required")))?;
+    let mode = match params.parser {
+        Some(parser) => parser.parse().map_err(Error::Query)?,
+        None => Mode::Full,
+    };
+    let search_engine = state.search_engine;
+    let response = blocking(move || search_engine.parse_query(&query, mode)).await?;
+    Ok(json_response(StatusCode::OK, response))
+}
+
+#[derive(Deserialize)]
+struct TermsParams {
+    field: Option<String>,
+    #[serde(default)]
+    prefix: String,
+    /// Number of terms, 100 by default.
+    limit: Option<usize>,
+}
+
+/// Return the most common indexed terms of a field that start with a prefix, e.g. for building
+/// stopword lists or checking what an analyzer made of the lyrics.
+async fn terms(
+    State(state): State<AppState>,
+    Params(params): Params<TermsParams>,
+) -> Result<Response, Error> {
+    let field = params
+        .field
+        .ok_or_else(|| Error::Query(String::from("field is required")))?;
+    let limit = params.limit.unwrap_or(100);
+    let prefix = params.prefix;
+    let search_engine = state.search_engine;
+    let response = blocking(move || search_engine.terms(&field, &prefix, limit)).await?;
+    Ok(json_response(StatusCode::OK, response))
+}
+
+/// Stream the stored fields of all documents as newline delimited JSON.
+async fn export(State(state): State<AppState>) -> Response {
+    let (mut sender, receiver) = mpsc::channel::<Result<String, Infallible>>(16);
+    tokio::task::spawn_blocking(move || {
+        let result = state
+            .search_engine
+            .export(|line| block_on(sender.send(Ok(line))).is_ok());
+        if let Err(e) = result {
+            warn!("export error: {}", e);
+        }
+    });
+    (
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        StreamBody::new(receiver),
+    )
+        .into_response()
+}
+
+async fn sitemap(State(state): State<AppState>) -> Result<Response, Error> {
+    let base_url = match &state.config().base_url {
+        Some(base_url) => base_url.clone(),
+        None => return Ok(not_found().await),
+    };
+    let search_engine = state.search_engine;
+    match blocking(move || search_engine.sitemap(&base_url)).await {
+        Ok(sitemap) => Ok(([(header::CONTENT_TYPE, "application/xml")], sitemap).into_response()),
+        Err(e) => {
+            warn!("sitemap error: {}", e);
+            Err(e)
+        }
+    }
+}
+
+/// Announce a finished reindex to the event subscribers and return its outcome as JSON.
+fn reindexed(events: &broadcast::Sender<String>, num_docs: u64) -> String {
+    info!("Reindexed {} documents", num_docs);
+    let response = format!("{{\"documents\": {}}}", num_docs);
+    // Sending only fails if there are no subscribers.
+    let _ = events.send(format!("event: reindex\ndata: {}\n\n", response));
+    response
+}
+
+/// Rebuild the index and notify the event subscribers. If it fails, the previous index keeps
+/// being served and the reindex is retried in the background.
+async fn reindex(State(state): State<AppState>) -> Result<Response, Error> {
+    let search_engine = state.search_engine.clone();
+    // Retries are already scheduled if the previous reindex failed too.
+    let retrying = search_engine.reindex_failure().is_some();
+    match blocking(move || search_engine.reindex()).await {
+        Ok(num_docs) => Ok(json_response(
+            StatusCode::OK,
+            reindexed(&state.events, num_docs),
+        )),
+        Err(e) => {
+            warn!("reindex error: {}", e);
+            if !retrying {
+                let delay = REINDEX_RETRY_DELAY;
+                tokio::spawn(retry_reindex(state.search_engine, state.events, delay));
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Rebuild the index in the background, e.g. after starting from the index of the previous
+/// run, retrying with exponential backoff until it succeeds.
+pub async fn reindex_in_background(search_engine: SearchEngine, events: broadcast::Sender<String>) {
+    retry_reindex(search_engine, events, Duration::from_secs(0)).await
+}
+
+async fn retry_reindex(
+    search_engine: SearchEngine,
+    events: broadcast::Sender<String>,
+    mut delay: Duration,
+) {
+    loop {
+        tokio::time::sleep(delay).await;
+        let engine = search_engine.clone();
+        match blocking(move || engine.reindex()).await {
+            Ok(num_docs) => {
+                reindexed(&events, num_docs);
+                return;
+            }
+            Err(e) => {
+                delay = (delay * 2).clamp(REINDEX_RETRY_DELAY, MAX_REINDEX_RETRY_DELAY);
+                warn!("reindex error: {}, retrying in {}s", e, delay.as_secs());
+            }
+        }
+    }
+}
+
+/// Whether the server is healthy, or degraded since the latest reindexes failed and the
+/// previous index is served. Degraded servers still answer searches, so both are successful.
+async fn health(State(state): State<AppState>) -> Response {
+    let body = match state.search_engine.reindex_failure() {
+        Some(failure) => json!({
+            "status": "degraded",
+            "reindex_error": failure.error,
+            "reindex_attempts": failure.attempts,
+        }),
+        None => json!({ "status": "ok" }),
+    };
+    json_response(StatusCode::OK, body.to_string())
+}
+
+/// Stream index events to the client as Server-Sent Events.
+async fn subscribe(State(state): State<AppState>) -> Response {
+    let stream = stream::unfold(state.events.subscribe(), |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(event) => return Some((Ok::<_, Infallible>(event), receiver)),
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    });
+    (
+        [
+            (header::CONTENT_TYPE, "text/event-stream"),
+            (header::CACHE_CONTROL, "no-cache"),
+        ],
+        StreamBody::new(stream),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pending_searches() {
+        let autocompletes = PendingSearches::default();
+        let (first_flag, other_flag, second_flag) = Default::default();
+        let first = autocompletes.start("a", &first_flag);
+        let other = autocompletes.start("b", &other_flag);
+        assert!(!first_flag.is_cancelled());
+        let second = autocompletes.start("a", &second_flag);
+        assert!(first_flag.is_cancelled());
+        assert!(!other_flag.is_cancelled());
+
+        assert!(!autocompletes.finish("a", first));
+        assert!(autocompletes.finish("b", other));
+        assert!(autocompletes.finish("a", second));
+        assert!(!second_flag.is_cancelled());
+        assert!(autocompletes.latest.lock().unwrap().get("a").is_none());
+    }
+}
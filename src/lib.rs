@@ -0,0 +1,31 @@
+pub mod cancellation;
+pub mod config;
+pub mod edge_ngram_filter;
+pub mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod greek_lower_caser;
+pub mod homoglyph_filter;
+#[cfg(feature = "http-server")]
+pub mod http;
+pub mod number_filter;
+pub mod phonetic_filter;
+pub mod popularity;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod query_cache;
+pub mod ranking;
+pub mod replay;
+#[cfg(feature = "s3")]
+pub mod s3;
+pub mod scales;
+pub mod search_engine;
+pub mod self_test;
+pub mod shingle_filter;
+pub mod snapshot;
+pub mod song;
+pub mod tokenizer;
+pub mod transliteration;
+pub mod utils;
+#[cfg(feature = "http-server")]
+pub mod websocket;
@@ -1,75 +1,23 @@
-use std::collections::HashMap;
-use std::convert::Infallible;
-use std::net::SocketAddr;
+use std::io::Write;
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::time::Duration;
 
-use hyper::service::{make_service_fn, service_fn};
-use hyper::Server;
-use hyper::{header, Body, Method, Request, Response, StatusCode};
 use log::{error, info, warn};
-use url::form_urlencoded;
-
-mod greek_lower_caser;
-mod search_engine;
-mod song;
-mod tokenizer;
-mod utils;
-
-use crate::search_engine::SearchEngine;
-
-async fn buzuki(
-    request: Request<Body>,
-    search_engine: SearchEngine,
-) -> Result<Response<Body>, hyper::Error> {
-    fn get_json_response(status: StatusCode, body: Body) -> Result<Response<Body>, hyper::Error> {
-        Ok(Response::builder()
-            .header(header::CONTENT_TYPE, "application/json")
-            .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
-            .status(status)
-            .body(body)
-            .unwrap())
-    }
-
-    fn search(
-        request: &Request<Body>,
-        search_engine: &SearchEngine,
-        simple: bool,
-    ) -> (StatusCode, String) {
-        let mut response = String::from("[]");
-        let mut status = StatusCode::NOT_FOUND;
-        if let Some(query) = request.uri().query() {
-            let query_map = form_urlencoded::parse(query.as_bytes())
-                .into_owned()
-                .collect::<HashMap<String, String>>();
-            if let Some(value) = query_map.get("q") {
-                let results = search_engine.search(value, simple);
-                match results {
-                    Ok(string) => {
-                        response = string;
-                        status = StatusCode::OK;
-                    }
-                    Err(e) => {
-                        warn!("error: {}\nquery: {}", e, query);
-                        response = format!("{{\"error\": \"{}\"}}", e);
-                        status = StatusCode::INTERNAL_SERVER_ERROR;
-                    }
-                }
-            }
-        }
-        (status, response)
-    }
+use tokio::sync::{broadcast, watch};
 
-    match (request.method(), request.uri().path()) {
-        (&Method::GET, "/") => {
-            let (status, response) = search(&request, &search_engine, true);
-            get_json_response(status, Body::from(response))
-        }
-        (&Method::GET, "/autocomplete/") => {
-            let (status, response) = search(&request, &search_engine, false);
-            get_json_response(status, Body::from(response))
-        }
-        _ => get_json_response(StatusCode::NOT_FOUND, Body::from("[]")),
-    }
-}
+#[cfg(feature = "grpc")]
+mod grpc;
+
+use buzuki_search::config::{changed_vars, read_config_file, Config, Distribution, Effect, Vars};
+use buzuki_search::error::Result;
+use buzuki_search::http;
+use buzuki_search::replay;
+use buzuki_search::search_engine::SearchEngine;
+use buzuki_search::self_test;
+
+/// Interval at which the config file is checked for changes.
+const CONFIG_POLL: Duration = Duration::from_secs(5);
 
 /// Initialize env_logger to use info level by default.
 fn init_logger() {
@@ -78,37 +26,322 @@ fn init_logger() {
     builder.init();
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     init_logger();
 
-    let key = "BUZUKI_SONGDIR";
-    let songdir = match std::env::var(key) {
-        Ok(val) => val,
+    let vars = Config::read_vars();
+    let (config, vars) = match vars.and_then(|vars| Ok((Config::from_vars(&vars)?, vars))) {
+        Ok((config, vars)) => (Arc::new(config), vars),
         Err(e) => {
-            error!("Couldn't get {}: {}", key, e);
+            error!("{}", e);
             std::process::exit(1);
         }
     };
 
-    let search_engine = SearchEngine::new(&songdir)?;
+    match std::env::args().nth(1).as_deref() {
+        Some("healthcheck") => std::process::exit(healthcheck(&config)),
+        Some("--self-test") => std::process::exit(self_test(&config)),
+        Some("serialize") => std::process::exit(serialize(&config)),
+        Some("vocabulary") => {
+            let fields: Vec<String> = std::env::args().skip(2).collect();
+            std::process::exit(vocabulary(&config, &fields))
+        }
+        Some("compare-analyzers") => {
+            let args: Vec<String> = std::env::args().skip(2).collect();
+            std::process::exit(compare_analyzers(&vars, &args))
+        }
+        Some("replay") => match std::env::args().nth(2) {
+            Some(path) => std::process::exit(replay(&config, &path)),
+            None => {
+                error!("replay: Expected a query log, e.g. replay queries.log");
+                std::process::exit(1)
+            }
+        },
+        _ => {}
+    }
+
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
+    builder.enable_all();
+    if let Some(worker_threads) = config.worker_threads {
+        builder.worker_threads(worker_threads);
+    }
+    if let Some(blocking_threads) = config.blocking_threads {
+        builder.max_blocking_threads(blocking_threads);
+    }
+    builder.build()?.block_on(serve(config, vars))
+}
+
+async fn serve(
+    config: Arc<Config>,
+    vars: Vars,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let search_engine = SearchEngine::new(&config)?;
 
-    let addr = SocketAddr::from(([127, 0, 0, 1], 1337));
+    let (events, _) = broadcast::channel(16);
 
-    let make_service = make_service_fn(move |_| {
+    if search_engine.is_stale() {
+        info!("Serving the previous index until the songs are reindexed");
+        tokio::spawn(http::reindex_in_background(
+            search_engine.clone(),
+            events.clone(),
+        ));
+    }
+
+    #[cfg(feature = "grpc")]
+    if let Some(grpc_addr) = config.grpc_addr {
+        tokio::spawn(grpc::serve(
+            grpc_addr,
+            search_engine.clone(),
+            config.clone(),
+            events.clone(),
+        ));
+    }
+    #[cfg(not(feature = "grpc"))]
+    if config.grpc_addr.is_some() {
+        warn!("BUZUKI_GRPC_ADDR is set, but gRPC support was not compiled in");
+    }
+
+    if let Distribution::Follow(_) = config.distribution {
+        tokio::spawn(follow(search_engine.clone(), config.clone()));
+    }
+
+    let listener = TcpListener::bind(config.http_addr)?;
+
+    info!("Listening on http://{}", config.http_addr);
+
+    let (reloads, config_receiver) = watch::channel(config.clone());
+    if config.config_file.is_some() {
+        tokio::spawn(watch_config(search_engine.clone(), reloads, vars));
+    }
+
+    http::serve(listener, search_engine, config_receiver, events).await?;
+
+    Ok(())
+}
+
+/// Switch to new snapshots as they are published.
+async fn follow(search_engine: SearchEngine, config: Arc<Config>) {
+    let mut interval = tokio::time::interval(config.snapshot_poll);
+    loop {
+        interval.tick().await;
         let search_engine = search_engine.clone();
-        async move {
-            Ok::<_, Infallible>(service_fn(move |request| {
-                buzuki(request, search_engine.clone())
-            }))
+        match tokio::task::spawn_blocking(move || search_engine.sync()).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => warn!("snapshot error: {}", e),
+            Err(e) => warn!("snapshot error: {}", e),
         }
-    });
+    }
+}
 
-    let server = Server::bind(&addr).serve(make_service);
+/// Apply the reloadable settings of the config file when it changes, and warn about changed
+/// settings that only apply after a restart.
+async fn watch_config(search_engine: SearchEngine, config: watch::Sender<Arc<Config>>, vars: Vars) {
+    let mut vars = vars;
+    let mut interval = tokio::time::interval(CONFIG_POLL);
+    loop {
+        interval.tick().await;
+        let new_vars = match Config::read_vars() {
+            Ok(new_vars) => new_vars,
+            Err(e) => {
+                warn!("config error: {}", e);
+                continue;
+            }
+        };
+        let changed = changed_vars(&vars, &new_vars);
+        if changed.is_empty() {
+            continue;
+        }
+        match Config::from_vars(&new_vars) {
+            Ok(new_config) => {
+                for key in changed {
+                    match Effect::of(key) {
+                        Effect::Reload => info!("Reloaded {}", key),
+                        Effect::Reindex => warn!("{} changed, restart to reindex with it", key),
+                        Effect::Restart => warn!("{} changed, restart to apply it", key),
+                    }
+                }
+                let reloaded = config.borrow().reload(&new_config);
+                search_engine.set_scoring(
+                    reloaded.boosts,
+                    reloaded.experiment.map(|experiment| experiment.boosts),
+                    reloaded.min_score,
+                    reloaded.min_relative_score,
+                );
+                config.send_replace(Arc::new(reloaded));
+            }
+            // The previous settings stay in effect.
+            Err(e) => warn!("config error: {}", e),
+        }
+        vars = new_vars;
+    }
+}
 
-    info!("Listening on http://{}", addr);
+/// Check that the server at the configured address answers, for container health checks.
+/// Returns the exit code.
+fn healthcheck(config: &Config) -> i32 {
+    let url = format!("http://{}/health/", config.http_addr);
+    let runtime = match tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            error!("healthcheck: {}", e);
+            return 1;
+        }
+    };
+    let request = async {
+        let request = hyper::Client::new().get(url.parse().unwrap());
+        tokio::time::timeout(Duration::from_secs(5), request).await
+    };
+    match runtime.block_on(request) {
+        Ok(Ok(response)) if response.status().is_success() => 0,
+        Ok(Ok(response)) => {
+            error!("healthcheck: {} returned {}", url, response.status());
+            1
+        }
+        Ok(Err(e)) => {
+            error!("healthcheck: {}: {}", url, e);
+            1
+        }
+        Err(_) => {
+            error!("healthcheck: {} timed out", url);
+            1
+        }
+    }
+}
 
-    server.await?;
+/// Build an index for a command that only reads it, in a temporary directory and without
+/// publishing or following snapshots, so that the index that is served is left alone.
+fn offline_engine(config: &Config) -> Result<SearchEngine> {
+    let config = Config {
+        index_dir: None,
+        lazy_start: false,
+        distribution: Distribution::Standalone,
+        ..config.clone()
+    };
+    SearchEngine::new(&config)
+}
 
-    Ok(())
+/// Build the index and run canned queries against it, printing the outcome of each. Returns
+/// the exit code, which is 0 only if all of them pass.
+fn self_test(config: &Config) -> i32 {
+    let checks = match offline_engine(config).and_then(|engine| self_test::run(&engine)) {
+        Ok(checks) => checks,
+        Err(e) => {
+            error!("self-test: {}", e);
+            return 1;
+        }
+    };
+    let mut exit_code = 0;
+    for check in checks {
+        let outcome = if check.passed { "PASS" } else { "FAIL" };
+        println!("{} {}: {}", outcome, check.name, check.query);
+        if !check.passed {
+            exit_code = 1;
+        }
+    }
+    exit_code
+}
+
+/// Build the index and print its terms with their document frequencies as CSV, for the fields
+/// that are given or all of them. Returns the exit code.
+fn vocabulary(config: &Config, fields: &[String]) -> i32 {
+    let engine = match offline_engine(config) {
+        Ok(engine) => engine,
+        Err(e) => {
+            error!("vocabulary: {}", e);
+            return 1;
+        }
+    };
+    let mut stdout = std::io::BufWriter::new(std::io::stdout().lock());
+    // Writing fails when the output is closed early, e.g. when piped to head.
+    let result = engine.export_vocabulary(fields, |line| stdout.write_all(line.as_bytes()).is_ok());
+    if let Err(e) = result {
+        error!("vocabulary: {}", e);
+        return 1;
+    }
+    let _ = stdout.flush();
+    0
+}
+
+/// Build the index and write it serialized to stdout, for serving it with
+/// `SearchEngine::from_serialized` where indexes can't be built, e.g. in a browser. Returns the
+/// exit code.
+fn serialize(config: &Config) -> i32 {
+    let result = offline_engine(config).and_then(|engine| engine.serialize());
+    let serialized = match result {
+        Ok(serialized) => serialized,
+        Err(e) => {
+            error!("serialize: {}", e);
+            return 1;
+        }
+    };
+    if let Err(e) = std::io::stdout().lock().write_all(&serialized) {
+        error!("serialize: {}", e);
+        return 1;
+    }
+    0
+}
+
+/// Build the index with the current settings and again with those of a config file laid over
+/// them, e.g. one that changes the numbers or word limits of the analyzers, and print the terms
+/// whose document frequencies differ as CSV, for the fields that are given or all of them.
+/// Both indexes are built in temporary directories. Returns the exit code.
+fn compare_analyzers(vars: &Vars, args: &[String]) -> i32 {
+    let (path, fields) = match args.split_first() {
+        Some((path, fields)) => (path, fields),
+        None => {
+            error!("compare-analyzers: Expected a config file, e.g. compare-analyzers b.env body");
+            return 1;
+        }
+    };
+    let engines = read_config_file(path.as_ref()).and_then(|overrides| {
+        let mut vars_b = vars.clone();
+        vars_b.extend(overrides);
+        let engine = |vars: &Vars| offline_engine(&Config::from_vars(vars)?);
+        Ok((engine(vars)?, engine(&vars_b)?))
+    });
+    let (engine_a, engine_b) = match engines {
+        Ok(engines) => engines,
+        Err(e) => {
+            error!("compare-analyzers: {}", e);
+            return 1;
+        }
+    };
+    let mut stdout = std::io::BufWriter::new(std::io::stdout().lock());
+    let result = engine_a.compare_vocabulary(&engine_b, fields, |line| {
+        stdout.write_all(line.as_bytes()).is_ok()
+    });
+    if let Err(e) = result {
+        error!("compare-analyzers: {}", e);
+        return 1;
+    }
+    let _ = stdout.flush();
+    0
+}
+
+/// Build the index and search for the queries of a log, printing the latency percentiles, the
+/// rate of searches without results and the most frequent of those queries. Returns the exit
+/// code.
+fn replay(config: &Config, path: &str) -> i32 {
+    let report = offline_engine(config).and_then(|engine| replay::run(&engine, path.as_ref()));
+    let report = match report {
+        Ok(report) => report,
+        Err(e) => {
+            error!("replay: {}", e);
+            return 1;
+        }
+    };
+    println!("queries: {}", report.queries());
+    println!("errors: {}", report.errors);
+    for percent in [50.0, 90.0, 99.0, 100.0] {
+        let latency = report.percentile(percent).as_secs_f64() * 1000.0;
+        println!("p{}: {:.2}ms", percent, latency);
+    }
+    println!("zero results: {:.1}%", report.zero_result_rate() * 100.0);
+    for (query, count) in report.zero_results.iter().take(20) {
+        println!("{:>6} {}", count, query);
+    }
+    0
 }
@@ -0,0 +1,52 @@
+use tantivy::tokenizer::BoxTokenStream;
+use tantivy::tokenizer::{Token, TokenFilter, TokenStream};
+
+use crate::config::NumberPolicy;
+
+impl TokenFilter for NumberFilter {
+    fn transform<'a>(&self, token_stream: BoxTokenStream<'a>) -> BoxTokenStream<'a> {
+        BoxTokenStream::from(NumberFilterTokenStream {
+            tail: token_stream,
+            numbers: self.numbers,
+        })
+    }
+}
+
+/// Token filter that applies the number policy, removing terms made of digits only, e.g. "30"
+/// but not "2x", if numbers are dropped.
+#[derive(Clone, Copy)]
+pub struct NumberFilter {
+    numbers: NumberPolicy,
+}
+
+impl NumberFilter {
+    pub fn new(numbers: NumberPolicy) -> NumberFilter {
+        NumberFilter { numbers }
+    }
+}
+
+pub struct NumberFilterTokenStream<'a> {
+    tail: BoxTokenStream<'a>,
+    numbers: NumberPolicy,
+}
+
+impl<'a> TokenStream for NumberFilterTokenStream<'a> {
+    fn advance(&mut self) -> bool {
+        while self.tail.advance() {
+            if self.numbers == NumberPolicy::Keep
+                || !self.tail.token().text.chars().all(char::is_numeric)
+            {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn token(&self) -> &Token {
+        self.tail.token()
+    }
+
+    fn token_mut(&mut self) -> &mut Token {
+        self.tail.token_mut()
+    }
+}
@@ -0,0 +1,76 @@
+use std::mem;
+
+use tantivy::tokenizer::BoxTokenStream;
+use tantivy::tokenizer::{Token, TokenFilter, TokenStream};
+
+impl TokenFilter for PhoneticFilter {
+    fn transform<'a>(&self, token_stream: BoxTokenStream<'a>) -> BoxTokenStream<'a> {
+        BoxTokenStream::from(PhoneticFilterTokenStream {
+            tail: token_stream,
+            buffer: String::with_capacity(100),
+        })
+    }
+}
+
+/// Token filter that spells Greek terms the way they sound, so that common misspellings like
+/// "τρεχι" for "τρεχει" produce the same term. Expects lowercased terms without accents.
+#[derive(Clone)]
+pub struct PhoneticFilter;
+
+pub struct PhoneticFilterTokenStream<'a> {
+    buffer: String,
+    tail: BoxTokenStream<'a>,
+}
+
+/// Writes a phonetic version of text into output. The ι sounds (η, υ, ει, οι, υι) become ι,
+/// ω becomes ο, αι becomes ε and double consonants become single. The υ of ου, αυ and ευ is
+/// kept, since it doesn't sound like ι there.
+fn to_phonetic(text: &str, output: &mut String) {
+    output.clear();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let next = chars.peek().copied();
+        let c = match (c, next) {
+            ('α', Some('υ')) | ('ε', Some('υ')) | ('ο', Some('υ')) => {
+                chars.next();
+                output.push(c);
+                'υ'
+            }
+            ('ε', Some('ι')) | ('ο', Some('ι')) | ('υ', Some('ι')) => {
+                chars.next();
+                'ι'
+            }
+            ('α', Some('ι')) => {
+                chars.next();
+                'ε'
+            }
+            ('η', _) | ('υ', _) => 'ι',
+            ('ω', _) => 'ο',
+            (c, _) => c,
+        };
+        let is_consonant = ('β'..='ω').contains(&c) && !matches!(c, 'ε' | 'ι' | 'ο' | 'υ');
+        if is_consonant && output.ends_with(c) {
+            continue;
+        }
+        output.push(c);
+    }
+}
+
+impl<'a> TokenStream for PhoneticFilterTokenStream<'a> {
+    fn advance(&mut self) -> bool {
+        if !self.tail.advance() {
+            return false;
+        }
+        to_phonetic(&self.tail.token().text, &mut self.buffer);
+        mem::swap(&mut self.tail.token_mut().text, &mut self.buffer);
+        true
+    }
+
+    fn token(&self) -> &Token {
+        self.tail.token()
+    }
+
+    fn token_mut(&mut self) -> &mut Token {
+        self.tail.token_mut()
+    }
+}
@@ -0,0 +1,76 @@
+//! View counts of the songs, exported from the analytics of the site and blended into the
+//! ranking by `BUZUKI_POPULARITY_BOOST`.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::error::{Error, Result};
+
+/// Read the view counts of a file, keyed by song slug. JSON files hold an object that maps
+/// slugs to counts, e.g. `{"fragkosyriani": 120}`, and other files hold `slug,views` lines,
+/// optionally under a header line. Songs that are missing have no views.
+pub fn read_views(path: &Path) -> Result<HashMap<String, u64>> {
+    let contents = std::fs::read_to_string(path)?;
+    if path
+        .extension()
+        .is_some_and(|extension| extension == "json")
+    {
+        return serde_json::from_str(&contents)
+            .map_err(|e| Error::Parse(format!("{}: {}", path.display(), e)));
+    }
+    let mut views = HashMap::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let count = line
+            .split_once(',')
+            .and_then(|(slug, count)| Some((slug.trim(), count.trim().parse().ok()?)));
+        match count {
+            Some((slug, count)) => views.insert(slug.to_string(), count),
+            // A header, such as "slug,views".
+            None if number == 0 => continue,
+            None => {
+                let message = format!("{}:{}: Expected slug,views", path.display(), number + 1);
+                return Err(Error::Parse(message));
+            }
+        };
+    }
+    Ok(views)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use tempfile::Builder;
+
+    use crate::popularity::read_views;
+
+    fn write_file(suffix: &str, contents: &str) -> tempfile::NamedTempFile {
+        let mut file = Builder::new().suffix(suffix).tempfile().unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_read_views() {
+        let file = write_file(
+            ".csv",
+            "slug,views\nfragkosyriani, 120\n\nta_mple_parathyra_sou,7\n",
+        );
+        let views = read_views(file.path()).unwrap();
+        assert_eq!(views.len(), 2);
+        assert_eq!(views["fragkosyriani"], 120);
+        assert_eq!(views["ta_mple_parathyra_sou"], 7);
+
+        let file = write_file(".json", r#"{"fragkosyriani": 120}"#);
+        assert_eq!(read_views(file.path()).unwrap()["fragkosyriani"], 120);
+
+        let file = write_file(".csv", "fragkosyriani,120\nta_mple_parathyra_sou,many\n");
+        assert!(read_views(file.path()).is_err());
+        let file = write_file(".json", "[120]");
+        assert!(read_views(file.path()).is_err());
+    }
+}
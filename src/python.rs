@@ -0,0 +1,186 @@
+//! Python bindings of the search engine, the song parser and the transliteration, built as the
+//! `buzuki_search` extension module by `make python`.
+
+// The code generated by pyo3 for methods returning a PyResult converts the error into itself.
+#![allow(clippy::useless_conversion)]
+
+use std::collections::HashMap;
+
+use lazy_static::lazy_static;
+use pyo3::exceptions::{PyIOError, PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+
+use crate::config::NumberPolicy;
+use crate::error::Error;
+use crate::search_engine::{Mode, SearchEngine, Sort};
+use crate::song::{LyricsFilter, Song};
+use crate::transliteration::Transliteration;
+
+lazy_static! {
+    static ref TRANSLITERATION: Transliteration = Transliteration::new(NumberPolicy::Keep);
+    static ref LYRICS: LyricsFilter = LyricsFilter::default();
+}
+
+impl From<Error> for PyErr {
+    fn from(e: Error) -> PyErr {
+        match e {
+            Error::Io(e) => PyIOError::new_err(e.to_string()),
+            Error::Parse(message) | Error::Query(message) => PyValueError::new_err(message),
+            Error::Index(e) => PyRuntimeError::new_err(e.to_string()),
+            Error::Cancelled => PyRuntimeError::new_err(e.to_string()),
+        }
+    }
+}
+
+/// A parsed song file, or a song created from its fields.
+#[pyclass(name = "Song")]
+#[derive(Clone)]
+struct PySong {
+    song: Song,
+}
+
+#[pymethods]
+impl PySong {
+    /// Create a song from its fields instead of a song file.
+    #[new]
+    #[pyo3(signature = (name, artist, body, year = None))]
+    fn new(name: String, artist: String, body: String, year: Option<u64>) -> PySong {
+        let song = Song::builder(name, artist, body)
+            .year(year)
+            .build(&TRANSLITERATION);
+        PySong { song }
+    }
+
+    #[getter]
+    fn name(&self) -> &str {
+        &self.song.name
+    }
+
+    #[getter]
+    fn slug(&self) -> &str {
+        &self.song.slug
+    }
+
+    #[getter]
+    fn artist(&self) -> &str {
+        &self.song.artist
+    }
+
+    #[getter]
+    fn artist_slug(&self) -> &str {
+        &self.song.artist_slug
+    }
+
+    #[getter]
+    fn year(&self) -> Option<u64> {
+        self.song.year
+    }
+
+    #[getter]
+    fn modified(&self) -> Option<u64> {
+        self.song.modified
+    }
+
+    #[getter]
+    fn scale(&self) -> Option<&str> {
+        self.song.scale.as_deref()
+    }
+
+    #[getter]
+    fn rhythm(&self) -> Option<&str> {
+        self.song.rhythm.as_deref()
+    }
+
+    #[getter]
+    fn body(&self) -> &str {
+        &self.song.body
+    }
+
+    #[getter]
+    fn body_greeklish(&self) -> &str {
+        &self.song.body_greeklish
+    }
+
+    #[getter]
+    fn raw_body(&self) -> Option<&str> {
+        self.song.raw_body.as_deref()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Song({:?}, {:?})", self.song.name, self.song.artist)
+    }
+}
+
+#[pyclass(name = "SearchEngine")]
+struct PySearchEngine {
+    engine: SearchEngine,
+}
+
+#[pymethods]
+impl PySearchEngine {
+    /// Index the song files of the directory.
+    #[new]
+    fn new(py: Python, song_dir: String) -> PyResult<PySearchEngine> {
+        let engine = py.allow_threads(|| SearchEngine::builder(song_dir).build())?;
+        Ok(PySearchEngine { engine })
+    }
+
+    /// Index the given songs, keeping the index in RAM.
+    #[staticmethod]
+    fn from_songs(py: Python, songs: Vec<PySong>) -> PyResult<PySearchEngine> {
+        let songs = songs.into_iter().map(|song| song.song).collect();
+        let engine = py.allow_threads(|| SearchEngine::from_songs(songs))?;
+        Ok(PySearchEngine { engine })
+    }
+
+    /// Return the stored fields of the hits, like GET / does.
+    #[pyo3(signature = (query, mode = "full", sort = "relevance"))]
+    fn search(
+        &self,
+        py: Python,
+        query: &str,
+        mode: &str,
+        sort: &str,
+    ) -> PyResult<Vec<HashMap<String, String>>> {
+        let mode: Mode = mode.parse().map_err(PyValueError::new_err)?;
+        let sort: Sort = sort.parse().map_err(PyValueError::new_err)?;
+        Ok(py.allow_threads(|| self.engine.hits(query, mode, sort))?)
+    }
+
+    /// Return the stored fields of the hits, like GET /autocomplete/ does.
+    fn autocomplete(&self, py: Python, query: &str) -> PyResult<Vec<HashMap<String, String>>> {
+        self.search(py, query, "ngram", "relevance")
+    }
+
+    /// Rebuild the index and return the number of indexed documents.
+    fn reindex(&self, py: Python) -> PyResult<u64> {
+        Ok(py.allow_threads(|| self.engine.reindex())?)
+    }
+}
+
+/// Parse the contents of a song file.
+#[pyfunction]
+fn parse_song(contents: &str) -> PyResult<PySong> {
+    let song = Song::parse(contents, &TRANSLITERATION, &LYRICS)?;
+    Ok(PySong { song })
+}
+
+#[pyfunction]
+fn to_greeklish(text: &str) -> String {
+    TRANSLITERATION.to_greeklish(text)
+}
+
+#[pyfunction]
+fn to_slug(name: &str) -> String {
+    TRANSLITERATION.to_slug(name)
+}
+
+#[pymodule]
+fn buzuki_search(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PySearchEngine>()?;
+    module.add_class::<PySong>()?;
+    module.add_function(wrap_pyfunction!(parse_song, module)?)?;
+    module.add_function(wrap_pyfunction!(to_greeklish, module)?)?;
+    module.add_function(wrap_pyfunction!(to_slug, module)?)?;
+    Ok(())
+}
@@ -0,0 +1,143 @@
+//! Parsed queries of recent searches. Autocomplete clients send the same prefixes over and over,
+//! and parsing them runs the analyzers of every searched field and expands greeklish, so the
+//! parsed queries are kept and cloned instead.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use tantivy::query::Query;
+
+use crate::search_engine::{Mode, Scope};
+
+struct Entry {
+    query: Box<dyn Query>,
+    /// Tick of the last lookup that returned the entry.
+    used: u64,
+}
+
+#[derive(Default)]
+struct Entries {
+    map: HashMap<(Mode, Scope, String), Entry>,
+    tick: u64,
+}
+
+/// Least recently used cache of parsed queries, keyed by the mode, the scope and the normalized
+/// query.
+pub struct QueryCache {
+    capacity: usize,
+    entries: Mutex<Entries>,
+}
+
+impl QueryCache {
+    pub fn new(capacity: usize) -> QueryCache {
+        QueryCache {
+            capacity,
+            entries: Mutex::new(Entries::default()),
+        }
+    }
+
+    /// Return the cached query, or parse it and cache the result if parsing succeeds.
+    pub fn get_or_parse<E>(
+        &self,
+        mode: Mode,
+        scope: Scope,
+        query: &str,
+        parse: impl FnOnce() -> Result<Box<dyn Query>, E>,
+    ) -> Result<Box<dyn Query>, E> {
+        if self.capacity == 0 {
+            return parse();
+        }
+        let key = (mode, scope, query.to_string());
+        {
+            let mut entries = self.entries.lock().unwrap();
+            entries.tick += 1;
+            let tick = entries.tick;
+            if let Some(entry) = entries.map.get_mut(&key) {
+                entry.used = tick;
+                return Ok(entry.query.box_clone());
+            }
+        }
+        // Parsed without the lock, so that a slow parse doesn't hold up the other searches.
+        let parsed_query = parse()?;
+        let mut entries = self.entries.lock().unwrap();
+        if entries.map.len() >= self.capacity && !entries.map.contains_key(&key) {
+            let oldest = entries
+                .map
+                .iter()
+                .min_by_key(|(_, entry)| entry.used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.map.remove(&oldest);
+            }
+        }
+        let used = entries.tick;
+        let entry = Entry {
+            query: parsed_query.box_clone(),
+            used,
+        };
+        entries.map.insert(key, entry);
+        Ok(parsed_query)
+    }
+
+    /// Number of cached queries.
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use tantivy::query::{AllQuery, Query};
+
+    use crate::query_cache::QueryCache;
+    use crate::search_engine::{Mode, Scope};
+
+    #[test]
+    fn test_query_cache() {
+        let cache = QueryCache::new(2);
+        let parses = Cell::new(0);
+        let parse = || -> Result<Box<dyn Query>, ()> {
+            parses.set(parses.get() + 1);
+            Ok(Box::new(AllQuery))
+        };
+        let get = |cache: &QueryCache, mode, query| {
+            cache.get_or_parse(mode, Scope::All, query, parse).unwrap();
+        };
+        get(&cache, Mode::Ngram, "φρα");
+        get(&cache, Mode::Ngram, "φρα");
+        assert_eq!(parses.get(), 1);
+        get(&cache, Mode::Full, "φρα");
+        assert_eq!(parses.get(), 2);
+
+        // "φρα" in ngram mode was used last, so the full one is evicted.
+        get(&cache, Mode::Ngram, "φρα");
+        get(&cache, Mode::Ngram, "φραγ");
+        assert_eq!(cache.len(), 2);
+        get(&cache, Mode::Ngram, "φρα");
+        assert_eq!(parses.get(), 3);
+        get(&cache, Mode::Full, "φρα");
+        assert_eq!(parses.get(), 4);
+
+        // Failures are not cached.
+        assert!(cache
+            .get_or_parse(Mode::Full, Scope::All, "(", || Err(()))
+            .is_err());
+        assert_eq!(cache.len(), 2);
+
+        // The same query in another scope is parsed again.
+        cache
+            .get_or_parse(Mode::Full, Scope::Name, "φρα", parse)
+            .unwrap();
+        assert_eq!(parses.get(), 5);
+
+        let cache = QueryCache::new(0);
+        get(&cache, Mode::Full, "φρα");
+        assert!(cache.is_empty());
+    }
+}
@@ -0,0 +1,320 @@
+//! The ranking pipeline. Every matching document is scored by multiplying the factors of the
+//! rank signals, which are computed from its text relevance and its fast fields, and the
+//! retrieved hits are then reordered by the signals that need their stored fields.
+//!
+//! Ranking experiments add a signal here and a weight to `Boosts`, and leave the search engine
+//! alone.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use tantivy::Score;
+
+use crate::config::Boosts;
+use crate::greek_lower_caser::to_greek_lowercase;
+use crate::transliteration::strip_disambiguation;
+use crate::utils::normalize_query;
+
+/// Age in seconds at which the recency boost of a song halves.
+const RECENCY_HALF_LIFE: f32 = 30.0 * 24.0 * 60.0 * 60.0;
+
+/// The type of a document, stored in the kind fast field.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Song = 0,
+    Artist = 1,
+    Scale = 2,
+}
+
+impl From<u64> for Kind {
+    fn from(kind: u64) -> Kind {
+        match kind {
+            1 => Kind::Artist,
+            2 => Kind::Scale,
+            _ => Kind::Song,
+        }
+    }
+}
+
+impl Kind {
+    /// The type of the document at the url, e.g. "/artists/markos_vamvakaris/".
+    pub fn from_url(url: &str) -> Option<Kind> {
+        match url.trim_start_matches('/').split('/').next() {
+            Some("songs") => Some(Kind::Song),
+            Some("artists") => Some(Kind::Artist),
+            Some("scales") => Some(Kind::Scale),
+            _ => None,
+        }
+    }
+
+    /// Label of the type, under which the results are grouped in the autocomplete dropdown.
+    pub fn category(self) -> &'static str {
+        match self {
+            Kind::Song => "Τραγούδι",
+            Kind::Artist => "Καλλιτέχνης",
+            Kind::Scale => "Δρόμος",
+        }
+    }
+}
+
+/// What the signals know about a matching document.
+pub struct Features {
+    /// BM25 score of the document for the query.
+    pub relevance: Score,
+    pub kind: Kind,
+    /// Seconds since the song file was modified, if known.
+    pub age: Option<u64>,
+    /// Number of views of the song.
+    pub views: u64,
+}
+
+/// Stored fields of a retrieved hit, by name.
+pub trait StoredFields {
+    fn get(&self, name: &str) -> Option<&str>;
+}
+
+impl StoredFields for HashMap<String, String> {
+    fn get(&self, name: &str) -> Option<&str> {
+        HashMap::get(self, name).map(String::as_str)
+    }
+}
+
+pub trait RankSignal: Send + Sync {
+    /// Factor of the score of a matching document. Computed for every match, so it only gets
+    /// the features that are cheap to read.
+    fn factor(&self, _features: &Features) -> Score {
+        1.0
+    }
+
+    /// Factor of a retrieved hit, given the query text and the stored fields of the hit.
+    fn hit_factor(&self, _query: &str, _fields: &dyn StoredFields) -> Score {
+        1.0
+    }
+}
+
+/// Text relevance, raised to the weight. 0 ranks by the other signals alone.
+pub struct Relevance {
+    pub weight: f32,
+}
+
+impl RankSignal for Relevance {
+    fn factor(&self, features: &Features) -> Score {
+        features.relevance.powf(self.weight)
+    }
+}
+
+/// Multiplier per document type.
+pub struct KindBoost {
+    pub song: f32,
+    pub artist: f32,
+    pub scale: f32,
+}
+
+impl RankSignal for KindBoost {
+    fn factor(&self, features: &Features) -> Score {
+        match features.kind {
+            Kind::Song => self.song,
+            Kind::Artist => self.artist,
+            Kind::Scale => self.scale,
+        }
+    }
+}
+
+/// The weight times the logarithm of the view count, as a fraction of the score.
+pub struct Popularity {
+    pub weight: f32,
+}
+
+impl RankSignal for Popularity {
+    fn factor(&self, features: &Features) -> Score {
+        1.0 + self.weight * (features.views as f32).ln_1p()
+    }
+}
+
+/// The weight as a fraction of the score for songs modified just now, halving every month
+/// after that.
+pub struct Recency {
+    pub weight: f32,
+}
+
+impl RankSignal for Recency {
+    fn factor(&self, features: &Features) -> Score {
+        match features.age {
+            Some(age) => 1.0 + self.weight * 0.5f32.powf(age as f32 / RECENCY_HALF_LIFE),
+            None => 1.0,
+        }
+    }
+}
+
+/// Hits named exactly like the query, so that a typed title is never ranked below documents
+/// that merely contain its words. Names are compared lowercased and without accents, and
+/// greeklish queries are compared with slugs, without the suffix of the slugs that collided. Like
+/// every stored field signal, it only reorders the retrieved hits.
+pub struct ExactMatch {
+    pub weight: f32,
+}
+
+impl RankSignal for ExactMatch {
+    fn hit_factor(&self, query: &str, fields: &dyn StoredFields) -> Score {
+        let name = normalize_query(query, true)
+            .split_whitespace()
+            .collect::<Vec<&str>>()
+            .join(" ");
+        if name.is_empty() {
+            return 1.0;
+        }
+        let exact_name = fields
+            .get("name")
+            .is_some_and(|hit_name| to_greek_lowercase(hit_name) == name);
+        // Slugs that collided have a suffix that the query doesn't.
+        let exact_slug = fields
+            .get("slug")
+            .is_some_and(|slug| strip_disambiguation(slug) == name.replace(' ', "_"));
+        if exact_name || exact_slug {
+            1.0 + self.weight
+        } else {
+            1.0
+        }
+    }
+}
+
+/// Which of the rankings of an experiment serves a search.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Variant {
+    /// The configured ranking.
+    #[default]
+    A,
+    /// The ranking under test.
+    B,
+}
+
+impl FromStr for Variant {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Variant, String> {
+        match s {
+            "a" => Ok(Variant::A),
+            "b" => Ok(Variant::B),
+            _ => Err(format!("Unknown variant {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Variant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Variant::A => write!(f, "a"),
+            Variant::B => write!(f, "b"),
+        }
+    }
+}
+
+/// The signals that make up the score.
+pub struct Ranking {
+    signals: Vec<Box<dyn RankSignal>>,
+}
+
+impl Ranking {
+    pub fn new(signals: Vec<Box<dyn RankSignal>>) -> Ranking {
+        Ranking { signals }
+    }
+
+    /// The signals of the search engine, weighted by the boosts.
+    pub fn from_boosts(boosts: &Boosts) -> Ranking {
+        Ranking::new(vec![
+            Box::new(Relevance {
+                weight: boosts.relevance,
+            }),
+            Box::new(KindBoost {
+                song: boosts.song,
+                artist: boosts.artist,
+                scale: boosts.scale,
+            }),
+            Box::new(Popularity {
+                weight: boosts.popularity,
+            }),
+            Box::new(Recency {
+                weight: boosts.recency,
+            }),
+            Box::new(ExactMatch {
+                weight: boosts.exact_match,
+            }),
+        ])
+    }
+
+    /// Score of a matching document.
+    pub fn score(&self, features: &Features) -> Score {
+        self.signals
+            .iter()
+            .map(|signal| signal.factor(features))
+            .product()
+    }
+
+    /// Factor of a retrieved hit. Hits are reordered by it, keeping the order of hits with
+    /// equal factors.
+    pub fn hit_factor(&self, query: &str, fields: &dyn StoredFields) -> Score {
+        self.signals
+            .iter()
+            .map(|signal| signal.hit_factor(query, fields))
+            .product()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::config::Boosts;
+    use crate::ranking::{Features, Kind, Ranking};
+
+    #[test]
+    fn test_kind() {
+        assert_eq!(Kind::from_url("/songs/fragkosyriani/"), Some(Kind::Song));
+        let artist = Kind::from_url("/artists/markos_vamvakaris/");
+        assert_eq!(artist.map(Kind::category), Some("Καλλιτέχνης"));
+        assert_eq!(
+            Kind::from_url("/scales/ousak/").map(Kind::category),
+            Some("Δρόμος")
+        );
+        assert_eq!(Kind::from_url("/admin/"), None);
+    }
+
+    #[test]
+    fn test_ranking() {
+        let features = |kind, age, views| Features {
+            relevance: 2.0,
+            kind,
+            age,
+            views,
+        };
+        let ranking = Ranking::from_boosts(&Boosts::default());
+        assert_eq!(ranking.score(&features(Kind::Song, Some(0), 100)), 2.0);
+
+        let boosts = Boosts {
+            artist: 0.5,
+            recency: 1.0,
+            popularity: 1.0,
+            ..Boosts::default()
+        };
+        let ranking = Ranking::from_boosts(&boosts);
+        assert_eq!(ranking.score(&features(Kind::Artist, None, 0)), 1.0);
+        assert_eq!(ranking.score(&features(Kind::Song, Some(0), 0)), 4.0);
+        let popular = ranking.score(&features(Kind::Song, None, 100));
+        assert!(popular > 2.0 * 5.0 && popular < 2.0 * 6.0);
+
+        let fields = HashMap::from([
+            (String::from("name"), String::from("Φραγκοσυριανή")),
+            (String::from("slug"), String::from("fragkosyriani")),
+        ]);
+        assert_eq!(ranking.hit_factor("φραγκοσυριανη", &fields), 2.0);
+        assert_eq!(ranking.hit_factor("fragkosyriani", &fields), 2.0);
+        assert_eq!(ranking.hit_factor("φραγκο", &fields), 1.0);
+
+        let fields = HashMap::from([
+            (String::from("name"), String::from("Φραγκοσυριανή.")),
+            (String::from("slug"), String::from("fragkosyriani_0a1b2c3d")),
+        ]);
+        assert_eq!(ranking.hit_factor("fragkosyriani", &fields), 2.0);
+    }
+}
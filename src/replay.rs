@@ -0,0 +1,127 @@
+//! Replays a log of queries against the engine, for load testing and for comparing analyzer
+//! changes on real traffic. Each line of the log is a query, optionally preceded by the parser
+//! and a tab, e.g. `ngram\tφραγκο`. Other queries are searched with the full parser.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::error::{Error, Result};
+use crate::search_engine::{Mode, SearchEngine, Sort};
+
+/// Latencies and results of the replayed queries.
+pub struct Report {
+    /// Latencies of the searches, from fastest to slowest.
+    latencies: Vec<Duration>,
+    /// Number of searches that failed, e.g. queries that don't parse.
+    pub errors: usize,
+    /// Queries without results and how many times each was searched, most frequent first.
+    pub zero_results: Vec<(String, usize)>,
+}
+
+impl Report {
+    /// Number of searches, including the failed ones.
+    pub fn queries(&self) -> usize {
+        self.latencies.len()
+    }
+
+    /// Latency under which the given percentage of the searches completed.
+    pub fn percentile(&self, percent: f64) -> Duration {
+        if self.latencies.is_empty() {
+            return Duration::ZERO;
+        }
+        let rank = (percent / 100.0 * self.latencies.len() as f64).ceil() as usize;
+        self.latencies[rank.clamp(1, self.latencies.len()) - 1]
+    }
+
+    /// Fraction of the searches that found nothing.
+    pub fn zero_result_rate(&self) -> f64 {
+        if self.latencies.is_empty() {
+            return 0.0;
+        }
+        let count: usize = self.zero_results.iter().map(|(_, count)| count).sum();
+        count as f64 / self.latencies.len() as f64
+    }
+}
+
+/// Search for every query of the log in turn.
+pub fn run(engine: &SearchEngine, path: &Path) -> Result<Report> {
+    let mut latencies = Vec::new();
+    let mut errors = 0;
+    let mut zero_results: HashMap<String, usize> = HashMap::new();
+    for (number, line) in std::fs::read_to_string(path)?.lines().enumerate() {
+        let (mode, query) = match line.split_once('\t') {
+            Some((mode, query)) => {
+                let mode: Mode = mode.parse().map_err(|e| {
+                    Error::Parse(format!("{}:{}: {}", path.display(), number + 1, e))
+                })?;
+                (mode, query.trim())
+            }
+            None => (Mode::Full, line.trim()),
+        };
+        if query.is_empty() {
+            continue;
+        }
+        let start = Instant::now();
+        let hits = engine.hits(query, mode, Sort::Relevance);
+        latencies.push(start.elapsed());
+        match hits {
+            Ok(hits) if hits.is_empty() => *zero_results.entry(query.to_string()).or_default() += 1,
+            Ok(_) => {}
+            Err(_) => errors += 1,
+        }
+    }
+    latencies.sort();
+    let mut zero_results: Vec<(String, usize)> = zero_results.into_iter().collect();
+    zero_results.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    Ok(Report {
+        latencies,
+        errors,
+        zero_results,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::time::Duration;
+
+    use tempfile::NamedTempFile;
+
+    use crate::replay::{run, Report};
+    use crate::search_engine::SearchEngine;
+
+    #[test]
+    fn test_run() {
+        let fixtures = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/songs");
+        let engine = SearchEngine::builder(fixtures).build().unwrap();
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all("φραγκοσυριανη\nngram\tφραγκ\n\nxyzzy\nxyzzy\nqwerty\n".as_bytes())
+            .unwrap();
+        let report = run(&engine, file.path()).unwrap();
+        assert_eq!(report.queries(), 5);
+        assert_eq!(report.errors, 0);
+        assert_eq!(
+            report.zero_results,
+            vec![(String::from("xyzzy"), 2), (String::from("qwerty"), 1)]
+        );
+        assert_eq!(report.zero_result_rate(), 0.6);
+        assert!(report.percentile(50.0) <= report.percentile(99.0));
+
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all("fuzzy\tφραγκ\n".as_bytes()).unwrap();
+        assert!(run(&engine, file.path()).is_err());
+    }
+
+    #[test]
+    fn test_percentile() {
+        let report = Report {
+            latencies: (1..=10).map(Duration::from_millis).collect(),
+            errors: 0,
+            zero_results: vec![],
+        };
+        assert_eq!(report.percentile(50.0), Duration::from_millis(5));
+        assert_eq!(report.percentile(95.0), Duration::from_millis(10));
+        assert_eq!(report.percentile(0.0), Duration::from_millis(1));
+    }
+}
@@ -0,0 +1,164 @@
+//! Song files read from an S3-compatible bucket. Credentials are taken from the usual AWS
+//! environment variables, e.g. `AWS_ACCESS_KEY_ID`, or the AWS credentials file.
+
+use std::io;
+
+use s3::bucket::Bucket;
+use s3::creds::Credentials;
+use s3::error::S3Error;
+use s3::region::Region;
+
+use crate::config::S3Location;
+use crate::error::{Error, Result};
+
+impl From<S3Error> for Error {
+    fn from(e: S3Error) -> Error {
+        Error::Io(io::Error::other(format!("S3: {}", e)))
+    }
+}
+
+fn open_bucket(location: &S3Location) -> Result<Bucket> {
+    let credentials = Credentials::default().map_err(S3Error::from)?;
+    // Stores other than AWS mostly don't support virtual-hosted buckets.
+    match &location.endpoint {
+        Some(endpoint) => {
+            let region = Region::Custom {
+                region: location.region.clone(),
+                endpoint: endpoint.clone(),
+            };
+            Ok(Bucket::new(&location.bucket, region, credentials)?.with_path_style())
+        }
+        None => {
+            let region = location.region.parse().map_err(S3Error::from)?;
+            Ok(Bucket::new(&location.bucket, region, credentials)?)
+        }
+    }
+}
+
+/// Return the key and the contents of every object under the prefix, sorted by key.
+pub fn read_objects(location: &S3Location) -> Result<Vec<(String, String)>> {
+    let bucket = open_bucket(location)?;
+    let mut keys = Vec::new();
+    for page in bucket.list(location.prefix.clone(), None)? {
+        for object in page.contents {
+            // Skip the placeholder objects of folders.
+            if !object.key.ends_with('/') {
+                keys.push(object.key);
+            }
+        }
+    }
+    keys.sort();
+
+    let mut objects = Vec::with_capacity(keys.len());
+    for key in keys {
+        let response = bucket.get_object(&key)?;
+        if response.status_code() != 200 {
+            let message = format!("S3: Got HTTP {} for {}", response.status_code(), key);
+            return Err(Error::Io(io::Error::other(message)));
+        }
+        let contents = response
+            .to_string()
+            .map_err(|e| Error::Parse(format!("{}: {}", key, e)))?;
+        objects.push((key, contents));
+    }
+    Ok(objects)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    use crate::config::S3Location;
+    use crate::s3::read_objects;
+
+    /// Serve the objects as a bucket named songs, with a connection per request, and return its
+    /// endpoint.
+    fn fake_bucket(objects: &'static [(&'static str, &'static str)]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request_line = String::new();
+                let mut reader = BufReader::new(&stream);
+                reader.read_line(&mut request_line).unwrap();
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                }
+                let path = request_line.split(' ').nth(1).unwrap();
+                let body = if path.contains("list-type=2") {
+                    let contents: String = objects
+                        .iter()
+                        .map(|(key, _)| {
+                            format!(
+                                "<Contents><Key>{}</Key><LastModified>2021-01-01T00:00:00.000Z\
+                                 </LastModified><Size>0</Size></Contents>",
+                                key
+                            )
+                        })
+                        .collect();
+                    format!(
+                        "<?xml version=\"1.0\" encoding=\"UTF-8\"?><ListBucketResult>\
+                         <Name>songs</Name><Prefix>songs/</Prefix><KeyCount>{}</KeyCount>\
+                         <MaxKeys>1000</MaxKeys><IsTruncated>false</IsTruncated>{}\
+                         </ListBucketResult>",
+                        objects.len(),
+                        contents
+                    )
+                } else {
+                    let key = path.trim_start_matches("/songs/");
+                    objects
+                        .iter()
+                        .find(|(k, _)| *k == key)
+                        .unwrap()
+                        .1
+                        .to_string()
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+        });
+        endpoint
+    }
+
+    #[test]
+    fn test_read_objects() {
+        std::env::set_var("AWS_ACCESS_KEY_ID", "test");
+        std::env::set_var("AWS_SECRET_ACCESS_KEY", "test");
+        let endpoint = fake_bucket(&[
+            ("songs/synnefiasmeni", "Συννεφιασμένη Κυριακή"),
+            ("songs/", ""),
+            ("songs/frangosyriani", "Φραγκοσυριανή"),
+        ]);
+        let location = S3Location {
+            bucket: String::from("songs"),
+            prefix: String::from("songs/"),
+            region: String::from("us-east-1"),
+            endpoint: Some(endpoint),
+        };
+        let objects = read_objects(&location).unwrap();
+        assert_eq!(
+            objects,
+            vec![
+                (
+                    String::from("songs/frangosyriani"),
+                    String::from("Φραγκοσυριανή")
+                ),
+                (
+                    String::from("songs/synnefiasmeni"),
+                    String::from("Συννεφιασμένη Κυριακή")
+                ),
+            ]
+        );
+    }
+}
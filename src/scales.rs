@@ -0,0 +1,130 @@
+//! The scales that have a page on the site, and the other spellings they are searched by, in
+//! Greek, greeklish and Turkish. More spellings are read from the file of
+//! `BUZUKI_SCALE_ALIASES`.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::error::{Error, Result};
+
+/// Names of the scales, in the order they are indexed.
+pub const SCALES: &[&str] = &[
+    "Ματζόρε",
+    "Ραστ",
+    "Φυσικό Μινόρε",
+    "Αρμονικό Μινόρε",
+    "Χιτζάζ",
+    "Χιτζαζκάρ",
+    "Πειραιώτικο",
+    "Ουσάκ",
+    "Καρσιγάρ",
+    "Σαμπάχ",
+    "Νικρίζ",
+    "Νιαβέντ",
+    "Χουζάμ",
+    "Σεγκιάχ",
+    "Σουζινάκ",
+    "Κιουρντί",
+];
+
+/// Common spellings of the scales, besides the name and its slug.
+const ALIASES: &[(&str, &[&str])] = &[
+    ("Ματζόρε", &["Ματζώρε", "majore", "major"]),
+    ("Ραστ", &["rast"]),
+    ("Φυσικό Μινόρε", &["minore", "natural minor"]),
+    ("Αρμονικό Μινόρε", &["harmonic minor"]),
+    ("Χιτζάζ", &["Χετζάζ", "hijaz", "hicaz", "hitzaz", "chitzaz"]),
+    ("Χιτζαζκάρ", &["hijazkar", "hicazkar", "hitzazkar"]),
+    ("Πειραιώτικο", &["pireotiko"]),
+    ("Ουσάκ", &["Ουσσάκ", "usak", "uşşak", "ousak"]),
+    ("Καρσιγάρ", &["karcigar", "karsigar", "kartsigar"]),
+    ("Σαμπάχ", &["Σαμπά", "saba", "sabah"]),
+    ("Νικρίζ", &["nikriz"]),
+    ("Νιαβέντ", &["Νιχαβέντ", "nihavend", "niavent"]),
+    ("Χουζάμ", &["Χουσάμ", "huzam", "houzam"]),
+    ("Σεγκιάχ", &["Σεγιάχ", "segah", "segkiah"]),
+    ("Σουζινάκ", &["suzinak", "souzinak"]),
+    ("Κιουρντί", &["Κουρντί", "kurdi", "kiourdi"]),
+];
+
+/// Other spellings of each scale, keyed by its name.
+pub type ScaleAliases = HashMap<String, Vec<String>>;
+
+/// The built in spellings of the scales.
+pub fn default_aliases() -> ScaleAliases {
+    ALIASES
+        .iter()
+        .map(|&(scale, aliases)| {
+            let aliases = aliases.iter().map(|&alias| String::from(alias)).collect();
+            (String::from(scale), aliases)
+        })
+        .collect()
+}
+
+/// Read the built in spellings together with those of a file of `scale = alias, alias` lines,
+/// e.g. `Χιτζάζ = hijaz, hicaz`. A scale listed in the file keeps only the spellings of the
+/// file. Empty lines and lines starting with # are skipped.
+pub fn read_aliases(path: &Path) -> Result<ScaleAliases> {
+    let mut scale_aliases = default_aliases();
+    for (number, line) in std::fs::read_to_string(path)?.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (scale, aliases) = match line.split_once('=') {
+            Some((scale, aliases)) => (scale.trim(), aliases),
+            None => {
+                let message = format!(
+                    "{}:{}: Expected scale = aliases",
+                    path.display(),
+                    number + 1
+                );
+                return Err(Error::Parse(message));
+            }
+        };
+        if !SCALES.contains(&scale) {
+            let message = format!("{}:{}: Unknown scale {}", path.display(), number + 1, scale);
+            return Err(Error::Parse(message));
+        }
+        let aliases = aliases
+            .split(',')
+            .map(str::trim)
+            .filter(|alias| !alias.is_empty())
+            .map(String::from)
+            .collect();
+        scale_aliases.insert(scale.to_string(), aliases);
+    }
+    Ok(scale_aliases)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use tempfile::NamedTempFile;
+
+    use crate::scales::{default_aliases, read_aliases, SCALES};
+
+    #[test]
+    fn test_read_aliases() {
+        let defaults = default_aliases();
+        assert!(defaults
+            .keys()
+            .all(|scale| SCALES.contains(&scale.as_str())));
+        assert!(defaults["Χιτζάζ"].contains(&String::from("hijaz")));
+
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all("# Spellings\n\nΧιτζάζ = hejaz, , hidjaz\n".as_bytes())
+            .unwrap();
+        let aliases = read_aliases(file.path()).unwrap();
+        assert_eq!(aliases["Χιτζάζ"], vec!["hejaz", "hidjaz"]);
+        assert_eq!(aliases["Ουσάκ"], defaults["Ουσάκ"]);
+
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all("Χιτζαζ = hijaz\n".as_bytes()).unwrap();
+        assert!(read_aliases(file.path()).is_err());
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all("hijaz\n".as_bytes()).unwrap();
+        assert!(read_aliases(file.path()).is_err());
+    }
+}
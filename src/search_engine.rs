@@ -1,234 +1,4774 @@
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+#[cfg(feature = "mmap")]
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 
-use tantivy::collector::TopDocs;
+use log::{info, warn};
+use serde::ser::{SerializeMap, Serializer};
+use serde::Serialize;
+use serde_json::json;
+use tantivy::collector::{Count, DocSetCollector, TopDocs};
 use tantivy::doc;
-use tantivy::query::QueryParser;
-use tantivy::schema::{IndexRecordOption, Schema, TextFieldIndexing, TextOptions, STORED};
+use tantivy::postings::Postings;
+use tantivy::query::{
+    AllQuery, BooleanQuery, BoostQuery, EmptyQuery, Occur, PhraseQuery, Query, QueryParser, Scorer,
+    TermQuery,
+};
+use tantivy::schema::{
+    Field, FieldType, IndexRecordOption, Schema, SchemaBuilder, Term, TextFieldIndexing,
+    TextOptions, FAST, INDEXED, STORED,
+};
 use tantivy::tokenizer::{
-    Language, LowerCaser, RemoveLongFilter, SimpleTokenizer, Stemmer, TextAnalyzer,
+    AsciiFoldingFilter, Language, LowerCaser, RemoveLongFilter, SimpleTokenizer, Stemmer,
+    TextAnalyzer, TokenizerManager,
 };
-use tantivy::Index;
-use tantivy::IndexReader;
 use tantivy::ReloadPolicy;
+use tantivy::TantivyError;
+use tantivy::TERMINATED;
+use tantivy::{
+    DocAddress, DocId, DocSet, Document, Index, IndexWriter, Score, Searcher, SegmentReader,
+};
+use tantivy::{IndexReader, LeasedItem};
+
+#[cfg(feature = "mmap")]
+use tempfile::{tempdir, TempDir};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::cancellation::{CancelFlag, Cancellable};
+use crate::config::{
+    Boosts, Config, Distribution, IndexingLimits, NumberPolicy, S3Location, WordLimits,
+};
+use crate::edge_ngram_filter::EdgeNgramFilter;
+use crate::error::{Error, Result};
+use crate::greek_lower_caser::{to_greek_lowercase, GreekLowerCaser};
+use crate::homoglyph_filter::HomoglyphFilter;
+use crate::number_filter::NumberFilter;
+use crate::phonetic_filter::PhoneticFilter;
+use crate::popularity::read_views;
+use crate::query_cache::QueryCache;
+use crate::ranking::{Features, Kind, Ranking, StoredFields, Variant};
+use crate::scales::{default_aliases, read_aliases, SCALES};
+use crate::shingle_filter::ShingleFilter;
+use crate::snapshot::{self, SnapshotDir};
+use crate::song::{ForeignLanguage, LyricsFilter, LyricsStats, Song};
+use crate::tokenizer::NgramTokenizer;
+use crate::transliteration::{Slugs, Transliteration};
+use crate::utils::{
+    detect_script, normalize_query, strip_query_syntax, swap_keyboard_layout, Script,
+};
+
+/// Version of the fields and analyzers, stored as the payload of every commit. Bump it whenever
+/// either changes, so that indexes built by older versions are detected rather than searched
+/// with mismatched fields.
+pub const SCHEMA_VERSION: u32 = 14;
+
+/// Log target of the searches that find nothing, e.g. for `RUST_LOG=buzuki_search::zero_results`.
+/// They point at songs that are missing or at spellings that the analyzers don't handle.
+pub const ZERO_RESULTS_TARGET: &str = "buzuki_search::zero_results";
+
+/// Length in characters of the longest indexed name prefix.
+const MAX_PREFIX_LEN: usize = 40;
+
+/// Number of parsed queries kept for repeated searches.
+const QUERY_CACHE_SIZE: usize = 1000;
+
+/// Capacity in bytes above which the response buffer of a thread is released after use, so
+/// that a single huge response doesn't stay allocated.
+const MAX_RESPONSE_BUFFER: usize = 4 << 20;
+
+/// Number of candidates per returned hit that are collected when the songs of each artist are
+/// limited, since the songs over the limit of their artist are dropped. Fewer hits than the limit
+/// are returned if the most relevant candidates belong to a few artists.
+const PER_ARTIST_CANDIDATES: usize = 10;
+
+thread_local! {
+    // Reused across the searches of each thread, so that the JSON of the results is written
+    // into an allocation of the right size rather than one that grows as it's written.
+    static RESPONSE_BUFFER: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Serialize the value into the response buffer of the thread and return a copy of it.
+fn to_json(value: &impl Serialize) -> Result<String> {
+    RESPONSE_BUFFER.with(|buffer| {
+        let mut buffer = buffer.borrow_mut();
+        buffer.clear();
+        let result = serde_json::to_writer(&mut *buffer, value);
+        let json = result.map(|_| String::from_utf8_lossy(&buffer).into_owned());
+        if buffer.capacity() > MAX_RESPONSE_BUFFER {
+            *buffer = Vec::new();
+        }
+        Ok(json?)
+    })
+}
+
+fn get_options(tokenizer: &str) -> TextOptions {
+    get_options_with(tokenizer, IndexRecordOption::WithFreqsAndPositions)
+}
+
+fn get_options_with(tokenizer: &str, record_option: IndexRecordOption) -> TextOptions {
+    let text_field_indexing = TextFieldIndexing::default()
+        .set_tokenizer(tokenizer)
+        .set_index_option(record_option);
+
+    TextOptions::default().set_indexing_options(text_field_indexing)
+}
+
+/// Commit the pending changes, marking the index with the schema version.
+fn commit(index_writer: &mut IndexWriter) -> Result<()> {
+    let mut prepared_commit = index_writer.prepare_commit()?;
+    prepared_commit.set_payload(&SCHEMA_VERSION.to_string());
+    prepared_commit.commit()?;
+    Ok(())
+}
+
+/// Open the serialized index in RAM, refusing it if it was built with another schema.
+fn open_serialized(serialized: &[u8], schema: &Schema) -> Result<Index> {
+    let index = Index::open(snapshot::deserialize(serialized)?)?;
+    if let Some(mismatch) = schema_mismatch(&index, schema)? {
+        let message = format!("Refusing the serialized index: {}", mismatch);
+        return Err(Error::Parse(message));
+    }
+    Ok(index)
+}
+
+/// Schema version of the last commit of the index, if it has one.
+fn schema_version(index: &Index) -> Result<Option<u32>> {
+    let payload = index.load_metas()?.payload;
+    Ok(payload.and_then(|payload| payload.parse().ok()))
+}
+
+/// Describe how the index differs from the compiled schema, if it has been built by another
+/// version.
+fn schema_mismatch(index: &Index, schema: &Schema) -> Result<Option<String>> {
+    Ok(match schema_version(index)? {
+        Some(SCHEMA_VERSION) if index.schema() == *schema => None,
+        Some(SCHEMA_VERSION) => Some(format!(
+            "its fields differ from those of schema version {}",
+            SCHEMA_VERSION
+        )),
+        Some(version) => Some(format!(
+            "it has schema version {} instead of {}",
+            version, SCHEMA_VERSION
+        )),
+        None => Some(format!(
+            "it has no schema version instead of {}",
+            SCHEMA_VERSION
+        )),
+    })
+}
+
+/// Open the index of the directory, or create it if there is none. An index of another schema
+/// is removed first, since the songs are indexed from scratch anyway.
+#[cfg(feature = "mmap")]
+fn open_or_create_in_dir(index_dir: &Path, schema: Schema) -> Result<Index> {
+    if index_dir.join("meta.json").exists() {
+        let index = Index::open_in_dir(index_dir)?;
+        match schema_mismatch(&index, &schema)? {
+            Some(mismatch) => warn!(
+                "Rebuilding the index of {}: {}",
+                index_dir.display(),
+                mismatch
+            ),
+            None => return Ok(index),
+        }
+        drop(index);
+        for entry in fs::read_dir(index_dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                fs::remove_dir_all(path)?;
+            } else {
+                fs::remove_file(path)?;
+            }
+        }
+    }
+    Ok(Index::create_in_dir(index_dir, schema)?)
+}
+
+/// Make sure that no field name has been registered more than once. Tantivy silently keeps
+/// both entries, but name lookups only resolve to the last one.
+fn check_schema(schema: &Schema) -> Result<()> {
+    let mut names = HashSet::new();
+    for (_field, entry) in schema.fields() {
+        if !names.insert(entry.name()) {
+            return Err(TantivyError::SchemaError(format!(
+                "Field \"{}\" is registered more than once",
+                entry.name()
+            ))
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// Render a parsed query in a compact, Lucene-like syntax with field names and term texts, e.g.
+/// `(+(name:"ελα" slug:"ela") +body:"τι")`.
+fn describe_query(query: &dyn Query, schema: &Schema) -> String {
+    let describe_term = |term: &Term| {
+        let field_name = schema.get_field_name(term.field());
+        let text = std::str::from_utf8(term.value_bytes()).unwrap_or("?");
+        (field_name, text.to_string())
+    };
+
+    if let Some(boolean_query) = query.downcast_ref::<BooleanQuery>() {
+        let clauses: Vec<String> = boolean_query
+            .clauses()
+            .iter()
+            .map(|(occur, subquery)| {
+                let prefix = match occur {
+                    Occur::Must => "+",
+                    Occur::MustNot => "-",
+                    Occur::Should => "",
+                };
+                format!("{}{}", prefix, describe_query(subquery.as_ref(), schema))
+            })
+            .collect();
+        format!("({})", clauses.join(" "))
+    } else if let Some(term_query) = query.downcast_ref::<TermQuery>() {
+        let (field_name, text) = describe_term(term_query.term());
+        format!("{}:\"{}\"", field_name, text)
+    } else if let Some(phrase_query) = query.downcast_ref::<PhraseQuery>() {
+        let terms = phrase_query.phrase_terms();
+        let field_name = schema.get_field_name(phrase_query.field());
+        let texts: Vec<String> = terms.iter().map(|term| describe_term(term).1).collect();
+        format!("{}:\"{}\"", field_name, texts.join(" "))
+    } else {
+        format!("{:?}", query)
+    }
+}
+
+/// Prefixes of the analyzed fields that only add to the score, through the term queries of word
+/// pairs and name prefixes that are built by hand. They are never parsed into phrase queries,
+/// so their positions, which take most of the space of their many terms, are not indexed.
+const UNPOSITIONED_PREFIXES: &[&str] = &["shingle_", "near_", "prefix_"];
+
+/// The sibling fields that hold the same source text analyzed with different tokenizers, keyed
+/// by the prefix of their name.
+#[derive(Clone)]
+struct AnalyzedFields {
+    fields: Vec<(&'static str, Field)>,
+}
+
+impl AnalyzedFields {
+    fn new(
+        schema_builder: &mut SchemaBuilder,
+        name: &str,
+        analyzers: &[(&'static str, &str)],
+        stored: bool,
+    ) -> AnalyzedFields {
+        let fields = analyzers
+            .iter()
+            .enumerate()
+            .map(|(i, &(prefix, tokenizer))| {
+                let record_option = if UNPOSITIONED_PREFIXES.contains(&prefix) {
+                    IndexRecordOption::WithFreqs
+                } else {
+                    IndexRecordOption::WithFreqsAndPositions
+                };
+                let options = get_options_with(tokenizer, record_option);
+                let options = if stored && i == 0 {
+                    options | STORED
+                } else {
+                    options
+                };
+                let field = schema_builder.add_text_field(&format!("{}{}", prefix, name), options);
+                (prefix, field)
+            })
+            .collect();
+        AnalyzedFields { fields }
+    }
+
+    fn get(&self, prefix: &str) -> Field {
+        self.fields
+            .iter()
+            .find(|&&(field_prefix, _)| field_prefix == prefix)
+            .map(|&(_, field)| field)
+            .unwrap()
+    }
+
+    fn simple(&self) -> Field {
+        self.get("")
+    }
+
+    fn ngram(&self) -> Field {
+        self.get("ngram_")
+    }
+
+    fn stemmed(&self) -> Field {
+        self.get("stemmed_")
+    }
+
+    fn exact(&self) -> Field {
+        self.get("exact_")
+    }
+
+    /// The field stemmed in the language.
+    fn stemmed_in(&self, language: ForeignLanguage) -> Field {
+        match language {
+            ForeignLanguage::Turkish => self.get("stemmed_tr_"),
+            ForeignLanguage::English => self.get("stemmed_en_"),
+        }
+    }
+
+    fn phonetic(&self) -> Field {
+        self.get("phonetic_")
+    }
+
+    fn shingle(&self) -> Field {
+        self.get("shingle_")
+    }
+
+    fn near(&self) -> Field {
+        self.get("near_")
+    }
+
+    fn prefix(&self) -> Field {
+        self.get("prefix_")
+    }
+
+    /// Add the text to every field of the group.
+    fn add_text(&self, document: &mut Document, text: &str) {
+        for &(_, field) in &self.fields {
+            document.add_text(field, text);
+        }
+    }
+
+    /// Add the text to every field of the group except the stemmed one.
+    fn add_text_unstemmed(&self, document: &mut Document, text: &str) {
+        for &(prefix, field) in &self.fields {
+            if prefix != "stemmed_" {
+                document.add_text(field, text);
+            }
+        }
+    }
+}
+
+/// Query parsers over the same kind of fields, for queries in each script.
+#[derive(Clone)]
+struct QueryParsers {
+    all: QueryParser,
+    greek: QueryParser,
+    latin: QueryParser,
+}
+
+impl QueryParsers {
+    fn new(
+        index: &Index,
+        all_fields: Vec<Field>,
+        greek_fields: Vec<Field>,
+        latin_fields: Vec<Field>,
+    ) -> QueryParsers {
+        let parser = |fields| {
+            let mut query_parser = QueryParser::for_index(index, fields);
+            query_parser.set_conjunction_by_default();
+            query_parser
+        };
+        QueryParsers {
+            all: parser(all_fields),
+            greek: parser(greek_fields),
+            latin: parser(latin_fields),
+        }
+    }
+
+    fn get(&self, script: Script) -> &QueryParser {
+        match script {
+            Script::Greek => &self.greek,
+            Script::Latin => &self.latin,
+            Script::Mixed => &self.all,
+        }
+    }
+}
+
+/// How the songs are read and which of their fields are stored.
+struct SongOptions<'a> {
+    transliteration: &'a Transliteration,
+    lyrics: &'a LyricsFilter,
+    previews: bool,
+    raw_bodies: bool,
+    greeklish_names: bool,
+    highlights: bool,
+}
+
+/// Handles to all the fields of the schema.
+#[derive(Clone)]
+struct Fields {
+    name: AnalyzedFields,
+    slug: AnalyzedFields,
+    body: AnalyzedFields,
+    body_greeklish: AnalyzedFields,
+    body_foreign: AnalyzedFields,
+    aliases: AnalyzedFields,
+    url: Field,
+    artist: Field,
+    preview: Field,
+    raw_body: Field,
+    stored_body: Field,
+    name_greeklish: Field,
+    artist_greeklish: Field,
+    artist_slug: Field,
+    scale: Field,
+    rhythm: Field,
+    year: Field,
+    kind: Field,
+    modified: Field,
+    views: Field,
+}
+
+impl Fields {
+    /// Add a document for every song of the source, one for every artist and one for every
+    /// scale. Returns the songs whose lyrics look misformatted, which are also logged.
+    fn add_documents(
+        &self,
+        source: &SongSource,
+        popularity: Option<&Path>,
+        scale_aliases: Option<&Path>,
+        options: &SongOptions,
+        index_writer: &IndexWriter,
+        pause: Duration,
+    ) -> Result<Vec<LyricsAnomaly>> {
+        let transliteration = options.transliteration;
+        // Read on every reindex, to pick up fresh counts.
+        let views = match popularity {
+            Some(path) => read_views(path)?,
+            None => HashMap::new(),
+        };
+        let scale_aliases = match scale_aliases {
+            Some(path) => read_aliases(path)?,
+            None => default_aliases(),
+        };
+        // Artists indexed so far and their slugs.
+        let mut indexed_artists: HashMap<String, String> = HashMap::new();
+        let mut anomalies = Vec::new();
+        let mut song_slugs = Slugs::default();
+        let mut artist_slugs = Slugs::default();
+
+        // Songs are keyed by filename or object key, or by name if kept in memory. Slug collisions
+        // are resolved with the file name without its directory, so that the slugs don't change
+        // when the song directory moves.
+        let songs = match source {
+            SongSource::Dir(song_dir) => {
+                // Sorted, so that the same song keeps its slug when slugs collide.
+                let mut filenames = std::fs::read_dir(song_dir)?
+                    .map(|entry| entry.map(|entry| entry.path()))
+                    .collect::<std::io::Result<Vec<_>>>()?;
+                filenames.sort();
+                filenames
+                    .into_iter()
+                    .map(|filename| {
+                        let song = Song::from_path(&filename, transliteration, options.lyrics)?;
+                        let file_name = file_name(&filename.to_string_lossy());
+                        Ok((filename.to_string_lossy().into_owned(), file_name, song))
+                    })
+                    .collect::<Result<Vec<_>>>()?
+            }
+            SongSource::Bucket(location) => read_bucket(location)?
+                .into_iter()
+                .map(|(key, contents)| {
+                    let song = Song::parse(&contents, transliteration, options.lyrics)
+                        .map_err(|e| Error::Parse(format!("{}: {}", key, e)))?;
+                    Ok((key.clone(), file_name(&key), song))
+                })
+                .collect::<Result<Vec<_>>>()?,
+            SongSource::Songs(songs) => songs
+                .iter()
+                .map(|song| (song.name.clone(), song.name.clone(), song.clone()))
+                .collect(),
+        };
+
+        for (key, collision_key, mut song) in songs {
+            song.slug = song_slugs.claim(&song.slug, &collision_key);
+            let new_artist = !indexed_artists.contains_key(&song.artist);
+            song.artist_slug = indexed_artists
+                .entry(song.artist.clone())
+                .or_insert_with(|| artist_slugs.claim(&song.artist_slug, &song.artist))
+                .clone();
+            let stats = song.lyrics_stats();
+            if let Some(problem) = stats.anomaly() {
+                warn!(
+                    "{}: {} ({} lines, {} words, {:.0}% of the lines stripped)",
+                    key,
+                    problem,
+                    stats.lines,
+                    stats.words,
+                    stats.stripped_ratio() * 100.0
+                );
+                anomalies.push(LyricsAnomaly {
+                    song: key.clone(),
+                    problem,
+                    stats,
+                });
+            }
+
+            // On songs, we tokenize the name and body with both the simple
+            // and the stemmed tokenizer. This results in including stemmed
+            // results, but giving a higher score to full word results.
+            let mut document = doc!(
+                self.url => format!("/songs/{}/", song.slug.as_str()),
+                self.artist => song.artist.as_str(),
+                self.year => song.year.unwrap_or(0),
+                self.modified => song.modified.unwrap_or(0),
+                self.views => views.get(song.slug.as_str()).copied().unwrap_or(0),
+                self.kind => Kind::Song as u64,
+            );
+            self.name.add_text(&mut document, &song.name);
+            self.slug.add_text(&mut document, &song.slug);
+            self.body.add_text(&mut document, &song.body);
+            self.body_greeklish
+                .add_text(&mut document, &song.body_greeklish);
+            let foreign_lines = song.foreign_lines();
+            if !foreign_lines.is_empty() {
+                document.add_text(self.body_foreign.simple(), &foreign_lines);
+                document.add_text(self.body_foreign.ngram(), &foreign_lines);
+                if let Some(language) = song.language {
+                    document.add_text(self.body_foreign.stemmed_in(language), &foreign_lines);
+                }
+            }
+            if options.previews {
+                document.add_text(self.preview, song.preview());
+            }
+            if let (true, Some(raw_body)) = (options.raw_bodies, &song.raw_body) {
+                document.add_text(self.raw_body, raw_body);
+            }
+            if options.highlights {
+                document.add_text(self.stored_body, &song.body);
+            }
+            if options.greeklish_names {
+                let name = transliteration.to_display_greeklish(&song.name);
+                document.add_text(self.name_greeklish, name);
+                let artist = transliteration.to_display_greeklish(&song.artist);
+                document.add_text(self.artist_greeklish, artist);
+            }
+            document.add_text(self.artist_slug, &song.artist_slug);
+            // Songs are found by the other spellings of their scale too.
+            if let Some(scale) = &song.scale {
+                document.add_text(self.scale, transliteration.to_slug(scale));
+                for alias in scale_aliases.get(scale).into_iter().flatten() {
+                    document.add_text(self.scale, transliteration.to_slug(alias));
+                }
+            }
+            if let Some(rhythm) = &song.rhythm {
+                document.add_text(self.rhythm, transliteration.to_slug(rhythm));
+            }
+            index_writer.add_document(document);
+
+            if new_artist {
+                let mut document = doc!(
+                    self.url => format!("/artists/{}/", song.artist_slug.as_str()),
+                    self.artist => song.artist.as_str(),
+                    self.kind => Kind::Artist as u64,
+                );
+                self.name.add_text_unstemmed(&mut document, &song.artist);
+                self.slug.add_text(&mut document, &song.artist_slug);
+                if options.greeklish_names {
+                    let artist = transliteration.to_display_greeklish(&song.artist);
+                    document.add_text(self.name_greeklish, &artist);
+                    document.add_text(self.artist_greeklish, artist);
+                }
+                index_writer.add_document(document);
+            }
+
+            if pause > Duration::from_millis(0) {
+                std::thread::sleep(pause);
+            }
+        }
+
+        for &scale in SCALES {
+            let scale_slug = transliteration.to_slug(scale);
+            let mut document = doc!(
+                self.url => format!("/scales/{}/", scale_slug.as_str()),
+                self.kind => Kind::Scale as u64,
+            );
+            self.name.add_text_unstemmed(&mut document, scale);
+            self.slug.add_text(&mut document, &scale_slug);
+            if options.greeklish_names {
+                let name = transliteration.to_display_greeklish(scale);
+                document.add_text(self.name_greeklish, name);
+            }
+            for alias in scale_aliases.get(scale).into_iter().flatten() {
+                self.aliases.add_text(&mut document, alias);
+            }
+            index_writer.add_document(document);
+        }
+
+        Ok(anomalies)
+    }
+}
+
+/// Where the songs are read from, on startup and on reindex.
+#[derive(Clone)]
+enum SongSource {
+    /// A directory with a file per song.
+    Dir(String),
+    /// An S3-compatible bucket with an object per song.
+    Bucket(S3Location),
+    /// Songs kept in memory.
+    Songs(Arc<Vec<Song>>),
+}
+
+/// Return the last component of the path or object key, e.g. "fragkosyriani" for
+/// "/srv/songs/fragkosyriani".
+fn file_name(path: &str) -> String {
+    path.rsplit(['/', '\\']).next().unwrap_or(path).to_string()
+}
+
+#[cfg(feature = "s3")]
+fn read_bucket(location: &S3Location) -> Result<Vec<(String, String)>> {
+    crate::s3::read_objects(location)
+}
+
+#[cfg(not(feature = "s3"))]
+fn read_bucket(_location: &S3Location) -> Result<Vec<(String, String)>> {
+    let message = "BUZUKI_S3_BUCKET is set, but S3 support was not compiled in";
+    Err(Error::Parse(String::from(message)))
+}
+
+/// Ranking and score cutoffs, which can be changed while serving.
+#[derive(Clone)]
+struct Scoring {
+    ranking: Arc<Ranking>,
+    /// Ranking of variant B, if an experiment is running.
+    ranking_b: Option<Arc<Ranking>>,
+    min_score: Option<Score>,
+    min_relative_score: Option<Score>,
+}
+
+impl Scoring {
+    /// Ranking of the variant, falling back to variant A if no experiment is running.
+    fn ranking(&self, variant: Variant) -> Arc<Ranking> {
+        match (variant, &self.ranking_b) {
+            (Variant::B, Some(ranking_b)) => ranking_b.clone(),
+            _ => self.ranking.clone(),
+        }
+    }
+}
+
+/// A song whose lyrics look wrong after stripping, which usually points to a formatting error
+/// of its file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LyricsAnomaly {
+    /// The song file, object key, or name of a song kept in memory.
+    pub song: String,
+    pub problem: &'static str,
+    pub stats: LyricsStats,
+}
+
+/// The consecutive failures of the latest reindexes, while the previous index is served.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReindexFailure {
+    /// Message of the latest failure.
+    pub error: String,
+    /// Number of failed reindexes since the last one that succeeded.
+    pub attempts: u32,
+}
+
+#[derive(Clone)]
+pub struct SearchEngine {
+    // Replaced when a follower switches to a new snapshot.
+    reader: Arc<RwLock<IndexReader>>,
+    index: Index,
+    full_query_parsers: QueryParsers,
+    ngram_query_parsers: QueryParsers,
+    exact_query_parsers: QueryParsers,
+    // Ranks the autocomplete hits that match in their names first.
+    name_query_parsers: QueryParsers,
+    // Name scoped full and exact searches.
+    name_full_query_parsers: QueryParsers,
+    name_exact_query_parsers: QueryParsers,
+    query_cache: Arc<QueryCache>,
+    schema: Schema,
+    // Names of the stored fields that are returned, indexed by field id.
+    stored_layout: Arc<Vec<Option<String>>>,
+    tokenizers: TokenizerManager,
+    full_fields: Vec<Field>,
+    ngram_fields: Vec<Field>,
+    exact_fields: Vec<Field>,
+    // Replaced when the configuration is reloaded.
+    scoring: Arc<RwLock<Scoring>>,
+    fields: Fields,
+    songs: SongSource,
+    popularity: Option<PathBuf>,
+    scale_aliases: Option<PathBuf>,
+    previews: bool,
+    raw_bodies: bool,
+    greeklish_names: bool,
+    highlights: bool,
+    lyrics: LyricsFilter,
+    transliteration: Arc<Transliteration>,
+    // None if the index was serialized.
+    index_writer: Option<Arc<Mutex<IndexWriter>>>,
+    indexing: IndexingLimits,
+    search_threads: Option<usize>,
+    distribution: Distribution,
+    /// Whether the documents of a previous run are served until the songs are reindexed.
+    stale: Arc<AtomicBool>,
+    reindex_failure: Arc<Mutex<Option<ReindexFailure>>>,
+    /// The misformatted songs of the latest indexing.
+    anomalies: Arc<Mutex<Vec<LyricsAnomaly>>>,
+    /// Name of the snapshot that is served by a follower.
+    snapshot: Arc<Mutex<Option<String>>>,
+    /// Number of searches without hits, by endpoint.
+    zero_results: Arc<Mutex<BTreeMap<&'static str, u64>>>,
+    // Keep the temporary index directory around for as long as the engine lives.
+    #[cfg(feature = "mmap")]
+    _index_dir: Option<Arc<TempDir>>,
+}
+
+/// Which fields a query is matched against.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Mode {
+    /// Full words, stemmed words and greeklish.
+    Full,
+    /// Word prefixes, for search as you type.
+    Ngram,
+    /// Full words, distinguishing accented letters.
+    Exact,
+}
+
+/// Which fields of the documents a query may match.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum Scope {
+    /// Names, slugs and lyrics.
+    #[default]
+    All,
+    /// Names and slugs only, for when the title is known and matches in the lyrics are noise.
+    Name,
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Scope, String> {
+        match s {
+            "all" => Ok(Scope::All),
+            "name" => Ok(Scope::Name),
+            _ => Err(format!("Unknown scope {}", s)),
+        }
+    }
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Mode, String> {
+        match s {
+            "full" => Ok(Mode::Full),
+            "ngram" => Ok(Mode::Ngram),
+            "exact" => Ok(Mode::Exact),
+            _ => Err(format!("Unknown parser {}", s)),
+        }
+    }
+}
+
+/// How many hits a search returns, and what is returned of each.
+#[derive(Clone, Debug, Default)]
+pub struct ResultOptions {
+    /// Maximum number of hits. Defaults to 1000, or 15 for ngram searches.
+    pub limit: Option<usize>,
+    /// Names of the stored fields to return, all of them if unset.
+    pub fields: Option<Vec<String>>,
+    /// Whether to return the byte ranges of the name and lyrics that match the query. The lyrics
+    /// are only highlighted if the engine stores them.
+    pub highlight: bool,
+    /// Maximum number of songs of each artist, picked from the most relevant matches. Artists
+    /// whose name matches the query are also returned first when set.
+    pub per_artist: Option<usize>,
+    /// Ranking of an experiment that serves the search.
+    pub variant: Variant,
+    /// Attributes that the songs must have.
+    pub filters: Filters,
+    /// Maximum number of hits of each type, each searched for separately.
+    pub quotas: Option<Quotas>,
+    /// Fields that the query may match.
+    pub scope: Scope,
+    /// Scan the stored lyrics for the query as a substring instead of searching the index. Fails
+    /// if the engine doesn't store the lyrics.
+    pub substring: bool,
+    /// Whether to return which parts of each hit match the query, among "title", "artist",
+    /// "lyrics" and "greeklish".
+    pub matched: bool,
+    /// Endpoint that serves the search, e.g. "search". Searches of an endpoint that find
+    /// nothing are logged and counted under its name.
+    pub endpoint: Option<&'static str>,
+    /// Stops the search when set, failing it with [`Error::Cancelled`].
+    pub cancel: CancelFlag,
+}
+
+/// Maximum number of hits of each type, so that one type doesn't crowd out the others, e.g.
+/// "songs:10,artists:3,scales:2". Types that are left out are only limited by the limit of the
+/// search.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Quotas {
+    pub songs: Option<usize>,
+    pub artists: Option<usize>,
+    pub scales: Option<usize>,
+}
+
+impl Quotas {
+    /// The quota of each type, in the order the hits are returned.
+    fn by_kind(self) -> [(Kind, Option<usize>); 3] {
+        [
+            (Kind::Song, self.songs),
+            (Kind::Artist, self.artists),
+            (Kind::Scale, self.scales),
+        ]
+    }
+}
+
+impl FromStr for Quotas {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Quotas, String> {
+        let mut quotas = Quotas::default();
+        for quota in s
+            .split(',')
+            .map(str::trim)
+            .filter(|quota| !quota.is_empty())
+        {
+            let (kind, count) = quota
+                .split_once(':')
+                .and_then(|(kind, count)| Some((kind.trim(), count.trim().parse().ok()?)))
+                .ok_or_else(|| format!("Expected type:count, got {}", quota))?;
+            match kind {
+                "songs" => quotas.songs = Some(count),
+                "artists" => quotas.artists = Some(count),
+                "scales" => quotas.scales = Some(count),
+                _ => return Err(format!("Unknown type {}", kind)),
+            }
+        }
+        Ok(quotas)
+    }
+}
+
+/// Attributes of the songs that a search is restricted to. Each is matched by the words of its
+/// slug, so that "zeimpekiko" finds the songs in "Ζεϊμπέκικο Παλιό" too.
+#[derive(Clone, Debug, Default)]
+pub struct Filters {
+    /// Name or slug of the artist, e.g. "markos_vamvakaris".
+    pub artist: Option<String>,
+    /// Name or slug of the scale, or another spelling of it, e.g. "ousak".
+    pub scale: Option<String>,
+    /// Name or slug of the rhythm, e.g. "zeimpekiko".
+    pub rhythm: Option<String>,
+}
+
+impl Filters {
+    pub fn is_empty(&self) -> bool {
+        self.artist.is_none() && self.scale.is_none() && self.rhythm.is_none()
+    }
+}
+
+/// An indexed term, with the number of documents that contain it and the number of times that
+/// it occurs in them.
+#[derive(Serialize)]
+struct TermCount {
+    term: String,
+    documents: u64,
+    occurrences: u64,
+}
+
+/// Quote the value for a CSV file if it holds a comma, a quote or a line break, doubling its
+/// quotes.
+fn csv_value(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Seconds since the Unix epoch, which the ages of the songs are measured from.
+#[cfg(not(target_arch = "wasm32"))]
+fn unix_now() -> Option<u64> {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?;
+    Some(now.as_secs())
+}
+
+/// Browsers give wasm32 no clock, so the songs have no age there and get no recency boost.
+#[cfg(target_arch = "wasm32")]
+fn unix_now() -> Option<u64> {
+    None
+}
+
+/// Whether the query is a single quoted phrase, e.g. `"σ' αντίκρυζα"`.
+fn is_quoted(query: &str) -> bool {
+    let query = query.trim();
+    query.len() > 2
+        && query.starts_with('"')
+        && query.ends_with('"')
+        && !query[1..query.len() - 1].contains('"')
+}
+
+/// A retrieved document.
+struct Hit<'a> {
+    address: DocAddress,
+    doc: Document,
+    /// Names of the returned stored fields, indexed by field id.
+    layout: &'a [Option<String>],
+    /// Whether the hit was found by scanning the lyrics rather than by the parsed query.
+    scanned: bool,
+}
+
+impl<'a> Hit<'a> {
+    /// The names and values of the returned stored fields.
+    fn stored_values(&self) -> impl Iterator<Item = (&str, &str)> {
+        stored_values(self.layout, &self.doc)
+    }
+
+    fn to_map(&self) -> HashMap<String, String> {
+        self.stored_values()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+}
+
+impl<'a> StoredFields for Hit<'a> {
+    fn get(&self, name: &str) -> Option<&str> {
+        self.stored_values()
+            .find(|&(field_name, _)| field_name == name)
+            .map(|(_, value)| value)
+    }
+}
+
+/// The names and values of the stored text fields of the document that have a name in the
+/// layout, followed by the slug after the url.
+fn stored_values<'a>(
+    layout: &'a [Option<String>],
+    doc: &'a Document,
+) -> impl Iterator<Item = (&'a str, &'a str)> {
+    doc.field_values().iter().flat_map(move |field_value| {
+        let stored = layout
+            .get(field_value.field().field_id() as usize)
+            .and_then(Option::as_ref)
+            .zip(field_value.value().text())
+            .map(|(field_name, value)| (field_name.as_str(), value));
+        let slug = match stored {
+            Some(("url", url)) => url.trim_end_matches('/').rsplit('/').next(),
+            _ => None,
+        };
+        stored.into_iter().chain(slug.map(|slug| ("slug", slug)))
+    })
+}
+
+/// A search result as returned to clients, serialized straight from the retrieved document,
+/// together with the category of the document.
+struct HitJson<'a> {
+    hit: &'a Hit<'a>,
+    /// Names of the stored fields to return, all of them if unset.
+    fields: Option<&'a [String]>,
+    highlights: Option<serde_json::Value>,
+    /// The parts of the hit that match the query.
+    matched: Option<Vec<&'static str>>,
+}
+
+impl<'a> Serialize for HitJson<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let returned = |name: &str| {
+            self.fields
+                .is_none_or(|fields| fields.iter().any(|field| field == name))
+        };
+        let mut map = serializer.serialize_map(None)?;
+        for (name, value) in self.hit.stored_values() {
+            if returned(name) {
+                map.serialize_entry(name, value)?;
+            }
+        }
+        let kind = self.hit.get("url").and_then(Kind::from_url);
+        if let (true, Some(kind)) = (returned("category"), kind) {
+            map.serialize_entry("category", kind.category())?;
+        }
+        if let Some(highlights) = &self.highlights {
+            map.serialize_entry("highlights", highlights)?;
+        }
+        if let Some(matched) = &self.matched {
+            map.serialize_entry("matched", matched)?;
+        }
+        map.end()
+    }
+}
+
+/// The results of a search, together with the total number of matching documents, the time the
+/// search took and the query itself.
+#[derive(Serialize)]
+struct Envelope<'a> {
+    results: Vec<HitJson<'a>>,
+    total: usize,
+    took_ms: f64,
+    query: &'a str,
+}
+
+/// Order of the search results. The sorts other than relevance reorder the most relevant
+/// matches, up to the limit, rather than every match, so e.g. sorting by year returns the earliest
+/// of the top hits, not the earliest songs that match.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sort {
+    Relevance,
+    Name,
+    Artist,
+    Year,
+    /// Most viewed first.
+    Popularity,
+}
+
+impl FromStr for Sort {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Sort, String> {
+        match s {
+            "relevance" => Ok(Sort::Relevance),
+            "name" => Ok(Sort::Name),
+            "artist" => Ok(Sort::Artist),
+            "year" => Ok(Sort::Year),
+            "popularity" => Ok(Sort::Popularity),
+            _ => Err(format!("Unknown sort {}", s)),
+        }
+    }
+}
+
+/// Builds a search engine with custom settings, e.g. in tests or when embedding the engine.
+/// Settings that are not set have the same defaults as when read from the environment.
+pub struct SearchEngineBuilder {
+    songs: SongSource,
+    popularity: Option<PathBuf>,
+    scale_aliases: Option<PathBuf>,
+    previews: bool,
+    raw_bodies: bool,
+    greeklish_names: bool,
+    highlights: bool,
+    lyrics: LyricsFilter,
+    index_dir: Option<PathBuf>,
+    analyzers: Vec<(String, TextAnalyzer)>,
+    numbers: NumberPolicy,
+    transliteration: Option<Transliteration>,
+    word_limits: WordLimits,
+    boosts: Boosts,
+    variant_b: Option<Boosts>,
+    min_score: Option<Score>,
+    min_relative_score: Option<Score>,
+    search_threads: Option<usize>,
+    indexing: IndexingLimits,
+    distribution: Distribution,
+    lazy: bool,
+    serialized: Option<Vec<u8>>,
+}
+
+impl SearchEngineBuilder {
+    pub fn new(song_dir: impl Into<String>) -> SearchEngineBuilder {
+        SearchEngineBuilder::with_source(SongSource::Dir(song_dir.into()))
+    }
+
+    /// Index the given songs instead of a song directory. The index is kept in RAM, unless an
+    /// index directory is set.
+    pub fn from_songs(songs: Vec<Song>) -> SearchEngineBuilder {
+        SearchEngineBuilder::with_source(SongSource::Songs(Arc::new(songs)))
+    }
+
+    /// Serve an index serialized by `SearchEngine::serialize` instead of indexing songs, e.g. on
+    /// wasm32, where indexes can't be built. The index is kept in RAM and can't be reindexed.
+    /// The settings that change the results, e.g. the boosts, should match those of the engine
+    /// that serialized it.
+    pub fn from_serialized(index: &[u8]) -> SearchEngineBuilder {
+        SearchEngineBuilder {
+            serialized: Some(index.to_vec()),
+            ..SearchEngineBuilder::from_songs(Vec::new())
+        }
+    }
+
+    /// Read the songs from the objects of a bucket instead of a song directory.
+    pub fn from_bucket(location: S3Location) -> SearchEngineBuilder {
+        SearchEngineBuilder::with_source(SongSource::Bucket(location))
+    }
+
+    fn with_source(songs: SongSource) -> SearchEngineBuilder {
+        SearchEngineBuilder {
+            songs,
+            popularity: None,
+            scale_aliases: None,
+            previews: true,
+            raw_bodies: false,
+            greeklish_names: false,
+            highlights: false,
+            lyrics: LyricsFilter::default(),
+            index_dir: None,
+            analyzers: Vec::new(),
+            numbers: NumberPolicy::Keep,
+            transliteration: None,
+            word_limits: WordLimits::default(),
+            boosts: Boosts::default(),
+            variant_b: None,
+            min_score: None,
+            min_relative_score: None,
+            search_threads: None,
+            indexing: IndexingLimits::default(),
+            distribution: Distribution::Standalone,
+            lazy: false,
+            serialized: None,
+        }
+    }
+
+    pub fn from_config(config: &Config) -> SearchEngineBuilder {
+        let builder = match &config.song_bucket {
+            Some(location) => SearchEngineBuilder::from_bucket(location.clone()),
+            None => SearchEngineBuilder::new(config.song_dir.clone()),
+        };
+        let builder = match &config.index_dir {
+            Some(index_dir) => builder.index_dir(index_dir.clone()),
+            None => builder,
+        };
+        builder
+            .numbers(config.numbers)
+            .transliteration(config.transliteration.clone())
+            .word_limits(config.word_limits.clone())
+            .boosts(config.boosts)
+            .variant_b(config.experiment.map(|experiment| experiment.boosts))
+            .min_score(config.min_score)
+            .min_relative_score(config.min_relative_score)
+            .search_threads(config.search_threads)
+            .indexing(config.indexing)
+            .distribution(config.distribution.clone())
+            .popularity(config.popularity.clone())
+            .scale_aliases(config.scale_aliases.clone())
+            .previews(config.previews)
+            .raw_bodies(config.raw_bodies)
+            .greeklish_names(config.greeklish_names)
+            .highlights(config.highlights)
+            .lyrics(config.lyrics.clone())
+            .lazy(config.lazy_start)
+    }
+
+    /// Create the index in the given directory, which must be empty, instead of a temporary
+    /// directory that is removed with the engine.
+    pub fn index_dir(mut self, index_dir: impl Into<PathBuf>) -> SearchEngineBuilder {
+        self.index_dir = Some(index_dir.into());
+        self
+    }
+
+    /// Replace the built-in analyzer with the given name, e.g. "el_stem".
+    pub fn analyzer(mut self, name: &str, analyzer: TextAnalyzer) -> SearchEngineBuilder {
+        self.analyzers.push((name.to_string(), analyzer));
+        self
+    }
+
+    /// Number policy of the analyzers, and of the transliteration if it's not set.
+    pub fn numbers(mut self, numbers: NumberPolicy) -> SearchEngineBuilder {
+        self.numbers = numbers;
+        self
+    }
+
+    pub fn transliteration(mut self, transliteration: Transliteration) -> SearchEngineBuilder {
+        self.transliteration = Some(transliteration);
+        self
+    }
+
+    pub fn word_limits(mut self, word_limits: WordLimits) -> SearchEngineBuilder {
+        self.word_limits = word_limits;
+        self
+    }
+
+    pub fn boosts(mut self, boosts: Boosts) -> SearchEngineBuilder {
+        self.boosts = boosts;
+        self
+    }
+
+    /// Boosts of the ranking under test, served to searches of variant B.
+    pub fn variant_b(mut self, variant_b: Option<Boosts>) -> SearchEngineBuilder {
+        self.variant_b = variant_b;
+        self
+    }
+
+    /// Drop the hits of full mode searches that score below this. Ngram and exact searches are
+    /// not cut off.
+    pub fn min_score(mut self, min_score: Option<Score>) -> SearchEngineBuilder {
+        self.min_score = min_score;
+        self
+    }
+
+    /// Drop the hits of full mode searches that score below this fraction of the score of the
+    /// top hit. Ngram and exact searches are not cut off.
+    pub fn min_relative_score(mut self, min_relative_score: Option<Score>) -> SearchEngineBuilder {
+        self.min_relative_score = min_relative_score;
+        self
+    }
+
+    /// Search the segments of the index in parallel on a dedicated pool with the given number
+    /// of threads, instead of one after the other on the calling thread.
+    pub fn search_threads(mut self, search_threads: Option<usize>) -> SearchEngineBuilder {
+        self.search_threads = search_threads;
+        self
+    }
+
+    /// Limit the threads and memory used for indexing, and pause between songs on reindex.
+    pub fn indexing(mut self, indexing: IndexingLimits) -> SearchEngineBuilder {
+        self.indexing = indexing;
+        self
+    }
+
+    /// Publish the index to, or serve the index from, a snapshot directory.
+    pub fn distribution(mut self, distribution: Distribution) -> SearchEngineBuilder {
+        self.distribution = distribution;
+        self
+    }
+
+    /// File of song view counts, read whenever the songs are indexed. See `read_views`.
+    pub fn popularity(mut self, popularity: Option<PathBuf>) -> SearchEngineBuilder {
+        self.popularity = popularity;
+        self
+    }
+
+    /// File of other spellings of the scales, read whenever the songs are indexed. See
+    /// `read_aliases`.
+    pub fn scale_aliases(mut self, scale_aliases: Option<PathBuf>) -> SearchEngineBuilder {
+        self.scale_aliases = scale_aliases;
+        self
+    }
+
+    /// Serve the documents that the index directory holds from a previous run, if its schema
+    /// matches, instead of indexing the songs before returning. The engine is stale until it's
+    /// reindexed, e.g. by `reindex` on a background thread.
+    pub fn lazy(mut self, lazy: bool) -> SearchEngineBuilder {
+        self.lazy = lazy;
+        self
+    }
+
+    /// Whether to store and return the preview of each song. Previews take most of the stored
+    /// data after the lyrics.
+    pub fn previews(mut self, previews: bool) -> SearchEngineBuilder {
+        self.previews = previews;
+        self
+    }
+
+    /// Whether to store the lyrics of each song file with their chords. They are not returned
+    /// with the results, and take about as much space as the lyrics.
+    pub fn raw_bodies(mut self, raw_bodies: bool) -> SearchEngineBuilder {
+        self.raw_bodies = raw_bodies;
+        self
+    }
+
+    /// Whether to store and return the names of the songs, artists and scales and the artists
+    /// of the songs romanized, as "name_greeklish" and "artist_greeklish", so that Latin script
+    /// frontends don't need to transliterate them.
+    pub fn greeklish_names(mut self, greeklish_names: bool) -> SearchEngineBuilder {
+        self.greeklish_names = greeklish_names;
+        self
+    }
+
+    /// Whether to store the lyrics of each song, which highlighting the matches in the lyrics
+    /// and substring searches read. Without them only the names are highlighted.
+    pub fn highlights(mut self, highlights: bool) -> SearchEngineBuilder {
+        self.highlights = highlights;
+        self
+    }
+
+    /// How chords and other metadata are stripped from the lyrics of the song files.
+    pub fn lyrics(mut self, lyrics: LyricsFilter) -> SearchEngineBuilder {
+        self.lyrics = lyrics;
+        self
+    }
+
+    /// Open or create the index in the index directory, in RAM if the songs are kept in memory, or else
+    /// in a temporary directory that has to be kept around.
+    #[cfg(feature = "mmap")]
+    fn create_index(&self, schema: Schema) -> Result<(Index, Option<TempDir>)> {
+        if let Some(serialized) = &self.serialized {
+            return Ok((open_serialized(serialized, &schema)?, None));
+        }
+        match (&self.index_dir, &self.songs) {
+            (Some(index_dir), _) => Ok((open_or_create_in_dir(index_dir, schema)?, None)),
+            (None, SongSource::Songs(_)) => Ok((Index::create_in_ram(schema), None)),
+            (None, SongSource::Dir(_)) | (None, SongSource::Bucket(_)) => {
+                let temp_dir = tempdir()?;
+                Ok((Index::create_in_dir(&temp_dir, schema)?, Some(temp_dir)))
+            }
+        }
+    }
+
+    /// Indexes are always kept in RAM without mmap support.
+    #[cfg(not(feature = "mmap"))]
+    fn create_index(&self, schema: Schema) -> Result<Index> {
+        if let Some(serialized) = &self.serialized {
+            return open_serialized(serialized, &schema);
+        }
+        if self.index_dir.is_some() {
+            let message = "Index directories require the mmap feature";
+            return Err(Error::Parse(String::from(message)));
+        }
+        Ok(Index::create_in_ram(schema))
+    }
+
+    /// Build the index from the song directory. Fails if a replaced analyzer doesn't exist, and
+    /// on wasm32, where the index writer can't start its threads, unless the index is
+    /// serialized.
+    pub fn build(self) -> Result<SearchEngine> {
+        // Build tokenizers. Long words are dropped before ngram generation, since all their
+        // prefixes would be indexed otherwise. The simple and ngram analyzers fold Latin
+        // diacritics, so that names like "Café Aman" are found as "cafe aman". The folding
+        // leaves Greek letters alone.
+        let word_limits = &self.word_limits;
+        let numbers = NumberFilter::new(self.numbers);
+        let greek_ngram_tokenizer =
+            TextAnalyzer::from(NgramTokenizer::limit(word_limits.get("el_ngram")))
+                .filter(HomoglyphFilter)
+                .filter(numbers)
+                .filter(GreekLowerCaser::folding_homophones())
+                .filter(AsciiFoldingFilter);
+
+        let english_ngram_tokenizer =
+            TextAnalyzer::from(NgramTokenizer::limit(word_limits.get("en_ngram")))
+                .filter(HomoglyphFilter)
+                .filter(numbers)
+                .filter(LowerCaser)
+                .filter(AsciiFoldingFilter);
+
+        let greek_simple_tokenizer = TextAnalyzer::from(SimpleTokenizer)
+            .filter(RemoveLongFilter::limit(word_limits.get("el_simple")))
+            .filter(HomoglyphFilter)
+            .filter(numbers)
+            .filter(GreekLowerCaser::default())
+            .filter(AsciiFoldingFilter);
+
+        let english_simple_tokenizer = TextAnalyzer::from(SimpleTokenizer)
+            .filter(RemoveLongFilter::limit(word_limits.get("en_simple")))
+            .filter(HomoglyphFilter)
+            .filter(numbers)
+            .filter(LowerCaser)
+            .filter(AsciiFoldingFilter);
+
+        let greek_stem_tokenizer = TextAnalyzer::from(SimpleTokenizer)
+            .filter(RemoveLongFilter::limit(word_limits.get("el_stem")))
+            .filter(HomoglyphFilter)
+            .filter(numbers)
+            .filter(GreekLowerCaser::default())
+            .filter(Stemmer::new(Language::Greek));
+
+        let greek_exact_tokenizer = TextAnalyzer::from(SimpleTokenizer)
+            .filter(RemoveLongFilter::limit(word_limits.get("el_exact")))
+            .filter(HomoglyphFilter)
+            .filter(numbers)
+            .filter(LowerCaser);
+
+        let greek_phonetic_tokenizer = TextAnalyzer::from(SimpleTokenizer)
+            .filter(RemoveLongFilter::limit(word_limits.get("el_phonetic")))
+            .filter(HomoglyphFilter)
+            .filter(numbers)
+            .filter(GreekLowerCaser::default())
+            .filter(PhoneticFilter);
+
+        let greek_shingle_tokenizer = TextAnalyzer::from(SimpleTokenizer)
+            .filter(RemoveLongFilter::limit(word_limits.get("el_shingle")))
+            .filter(HomoglyphFilter)
+            .filter(numbers)
+            .filter(GreekLowerCaser::default())
+            .filter(ShingleFilter::adjacent());
+
+        let greek_near_tokenizer = TextAnalyzer::from(SimpleTokenizer)
+            .filter(RemoveLongFilter::limit(word_limits.get("el_near")))
+            .filter(HomoglyphFilter)
+            .filter(numbers)
+            .filter(GreekLowerCaser::default())
+            .filter(ShingleFilter::near(4));
+
+        // Prefixes of whole names, normalized like the ngrams.
+        let greek_prefix_tokenizer = TextAnalyzer::from(SimpleTokenizer)
+            .filter(HomoglyphFilter)
+            .filter(numbers)
+            .filter(GreekLowerCaser::folding_homophones())
+            .filter(AsciiFoldingFilter)
+            .filter(EdgeNgramFilter::limit(MAX_PREFIX_LEN));
+
+        let english_prefix_tokenizer = TextAnalyzer::from(SimpleTokenizer)
+            .filter(HomoglyphFilter)
+            .filter(numbers)
+            .filter(LowerCaser)
+            .filter(AsciiFoldingFilter)
+            .filter(EdgeNgramFilter::limit(MAX_PREFIX_LEN));
+
+        // Verses in other languages, which the Greek filters would mangle.
+        let turkish_stem_tokenizer = TextAnalyzer::from(SimpleTokenizer)
+            .filter(RemoveLongFilter::limit(word_limits.get("tr_stem")))
+            .filter(numbers)
+            .filter(LowerCaser)
+            .filter(Stemmer::new(Language::Turkish))
+            .filter(AsciiFoldingFilter);
+
+        let english_stem_tokenizer = TextAnalyzer::from(SimpleTokenizer)
+            .filter(RemoveLongFilter::limit(word_limits.get("en_stem")))
+            .filter(numbers)
+            .filter(LowerCaser)
+            .filter(Stemmer::new(Language::English))
+            .filter(AsciiFoldingFilter);
+
+        // Build schema
+        let mut schema_builder = Schema::builder();
+
+        // Tantivy supports a single tokenizer per field, so every source text is indexed once per
+        // analyzer in a group of sibling fields. Only the first field of each group is stored,
+        // the rest hold postings only.
+        let name = AnalyzedFields::new(
+            &mut schema_builder,
+            "name",
+            &[
+                ("", "el_simple"),
+                ("ngram_", "el_ngram"),
+                ("stemmed_", "el_stem"),
+                ("exact_", "el_exact"),
+                ("phonetic_", "el_phonetic"),
+                ("shingle_", "el_shingle"),
+                ("prefix_", "el_prefix"),
+            ],
+            true,
+        );
+        // Slugs are returned from the stored urls, which end with them.
+        let slug = AnalyzedFields::new(
+            &mut schema_builder,
+            "slug",
+            &[
+                ("", "en_simple"),
+                ("ngram_", "en_ngram"),
+                ("prefix_", "en_prefix"),
+            ],
+            false,
+        );
+        let body = AnalyzedFields::new(
+            &mut schema_builder,
+            "body",
+            &[
+                ("", "el_simple"),
+                ("ngram_", "el_ngram"),
+                ("stemmed_", "el_stem"),
+                ("exact_", "el_exact"),
+                ("phonetic_", "el_phonetic"),
+                ("shingle_", "el_shingle"),
+                ("near_", "el_near"),
+            ],
+            false,
+        );
+        let body_greeklish = AnalyzedFields::new(
+            &mut schema_builder,
+            "body_greeklish",
+            &[("", "en_simple"), ("ngram_", "en_ngram")],
+            false,
+        );
+
+        // The lyrics lines in the Latin alphabet, which are stemmed in the language of the song
+        // if it names one.
+        let body_foreign = AnalyzedFields::new(
+            &mut schema_builder,
+            "body_foreign",
+            &[
+                ("", "en_simple"),
+                ("ngram_", "en_ngram"),
+                ("stemmed_tr_", "tr_stem"),
+                ("stemmed_en_", "en_stem"),
+            ],
+            false,
+        );
+
+        // Other spellings of the scale names.
+        let aliases = AnalyzedFields::new(
+            &mut schema_builder,
+            "aliases",
+            &[("", "el_simple"), ("ngram_", "el_ngram")],
+            false,
+        );
+
+        // Keyword fields
+        let url = schema_builder.add_text_field("url", STORED);
+        let artist = schema_builder.add_text_field("artist", STORED);
+        let preview = schema_builder.add_text_field("preview", STORED);
+        // The lyrics with the chords, for showing a song rather than a search result.
+        let raw_body = schema_builder.add_text_field("raw_body", STORED);
+        // The lyrics, for highlighting them and for substring searches.
+        let stored_body = schema_builder.add_text_field("stored_body", STORED);
+        // The names romanized for display, e.g. "Markos Vamvakaris".
+        let name_greeklish = schema_builder.add_text_field("name_greeklish", STORED);
+        let artist_greeklish = schema_builder.add_text_field("artist_greeklish", STORED);
+
+        // Slugs of the artist, the scale and the rhythm of the songs, which searches are filtered
+        // by.
+        let artist_slug = schema_builder.add_text_field("artist_slug", get_options("en_simple"));
+        let scale = schema_builder.add_text_field("scale", get_options("en_simple"));
+        let rhythm = schema_builder.add_text_field("rhythm", get_options("en_simple"));
+
+        // Fast fields
+        let year = schema_builder.add_u64_field("year", FAST);
+        // Indexed too, for searching each type separately.
+        let kind = schema_builder.add_u64_field("kind", FAST | INDEXED);
+        let modified = schema_builder.add_u64_field("modified", FAST);
+        let views = schema_builder.add_u64_field("views", FAST);
+
+        let schema = schema_builder.build();
+        check_schema(&schema)?;
+
+        let fields = Fields {
+            name,
+            slug,
+            body,
+            body_greeklish,
+            body_foreign,
+            aliases,
+            url,
+            artist,
+            preview,
+            raw_body,
+            stored_body,
+            name_greeklish,
+            artist_greeklish,
+            artist_slug,
+            scale,
+            rhythm,
+            year,
+            kind,
+            modified,
+            views,
+        };
+
+        // Build index
+        #[cfg(feature = "mmap")]
+        let (mut index, temp_dir) = self.create_index(schema)?;
+        #[cfg(not(feature = "mmap"))]
+        let mut index = self.create_index(schema)?;
+        if let Some(search_threads) = self.search_threads {
+            index.set_multithread_executor(search_threads)?;
+        }
+
+        let manager = index.tokenizers();
+        manager.register("el_ngram", greek_ngram_tokenizer);
+        manager.register("en_ngram", english_ngram_tokenizer);
+        manager.register("el_simple", greek_simple_tokenizer);
+        manager.register("en_simple", english_simple_tokenizer);
+        manager.register("el_stem", greek_stem_tokenizer);
+        manager.register("el_exact", greek_exact_tokenizer);
+        manager.register("el_phonetic", greek_phonetic_tokenizer);
+        manager.register("el_shingle", greek_shingle_tokenizer);
+        manager.register("el_near", greek_near_tokenizer);
+        manager.register("el_prefix", greek_prefix_tokenizer);
+        manager.register("en_prefix", english_prefix_tokenizer);
+        manager.register("tr_stem", turkish_stem_tokenizer);
+        manager.register("en_stem", english_stem_tokenizer);
+        for (name, analyzer) in self.analyzers {
+            if manager.get(&name).is_none() {
+                return Err(Error::Parse(format!("Unknown analyzer {}", name)));
+            }
+            manager.register(&name, analyzer);
+        }
+
+        let numbers = self.numbers;
+        let transliteration = self
+            .transliteration
+            .unwrap_or_else(|| Transliteration::new(numbers));
+        // Serialized indexes are served as they are.
+        let mut index_writer = match (&self.serialized, self.indexing.threads) {
+            (Some(_), _) => None,
+            (None, Some(threads)) => {
+                Some(index.writer_with_num_threads(threads, self.indexing.heap_size)?)
+            }
+            (None, None) => Some(index.writer(self.indexing.heap_size)?),
+        };
+        // Only committed indexes have a schema version, and indexes of other versions have been
+        // removed by now.
+        let follower = matches!(self.distribution, Distribution::Follow(_));
+        let stale = self.lazy && !follower && schema_version(&index)?.is_some();
+        let mut anomalies = Vec::new();
+        if let (false, Some(index_writer)) = (stale, &mut index_writer) {
+            // An index directory may hold the documents of the previous run.
+            index_writer.delete_all_documents()?;
+            // Nothing is served yet, so the initial indexing doesn't pause.
+            // Followers serve an empty index until the first snapshot is published, since the
+            // songs may not be available to them.
+            if !follower {
+                let no_pause = Duration::from_millis(0);
+                let options = SongOptions {
+                    transliteration: &transliteration,
+                    lyrics: &self.lyrics,
+                    previews: self.previews,
+                    raw_bodies: self.raw_bodies,
+                    greeklish_names: self.greeklish_names,
+                    highlights: self.highlights,
+                };
+                anomalies = fields.add_documents(
+                    &self.songs,
+                    self.popularity.as_deref(),
+                    self.scale_aliases.as_deref(),
+                    &options,
+                    index_writer,
+                    no_pause,
+                )?;
+            }
+            commit(index_writer)?;
+        }
+
+        // Reloaded by hand once reindexing has finished, so that searches are served from the
+        // previous index in the meantime.
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::Manual)
+            .try_into()?;
+
+        // Greek queries skip the greeklish and foreign fields and Latin queries skip the Greek
+        // lyrics, but names are always searched since they may contain Latin words.
+        let full_fields = vec![
+            fields.name.simple(),
+            fields.slug.simple(),
+            fields.aliases.simple(),
+            fields.body.simple(),
+            fields.body_greeklish.simple(),
+            fields.body_foreign.simple(),
+            fields.name.stemmed(),
+            fields.body.stemmed(),
+            fields.body_foreign.stemmed_in(ForeignLanguage::Turkish),
+            fields.body_foreign.stemmed_in(ForeignLanguage::English),
+            fields.name.phonetic(),
+            fields.body.phonetic(),
+        ];
+        let full_query_parsers = QueryParsers::new(
+            &index,
+            full_fields.clone(),
+            vec![
+                fields.name.simple(),
+                fields.aliases.simple(),
+                fields.body.simple(),
+                fields.name.stemmed(),
+                fields.body.stemmed(),
+                fields.name.phonetic(),
+                fields.body.phonetic(),
+            ],
+            vec![
+                fields.name.simple(),
+                fields.slug.simple(),
+                fields.aliases.simple(),
+                fields.body_greeklish.simple(),
+                fields.body_foreign.simple(),
+                fields.body_foreign.stemmed_in(ForeignLanguage::Turkish),
+                fields.body_foreign.stemmed_in(ForeignLanguage::English),
+            ],
+        );
+
+        let ngram_fields = vec![
+            fields.name.ngram(),
+            fields.slug.ngram(),
+            fields.aliases.ngram(),
+            fields.body.ngram(),
+            fields.body_greeklish.ngram(),
+            fields.body_foreign.ngram(),
+        ];
+        let ngram_query_parsers = QueryParsers::new(
+            &index,
+            ngram_fields.clone(),
+            vec![
+                fields.name.ngram(),
+                fields.aliases.ngram(),
+                fields.body.ngram(),
+            ],
+            vec![
+                fields.name.ngram(),
+                fields.slug.ngram(),
+                fields.aliases.ngram(),
+                fields.body_greeklish.ngram(),
+                fields.body_foreign.ngram(),
+            ],
+        );
+
+        let name_fields = vec![
+            fields.name.ngram(),
+            fields.slug.ngram(),
+            fields.aliases.ngram(),
+        ];
+        let name_query_parsers = QueryParsers::new(
+            &index,
+            name_fields.clone(),
+            vec![fields.name.ngram(), fields.aliases.ngram()],
+            name_fields,
+        );
+
+        let name_full_query_parsers = QueryParsers::new(
+            &index,
+            vec![
+                fields.name.simple(),
+                fields.slug.simple(),
+                fields.aliases.simple(),
+                fields.name.stemmed(),
+                fields.name.phonetic(),
+            ],
+            vec![
+                fields.name.simple(),
+                fields.aliases.simple(),
+                fields.name.stemmed(),
+                fields.name.phonetic(),
+            ],
+            vec![
+                fields.name.simple(),
+                fields.slug.simple(),
+                fields.aliases.simple(),
+            ],
+        );
+        let name_exact_fields = vec![fields.name.exact()];
+        let name_exact_query_parsers = QueryParsers::new(
+            &index,
+            name_exact_fields.clone(),
+            name_exact_fields.clone(),
+            name_exact_fields,
+        );
+
+        // Accents are significant in exact mode, so Latin text is irrelevant.
+        let exact_fields = vec![fields.name.exact(), fields.body.exact()];
+        let exact_query_parsers = QueryParsers::new(
+            &index,
+            exact_fields.clone(),
+            exact_fields.clone(),
+            exact_fields.clone(),
+        );
+
+        let schema = index.schema();
+        let stored_layout = schema
+            .fields()
+            .map(|(field, entry)| {
+                let returned =
+                    entry.is_stored() && field != fields.stored_body && field != fields.raw_body;
+                Some(entry.name().to_string()).filter(|_| returned)
+            })
+            .collect();
+        let tokenizers = index.tokenizers().clone();
+
+        let engine = SearchEngine {
+            reader: Arc::new(RwLock::new(reader)),
+            index,
+            full_query_parsers,
+            ngram_query_parsers,
+            exact_query_parsers,
+            name_query_parsers,
+            name_full_query_parsers,
+            name_exact_query_parsers,
+            query_cache: Arc::new(QueryCache::new(QUERY_CACHE_SIZE)),
+            schema,
+            stored_layout: Arc::new(stored_layout),
+            tokenizers,
+            full_fields,
+            ngram_fields,
+            exact_fields,
+            scoring: Arc::new(RwLock::new(Scoring {
+                ranking: Arc::new(Ranking::from_boosts(&self.boosts)),
+                ranking_b: self
+                    .variant_b
+                    .map(|boosts| Arc::new(Ranking::from_boosts(&boosts))),
+                min_score: self.min_score,
+                min_relative_score: self.min_relative_score,
+            })),
+            fields,
+            songs: self.songs,
+            popularity: self.popularity,
+            scale_aliases: self.scale_aliases,
+            previews: self.previews,
+            raw_bodies: self.raw_bodies,
+            greeklish_names: self.greeklish_names,
+            highlights: self.highlights,
+            lyrics: self.lyrics,
+            transliteration: Arc::new(transliteration),
+            index_writer: index_writer.map(|index_writer| Arc::new(Mutex::new(index_writer))),
+            indexing: self.indexing,
+            search_threads: self.search_threads,
+            distribution: self.distribution,
+            stale: Arc::new(AtomicBool::new(stale)),
+            reindex_failure: Arc::new(Mutex::new(None)),
+            anomalies: Arc::new(Mutex::new(anomalies)),
+            snapshot: Arc::new(Mutex::new(None)),
+            zero_results: Arc::new(Mutex::new(BTreeMap::new())),
+            #[cfg(feature = "mmap")]
+            _index_dir: temp_dir.map(Arc::new),
+        };
+        match &engine.distribution {
+            Distribution::Standalone => {}
+            // Published once the songs are reindexed.
+            Distribution::Publish(_) if stale => {}
+            Distribution::Publish(dir) => {
+                let name = SnapshotDir::new(dir).publish(&engine.index)?;
+                info!("Published snapshot {}", name);
+            }
+            Distribution::Follow(_) => {
+                engine.sync()?;
+            }
+        }
+        Ok(engine)
+    }
+}
+
+impl SearchEngine {
+    pub fn new(config: &Config) -> Result<SearchEngine> {
+        SearchEngineBuilder::from_config(config).build()
+    }
+
+    pub fn builder(song_dir: impl Into<String>) -> SearchEngineBuilder {
+        SearchEngineBuilder::new(song_dir)
+    }
+
+    /// Replace the boosts of both variants and the score cutoffs of the following searches,
+    /// e.g. when the configuration is reloaded.
+    pub fn set_scoring(
+        &self,
+        boosts: Boosts,
+        variant_b: Option<Boosts>,
+        min_score: Option<Score>,
+        min_relative_score: Option<Score>,
+    ) {
+        *self.scoring.write().unwrap() = Scoring {
+            ranking: Arc::new(Ranking::from_boosts(&boosts)),
+            ranking_b: variant_b.map(|boosts| Arc::new(Ranking::from_boosts(&boosts))),
+            min_score,
+            min_relative_score,
+        };
+    }
+
+    /// Whether the engine serves the documents of a previous run, since it was built lazily and
+    /// hasn't been reindexed yet.
+    pub fn is_stale(&self) -> bool {
+        self.stale.load(Ordering::Relaxed)
+    }
+
+    /// The transliteration of the names and lyrics into greeklish and slugs.
+    pub fn transliteration(&self) -> &Transliteration {
+        &self.transliteration
+    }
+
+    /// Build an engine over the given songs, with the index in RAM.
+    pub fn from_songs(songs: Vec<Song>) -> Result<SearchEngine> {
+        SearchEngineBuilder::from_songs(songs).build()
+    }
+
+    /// Serve an index serialized by `serialize`, kept in RAM, with the default settings.
+    pub fn from_serialized(index: &[u8]) -> Result<SearchEngine> {
+        SearchEngineBuilder::from_serialized(index).build()
+    }
+
+    /// Serialize the committed index into a buffer, for serving it with `from_serialized`
+    /// where indexes can't be built, e.g. in a browser.
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        snapshot::serialize(&self.index)
+    }
+
+    /// Rebuild the index from the songs and make the new documents visible to
+    /// searches. Searches see the old documents until then. Returns the number of
+    /// indexed documents.
+    ///
+    /// Publishers also publish the new index, and followers switch to the latest
+    /// snapshot instead.
+    ///
+    /// Failures are recorded until a reindex succeeds, and the previous index keeps being
+    /// served.
+    pub fn reindex(&self) -> Result<u64> {
+        let result = self.rebuild();
+        let mut failure = self.reindex_failure.lock().unwrap();
+        match &result {
+            Ok(_) => *failure = None,
+            Err(e) => {
+                let attempts = failure.as_ref().map_or(0, |failure| failure.attempts);
+                *failure = Some(ReindexFailure {
+                    error: e.to_string(),
+                    attempts: attempts + 1,
+                });
+            }
+        }
+        result
+    }
+
+    /// The failures of the latest reindexes, if the last one failed.
+    pub fn reindex_failure(&self) -> Option<ReindexFailure> {
+        self.reindex_failure.lock().unwrap().clone()
+    }
+
+    /// The songs whose lyrics looked misformatted when they were last indexed.
+    pub fn anomalies(&self) -> Vec<LyricsAnomaly> {
+        self.anomalies.lock().unwrap().clone()
+    }
+
+    fn rebuild(&self) -> Result<u64> {
+        if let Distribution::Follow(_) = self.distribution {
+            self.sync()?;
+            return Ok(self.searcher().num_docs());
+        }
+        let mut index_writer = match &self.index_writer {
+            Some(index_writer) => index_writer.lock().unwrap(),
+            None => {
+                let message = "A serialized index can't be reindexed";
+                return Err(TantivyError::InvalidArgument(String::from(message)).into());
+            }
+        };
+        index_writer.delete_all_documents()?;
+        let options = SongOptions {
+            transliteration: &self.transliteration,
+            lyrics: &self.lyrics,
+            previews: self.previews,
+            raw_bodies: self.raw_bodies,
+            greeklish_names: self.greeklish_names,
+            highlights: self.highlights,
+        };
+        let anomalies = match self.fields.add_documents(
+            &self.songs,
+            self.popularity.as_deref(),
+            self.scale_aliases.as_deref(),
+            &options,
+            &index_writer,
+            self.indexing.pause,
+        ) {
+            Ok(anomalies) => anomalies,
+            Err(e) => {
+                index_writer.rollback()?;
+                return Err(e);
+            }
+        };
+        commit(&mut index_writer)?;
+        self.reader.read().unwrap().reload()?;
+        *self.anomalies.lock().unwrap() = anomalies;
+        self.stale.store(false, Ordering::Relaxed);
+        if let Distribution::Publish(dir) = &self.distribution {
+            let name = SnapshotDir::new(dir).publish(&self.index)?;
+            info!("Published snapshot {}", name);
+        }
+        Ok(self.searcher().num_docs())
+    }
+
+    /// Switch to the latest snapshot, if this is a follower and a newer one has been
+    /// published. Returns whether it switched.
+    pub fn sync(&self) -> Result<bool> {
+        let snapshots = match &self.distribution {
+            Distribution::Follow(dir) => SnapshotDir::new(dir),
+            _ => return Ok(false),
+        };
+        let mut served = self.snapshot.lock().unwrap();
+        let latest = match snapshots.latest()? {
+            Some(latest) if served.as_ref() != Some(&latest) => latest,
+            _ => return Ok(false),
+        };
+        let mut index = snapshots.open(&latest)?;
+        // Published by an instance of another version, which followers can't rebuild.
+        if let Some(mismatch) = schema_mismatch(&index, &self.schema)? {
+            let message = format!("Refusing snapshot {}: {}", latest, mismatch);
+            return Err(TantivyError::SchemaError(message).into());
+        }
+        for (_, entry) in self.schema.fields() {
+            if let FieldType::Str(options) = entry.field_type() {
+                if let Some(indexing) = options.get_indexing_options() {
+                    let name = indexing.tokenizer();
+                    if let Some(analyzer) = self.tokenizers.get(name) {
+                        index.tokenizers().register(name, analyzer);
+                    }
+                }
+            }
+        }
+        if let Some(search_threads) = self.search_threads {
+            index.set_multithread_executor(search_threads)?;
+        }
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::Manual)
+            .try_into()?;
+        *self.reader.write().unwrap() = reader;
+        info!("Switched to snapshot {}", latest);
+        *served = Some(latest);
+        Ok(true)
+    }
+
+    fn searcher(&self) -> LeasedItem<Searcher> {
+        self.reader.read().unwrap().searcher()
+    }
+
+    fn query_parser(&self, mode: Mode) -> (&QueryParsers, usize) {
+        match mode {
+            Mode::Full => (&self.full_query_parsers, 1000),
+            Mode::Ngram => (&self.ngram_query_parsers, 15),
+            Mode::Exact => (&self.exact_query_parsers, 1000),
+        }
+    }
+
+    /// The query parsers of the mode over the fields of the scope.
+    fn scoped_query_parser(&self, mode: Mode, scope: Scope) -> &QueryParsers {
+        match (scope, mode) {
+            (Scope::All, _) => self.query_parser(mode).0,
+            (Scope::Name, Mode::Full) => &self.name_full_query_parsers,
+            (Scope::Name, Mode::Ngram) => &self.name_query_parsers,
+            (Scope::Name, Mode::Exact) => &self.name_exact_query_parsers,
+        }
+    }
+
+    fn parse(&self, query: &str, mode: Mode) -> Result<Box<dyn Query>> {
+        self.parse_in(query, mode, Scope::All)
+    }
+
+    /// Normalize the query and parse it with the query parser of the mode and scope, or return
+    /// the cached query if the same normalized query has been parsed recently.
+    fn parse_in(&self, query: &str, mode: Mode, scope: Scope) -> Result<Box<dyn Query>> {
+        let query = normalize_query(query, mode != Mode::Exact);
+        self.query_cache.get_or_parse(mode, scope, &query, || {
+            self.parse_normalized(&query, mode, scope)
+        })
+    }
+
+    fn parse_normalized(&self, query: &str, mode: Mode, scope: Scope) -> Result<Box<dyn Query>> {
+        let query_parsers = self.scoped_query_parser(mode, scope);
+        let script = detect_script(query);
+        if mode == Mode::Ngram && scope == Scope::All {
+            return self.parse_as_you_type(query, script);
+        }
+        if mode != Mode::Full {
+            return Ok(query_parsers.get(script).parse_query(query)?);
+        }
+        // Greeklish queries also search the Greek fields, through their Greek candidates.
+        let parsed_query = if script == Script::Latin {
+            let expanded_query = self.transliteration.expand_query(query);
+            query_parsers
+                .get(Script::Mixed)
+                .parse_query(&expanded_query)?
+        } else {
+            query_parsers.get(script).parse_query(query)?
+        };
+        Ok(self.boost_shingles(parsed_query, query))
+    }
+
+    /// Parse an autocomplete query, matching the words that have already been typed against the
+    /// full-word fields and only the last one, which may be incomplete, against the ngram
+    /// fields. Otherwise the completed words also match every word that starts with them. The
+    /// last word keeps its `-` or `+`. Queries with phrases or operators, or whose last word
+    /// isn't a plain word, are matched against the ngram fields as a whole. Parentheses that
+    /// haven't been closed yet are closed.
+    fn parse_as_you_type(&self, query: &str, script: Script) -> Result<Box<dyn Query>> {
+        let unclosed = query
+            .matches('(')
+            .count()
+            .saturating_sub(query.matches(')').count());
+        let query = format!("{}{}", query.trim(), ")".repeat(unclosed));
+        let ngram_query_parser = self.ngram_query_parsers.get(script);
+        let whole = || Ok(ngram_query_parser.parse_query(&query)?);
+        let (words, last_word) = match query.rsplit_once(' ') {
+            Some(split) if !query.contains('"') => split,
+            _ => return whole(),
+        };
+        let (occur, last_word) = match last_word.strip_prefix('-') {
+            Some(last_word) => (Occur::MustNot, last_word),
+            None => (
+                Occur::Must,
+                last_word.strip_prefix('+').unwrap_or(last_word),
+            ),
+        };
+        let is_operator = |word: &str| matches!(word, "AND" | "OR" | "NOT" | "TO");
+        let is_plain = !last_word.is_empty() && last_word.chars().all(char::is_alphanumeric);
+        if !is_plain || query.split(' ').any(is_operator) {
+            return whole();
+        }
+        let words = match self.full_query_parsers.get(script).parse_query(words) {
+            Ok(words) => words,
+            Err(_) => return whole(),
+        };
+        let last_word = ngram_query_parser.parse_query(last_word)?;
+        Ok(Box::new(BooleanQuery::from(vec![
+            (Occur::Must, words),
+            (occur, last_word),
+        ])))
+    }
+
+    /// Return a query for the documents whose name or slug starts with the text, matched
+    /// against the fields that hold the prefixes of whole names.
+    fn prefix_query(&self, text: &str) -> Option<Box<dyn Query>> {
+        let mut clauses: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+        for &(field, tokenizer) in &[
+            (self.fields.name.prefix(), "el_prefix"),
+            (self.fields.slug.prefix(), "en_prefix"),
+        ] {
+            let mut token_stream = match self.tokenizers.get(tokenizer) {
+                Some(tokenizer) => tokenizer.token_stream(text),
+                None => continue,
+            };
+            // The longest prefix is the whole text.
+            let mut prefix = None;
+            while token_stream.advance() {
+                prefix = Some(token_stream.token().text.clone());
+            }
+            if let Some(prefix) = prefix {
+                let term = Term::from_field_text(field, &prefix);
+                let term_query = TermQuery::new(term, IndexRecordOption::WithFreqs);
+                clauses.push((Occur::Should, Box::new(term_query)));
+            }
+        }
+        if clauses.is_empty() {
+            return None;
+        }
+        Some(Box::new(BooleanQuery::from(clauses)))
+    }
+
+    /// Return a query for the documents whose name or slug contains the words of the text, or
+    /// prefixes of them.
+    fn name_query(&self, text: &str) -> Option<Box<dyn Query>> {
+        let text = normalize_query(text, true);
+        let script = detect_script(&text);
+        self.name_query_parsers.get(script).parse_query(&text).ok()
+    }
+
+    /// Make documents that contain query words close to each other rank higher, by adding the
+    /// word pairs of the query as optional clauses on the shingle fields, which hold consecutive
+    /// words, and on the proximity field of the lyrics, which holds words a few positions apart.
+    /// The pairs are made of the words the documents should contain, without the query syntax.
+    fn boost_shingles(&self, query: Box<dyn Query>, text: &str) -> Box<dyn Query> {
+        let text = strip_query_syntax(text);
+        let mut clauses: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+        for &(field, tokenizer) in &[
+            (self.fields.name.shingle(), "el_shingle"),
+            (self.fields.body.shingle(), "el_shingle"),
+            (self.fields.body.near(), "el_near"),
+        ] {
+            let mut token_stream = match self.tokenizers.get(tokenizer) {
+                Some(tokenizer) => tokenizer.token_stream(&text),
+                None => continue,
+            };
+            while token_stream.advance() {
+                let term = Term::from_field_text(field, &token_stream.token().text);
+                let term_query = TermQuery::new(term, IndexRecordOption::WithFreqs);
+                clauses.push((Occur::Should, Box::new(term_query)));
+            }
+        }
+        if clauses.is_empty() {
+            return query;
+        }
+        Box::new(BooleanQuery::from(vec![
+            (Occur::Must, query),
+            (
+                Occur::Should,
+                Box::new(BooleanQuery::from(clauses)) as Box<dyn Query>,
+            ),
+        ]))
+    }
+
+    /// Parse the query, and if nothing matches it, try again as if it had been typed with the
+    /// other keyboard layout, e.g. "tsitsanhs" for "τσιτσανης". The original query
+    /// is kept if the swapped one doesn't match anything either. Exact searches have no
+    /// fallback, since swapping the layout drops the accents that they match.
+    fn parse_with_fallback(
+        &self,
+        searcher: &Searcher,
+        query: &str,
+        mode: Mode,
+        scope: Scope,
+    ) -> Result<Box<dyn Query>> {
+        let parsed_query = self.parse_in(query, mode, scope)?;
+        if mode == Mode::Exact || searcher.search(&parsed_query, &Count)? > 0 {
+            return Ok(parsed_query);
+        }
+        match self.parse_in(&swap_keyboard_layout(query), mode, scope) {
+            Ok(swapped_query) if searcher.search(&swapped_query, &Count)? > 0 => Ok(swapped_query),
+            _ => Ok(parsed_query),
+        }
+    }
+
+    /// Return the clauses that match the songs with the attributes of the filters. They don't
+    /// add to the score, so that the results are ranked by the query alone.
+    fn filter_clauses(&self, filters: &Filters) -> Vec<(Occur, Box<dyn Query>)> {
+        let mut clauses: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+        for (field, value) in [
+            (self.fields.artist_slug, &filters.artist),
+            (self.fields.scale, &filters.scale),
+            (self.fields.rhythm, &filters.rhythm),
+        ] {
+            let value = match value {
+                Some(value) => self.transliteration.to_slug(value),
+                None => continue,
+            };
+            let mut token_stream = match self.tokenizers.get("en_simple") {
+                Some(tokenizer) => tokenizer.token_stream(&value),
+                None => continue,
+            };
+            while token_stream.advance() {
+                let term = Term::from_field_text(field, &token_stream.token().text);
+                let term_query = TermQuery::new(term, IndexRecordOption::Basic);
+                let query = BoostQuery::new(Box::new(term_query), 0.0);
+                clauses.push((Occur::Must, Box::new(query)));
+            }
+        }
+        clauses
+    }
+
+    /// Parse the query like `parse_with_fallback` over the fields of the scope of the options,
+    /// restricted to the songs that pass their filters. An empty query matches every song that
+    /// passes them.
+    fn parse_filtered(
+        &self,
+        searcher: &Searcher,
+        query: &str,
+        mode: Mode,
+        options: &ResultOptions,
+    ) -> Result<Box<dyn Query>> {
+        let mut clauses = self.filter_clauses(&options.filters);
+        if clauses.is_empty() {
+            return self.parse_with_fallback(searcher, query, mode, options.scope);
+        }
+        let parsed_query: Box<dyn Query> = if query.trim().is_empty() {
+            Box::new(AllQuery)
+        } else {
+            self.parse_with_fallback(searcher, query, mode, options.scope)?
+        };
+        clauses.push((Occur::Must, parsed_query));
+        Ok(Box::new(BooleanQuery::from(clauses)))
+    }
+
+    /// Run the query, multiplying the score of each document with the boost of its type, and
+    /// apply the score cutoffs to the results of full mode searches. Ngram searches would lose
+    /// the songs that only match by a short prefix, and exact searches are made by people who
+    /// want every occurrence of the spelling.
+    fn top_docs(
+        &self,
+        searcher: &Searcher,
+        query: &dyn Query,
+        mode: Mode,
+        limit: Option<usize>,
+        variant: Variant,
+        cancel: &CancelFlag,
+    ) -> Result<Vec<(Score, DocAddress)>> {
+        let (_query_parser, default_limit) = self.query_parser(mode);
+        let limit = limit.unwrap_or(default_limit);
+        let (kind, modified, views) = (self.fields.kind, self.fields.modified, self.fields.views);
+        let scoring = self.scoring.read().unwrap().clone();
+        let ranking = scoring.ranking(variant);
+        let now = unix_now();
+        // The scoring closure can't fail, so a segment without the fast fields fails the search
+        // here instead.
+        let mut readers = HashMap::new();
+        for segment_reader in searcher.segment_readers() {
+            let fast_fields = segment_reader.fast_fields();
+            readers.insert(
+                segment_reader.segment_id(),
+                (
+                    fast_fields.u64(kind)?,
+                    fast_fields.u64(modified)?,
+                    fast_fields.u64(views)?,
+                ),
+            );
+        }
+        let collector =
+            TopDocs::with_limit(limit).tweak_score(move |segment_reader: &SegmentReader| {
+                let (kinds, modified, views) = readers[&segment_reader.segment_id()].clone();
+                let ranking = ranking.clone();
+                move |doc: DocId, score: Score| {
+                    ranking.score(&Features {
+                        relevance: score,
+                        kind: Kind::from(kinds.get(doc)),
+                        // Unknown for artists, scales and songs that weren't read from files.
+                        age: match (now, modified.get(doc)) {
+                            (_, 0) | (None, _) => None,
+                            (Some(now), modified) => Some(now.saturating_sub(modified)),
+                        },
+                        views: views.get(doc),
+                    })
+                }
+            });
+        let collector = Cancellable::new(collector, cancel.clone());
+        let mut top_docs = searcher.search(query, &collector).map_err(|e| {
+            if cancel.is_cancelled() {
+                Error::Cancelled
+            } else {
+                Error::from(e)
+            }
+        })?;
+        if mode == Mode::Full {
+            let top_score = top_docs.first().map_or(0.0, |&(score, _)| score);
+            let mut min_score = scoring.min_score.unwrap_or(Score::MIN);
+            if let Some(min_relative_score) = scoring.min_relative_score {
+                min_score = min_score.max(top_score * min_relative_score);
+            }
+            top_docs.retain(|&(score, _)| score >= min_score);
+        }
+        Ok(top_docs)
+    }
+
+    /// Return the stored fields of the document, except for the lyrics which are only stored
+    /// for highlighting.
+    fn stored_fields(&self, doc: &Document) -> HashMap<String, String> {
+        stored_values(&self.stored_layout, doc)
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    /// Reorder the results by name, artist, year or popularity. Names are compared lowercased and
+    /// without accents, documents missing the sort key go last, and ties are broken by name. Only
+    /// the collected results are reordered.
+    fn sort_results(&self, searcher: &Searcher, results: &mut Vec<Hit>, sort: Sort) -> Result<()> {
+        let collation_key = |hit: &Hit, key: &str| match hit.get(key) {
+            Some(value) => (false, to_greek_lowercase(value)),
+            None => (true, String::new()),
+        };
+        match sort {
+            Sort::Relevance => {}
+            Sort::Name => results.sort_by_cached_key(|hit| collation_key(hit, "name")),
+            Sort::Artist => results.sort_by_cached_key(|hit| {
+                (collation_key(hit, "artist"), collation_key(hit, "name"))
+            }),
+            Sort::Year | Sort::Popularity => {
+                let field = match sort {
+                    Sort::Year => self.fields.year,
+                    _ => self.fields.views,
+                };
+                let mut keyed = Vec::with_capacity(results.len());
+                for hit in results.drain(..) {
+                    let segment_reader = searcher.segment_reader(hit.address.segment_ord());
+                    let value = segment_reader
+                        .fast_fields()
+                        .u64(field)?
+                        .get(hit.address.doc());
+                    // Earliest year first, but most views first.
+                    let value = match sort {
+                        Sort::Year => value,
+                        _ => u64::MAX - value,
+                    };
+                    let key = ((value == 0, value), collation_key(&hit, "name"));
+                    keyed.push((key, hit));
+                }
+                keyed.sort_by(|(a, _), (b, _)| a.cmp(b));
+                results.extend(keyed.into_iter().map(|(_, hit)| hit));
+            }
+        }
+        Ok(())
+    }
+
+    /// Keep the first songs of each artist, up to the given number, and move the artists whose
+    /// name or slug has a word starting with every query word to the top, so that searching for
+    /// an artist returns the artist rather than a page of their songs.
+    fn group_by_artist(&self, results: &mut Vec<Hit>, text: &str, sort: Sort, per_artist: usize) {
+        let is_song = |hit: &Hit| hit.get("url").is_some_and(|url| url.starts_with("/songs/"));
+        let mut songs: HashMap<Option<String>, usize> = HashMap::new();
+        results.retain(|hit| {
+            if !is_song(hit) {
+                return true;
+            }
+            let artist = hit.get("artist").map(str::to_string);
+            let count = songs.entry(artist).or_insert(0);
+            *count += 1;
+            *count <= per_artist
+        });
+        if sort != Sort::Relevance {
+            return;
+        }
+        let query = normalize_query(text, true);
+        let query_words: Vec<&str> = query.split_whitespace().collect();
+        if query_words.is_empty() {
+            return;
+        }
+        let matches = |hit: &Hit| {
+            let is_artist = hit
+                .get("url")
+                .is_some_and(|url| url.starts_with("/artists/"));
+            let name = hit.get("name").map_or(String::new(), to_greek_lowercase);
+            let slug = hit
+                .get("slug")
+                .map_or(String::new(), |slug| slug.replace('_', " "));
+            is_artist
+                && [name, slug].iter().any(|name| {
+                    query_words.iter().all(|query_word| {
+                        name.split(|c: char| !c.is_alphanumeric())
+                            .any(|word| word.starts_with(query_word))
+                    })
+                })
+        };
+        results.sort_by_cached_key(|hit| !matches(hit));
+    }
+
+    /// Return the documents matching the parsed query, which was parsed from the text.
+    fn collect_hits(
+        &self,
+        searcher: &Searcher,
+        text: &str,
+        query: &dyn Query,
+        mode: Mode,
+        sort: Sort,
+        options: &ResultOptions,
+    ) -> Result<Vec<Hit<'_>>> {
+        if let Some(quotas) = options.quotas {
+            let (_query_parser, default_limit) = self.query_parser(mode);
+            let limit = options.limit.unwrap_or(default_limit);
+            let mut results = Vec::new();
+            for (kind, quota) in quotas.by_kind() {
+                let term = Term::from_field_u64(self.fields.kind, kind as u64);
+                let term_query = TermQuery::new(term, IndexRecordOption::Basic);
+                let kind_query = BooleanQuery::from(vec![
+                    (Occur::Must, query.box_clone()),
+                    (
+                        Occur::Must,
+                        Box::new(BoostQuery::new(Box::new(term_query), 0.0)) as Box<dyn Query>,
+                    ),
+                ]);
+                let kind_options = ResultOptions {
+                    limit: Some(quota.unwrap_or(limit).min(limit)),
+                    quotas: None,
+                    ..options.clone()
+                };
+                if kind_options.limit == Some(0) {
+                    continue;
+                }
+                let mut hits =
+                    self.collect_hits(searcher, text, &kind_query, mode, sort, &kind_options)?;
+                results.append(&mut hits);
+            }
+            results.truncate(limit);
+            return Ok(results);
+        }
+        let (_query_parser, default_limit) = self.query_parser(mode);
+        let limit = options.limit.unwrap_or(default_limit);
+        // Songs over the limit of their artist are dropped later, so more are collected.
+        let collected = match options.per_artist {
+            Some(_) => Some(limit.saturating_mul(PER_ARTIST_CANDIDATES)),
+            None => options.limit,
+        };
+        let mut top_docs = self.top_docs(
+            searcher,
+            query,
+            mode,
+            collected,
+            options.variant,
+            &options.cancel,
+        )?;
+        // Autocomplete matches in names come before matches in lyrics alone, however often the
+        // lyrics repeat the query, and matches at the start of a name come first, since the name
+        // is probably being typed.
+        if let (Mode::Ngram, Sort::Relevance) = (mode, sort) {
+            for tier in [self.name_query(text), self.prefix_query(text)]
+                .iter()
+                .flatten()
+            {
+                let tier_query = BooleanQuery::from(vec![
+                    (Occur::Must, query.box_clone()),
+                    (Occur::Must, tier.box_clone()),
+                ]);
+                let mut tier_docs = self.top_docs(
+                    searcher,
+                    &tier_query,
+                    mode,
+                    collected,
+                    options.variant,
+                    &options.cancel,
+                )?;
+                let len = top_docs.len();
+                top_docs.retain(|(_, doc)| tier_docs.iter().all(|(_, tier_doc)| tier_doc != doc));
+                tier_docs.append(&mut top_docs);
+                tier_docs.truncate(len);
+                top_docs = tier_docs;
+            }
+        }
+        let mut results = Vec::new();
+        for (_score, address) in top_docs {
+            results.push(Hit {
+                address,
+                doc: searcher.doc(address)?,
+                layout: &self.stored_layout,
+                scanned: false,
+            });
+        }
+        if sort == Sort::Relevance {
+            let ranking = self.scoring.read().unwrap().ranking(options.variant);
+            let mut ranked: Vec<(Score, Hit)> = results
+                .drain(..)
+                .map(|hit| (ranking.hit_factor(text, &hit), hit))
+                .collect();
+            // Stable, so that hits with equal factors keep their order.
+            ranked.sort_by(|(factor, _), (other, _)| other.total_cmp(factor));
+            results.extend(ranked.into_iter().map(|(_, hit)| hit));
+        }
+        // After the ranking, which would otherwise undo the grouping.
+        if let Some(per_artist) = options.per_artist {
+            self.group_by_artist(&mut results, text, sort, per_artist);
+            results.truncate(limit);
+        }
+        self.sort_results(searcher, &mut results, sort)?;
+        Ok(results)
+    }
+
+    /// Parse the text and return the parsed query with its hits. The lyrics are scanned for the
+    /// text instead if the options ask for it, or if the text is a quoted phrase that matched
+    /// nothing, since tokenized phrases ignore punctuation and elisions.
+    fn find_hits(
+        &self,
+        searcher: &Searcher,
+        text: &str,
+        mode: Mode,
+        sort: Sort,
+        options: &ResultOptions,
+    ) -> Result<(Box<dyn Query>, Vec<Hit<'_>>)> {
+        if options.substring {
+            if !self.highlights {
+                let message = "Substring searches need the stored lyrics";
+                return Err(Error::Query(String::from(message)));
+            }
+            let hits = self.scan_lyrics(searcher, text, sort, options)?;
+            return Ok((Box::new(EmptyQuery), hits));
+        }
+        let parsed_query = self.parse_filtered(searcher, text, mode, options)?;
+        let mut hits =
+            self.collect_hits(searcher, text, parsed_query.as_ref(), mode, sort, options)?;
+        if hits.is_empty() && mode != Mode::Ngram && self.highlights && is_quoted(text) {
+            hits = self.scan_lyrics(searcher, text, sort, options)?;
+        }
+        if hits.is_empty() {
+            self.zero_results(text, mode, options);
+        }
+        Ok((parsed_query, hits))
+    }
+
+    /// Log and count a search of an endpoint that found nothing. Browsing without a query isn't
+    /// counted.
+    fn zero_results(&self, text: &str, mode: Mode, options: &ResultOptions) {
+        let endpoint = match options.endpoint {
+            Some(endpoint) if !text.trim().is_empty() => endpoint,
+            _ => return,
+        };
+        *self
+            .zero_results
+            .lock()
+            .unwrap()
+            .entry(endpoint)
+            .or_default() += 1;
+        let normalized = normalize_query(text, mode != Mode::Exact)
+            .split_whitespace()
+            .collect::<Vec<&str>>()
+            .join(" ");
+        info!(
+            target: ZERO_RESULTS_TARGET,
+            "endpoint={} mode={:?} query={:?} normalized={:?}", endpoint, mode, text, normalized
+        );
+    }
+
+    /// Return the songs whose lyrics contain the text, compared lowercased, without accents and
+    /// with runs of whitespace collapsed. Every stored lyrics text that passes the filters is
+    /// read, so this is much slower than searching the index.
+    fn scan_lyrics(
+        &self,
+        searcher: &Searcher,
+        text: &str,
+        sort: Sort,
+        options: &ResultOptions,
+    ) -> Result<Vec<Hit<'_>>> {
+        let fold = |text: &str| {
+            to_greek_lowercase(&text.nfc().collect::<String>())
+                .split_whitespace()
+                .collect::<Vec<&str>>()
+                .join(" ")
+        };
+        let needle = fold(text.trim().trim_matches('"'));
+        if needle.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut clauses = self.filter_clauses(&options.filters);
+        let passing = if clauses.is_empty() {
+            None
+        } else {
+            clauses.push((Occur::Must, Box::new(AllQuery)));
+            Some(searcher.search(&BooleanQuery::from(clauses), &DocSetCollector)?)
+        };
+        let limit = options.limit.unwrap_or(1000);
+        let mut results = Vec::new();
+        'segments: for (segment_ord, segment_reader) in
+            searcher.segment_readers().iter().enumerate()
+        {
+            let store_reader = segment_reader.get_store_reader()?;
+            for doc_id in 0..segment_reader.max_doc() {
+                let address = DocAddress(segment_ord as u32, doc_id);
+                if segment_reader.is_deleted(doc_id)
+                    || passing
+                        .as_ref()
+                        .is_some_and(|passing| !passing.contains(&address))
+                {
+                    continue;
+                }
+                let doc = store_reader.get(doc_id)?;
+                let body = doc
+                    .get_first(self.fields.stored_body)
+                    .and_then(|value| value.text());
+                if body.is_some_and(|body| fold(body).contains(&needle)) {
+                    results.push(Hit {
+                        address,
+                        doc,
+                        layout: &self.stored_layout,
+                        scanned: true,
+                    });
+                    if results.len() == limit {
+                        break 'segments;
+                    }
+                }
+            }
+        }
+        self.sort_results(searcher, &mut results, sort)?;
+        Ok(results)
+    }
+
+    /// Return the byte ranges of the name and lyrics of the document that match the query. The
+    /// ranges refer to the original text, since the token filters never change token offsets.
+    /// Matches in the slug and in the greeklish of the lyrics are mapped back to the Greek
+    /// words that they were transliterated from.
+    fn highlights(&self, query: &dyn Query, doc: &Document) -> serde_json::Value {
+        let mut terms = BTreeSet::new();
+        query.query_terms(&mut terms);
+        let mut highlights = serde_json::Map::new();
+        for &(name, stored, group, greeklish) in &[
+            (
+                "name",
+                self.fields.name.simple(),
+                &self.fields.name,
+                &self.fields.slug,
+            ),
+            (
+                "body",
+                self.fields.stored_body,
+                &self.fields.body,
+                &self.fields.body_greeklish,
+            ),
+        ] {
+            let text = match doc.get_first(stored).and_then(|value| value.text()) {
+                Some(text) => text,
+                None => continue,
+            };
+            let mut ranges = self.term_ranges(group, &terms, text);
+            let matches_greeklish = terms.iter().any(|term| {
+                greeklish
+                    .fields
+                    .iter()
+                    .any(|&(_, field)| field == term.field())
+            });
+            if matches_greeklish {
+                let (greeklish_text, alignment) = self.transliteration.to_greeklish_aligned(text);
+                let greeklish_ranges = self.term_ranges(greeklish, &terms, &greeklish_text);
+                ranges.extend(
+                    greeklish_ranges
+                        .into_iter()
+                        .map(|(from, to)| (alignment[from].0, alignment[to - 1].1)),
+                );
+            }
+            ranges.sort_unstable();
+            let mut merged: Vec<(usize, usize)> = Vec::with_capacity(ranges.len());
+            for (from, to) in ranges {
+                match merged.last_mut() {
+                    Some(last) if from <= last.1 => last.1 = last.1.max(to),
+                    _ => merged.push((from, to)),
+                }
+            }
+            highlights.insert(name.to_string(), json!(merged));
+        }
+        serde_json::Value::Object(highlights)
+    }
+
+    /// Return the byte ranges of the tokens of the text that are terms of the fields of the
+    /// group, tokenizing the text with the analyzer of each field.
+    fn term_ranges(
+        &self,
+        group: &AnalyzedFields,
+        terms: &BTreeSet<Term>,
+        text: &str,
+    ) -> Vec<(usize, usize)> {
+        let mut ranges = Vec::new();
+        for &(prefix, field) in &group.fields {
+            let mut field_terms: HashSet<&[u8]> = terms
+                .iter()
+                .filter(|term| term.field() == field)
+                .map(|term| term.value_bytes())
+                .collect();
+            // Proximity pairs span the words between them, which didn't match, and name
+            // prefixes span the whole name.
+            if field_terms.is_empty() || prefix == "near_" || prefix == "prefix_" {
+                continue;
+            }
+            if prefix == "ngram_" {
+                // Every prefix of a query word is a term, but only the longest one shows
+                // which part of the word was matched.
+                let all_terms = field_terms.clone();
+                field_terms.retain(|term| {
+                    !all_terms
+                        .iter()
+                        .any(|other| other.len() > term.len() && other.starts_with(term))
+                });
+            }
+            let tokenizer = match self.schema.get_field_entry(field).field_type() {
+                FieldType::Str(options) => options
+                    .get_indexing_options()
+                    .and_then(|indexing| self.tokenizers.get(indexing.tokenizer())),
+                _ => None,
+            };
+            let mut token_stream = match tokenizer {
+                Some(tokenizer) => tokenizer.token_stream(text),
+                None => continue,
+            };
+            while token_stream.advance() {
+                let token = token_stream.token();
+                if field_terms.contains(token.text.as_bytes()) {
+                    ranges.push((token.offset_from, token.offset_to));
+                }
+            }
+        }
+        ranges
+    }
+
+    /// Return the parts of each hit that the parsed query, which was parsed from the text,
+    /// matches. Each part is checked with a subquery for the terms of the query in its fields,
+    /// which is run over the segments of the hits only. Songs are not searched by artist, so
+    /// their artist is checked with the words of the text instead. Names match as the artist on
+    /// artists, and scanned hits only match in the lyrics.
+    fn matched_parts(
+        &self,
+        searcher: &Searcher,
+        query: &dyn Query,
+        text: &str,
+        hits: &[Hit],
+    ) -> Result<Vec<Vec<&'static str>>> {
+        let mut terms = BTreeSet::new();
+        query.query_terms(&mut terms);
+        let term_clauses = |groups: &[&AnalyzedFields]| {
+            terms
+                .iter()
+                .filter(|term| {
+                    groups
+                        .iter()
+                        .any(|group| group.fields.iter().any(|&(_, field)| field == term.field()))
+                })
+                .map(|term| {
+                    let term_query = TermQuery::new(term.clone(), IndexRecordOption::Basic);
+                    (Occur::Should, Box::new(term_query) as Box<dyn Query>)
+                })
+                .collect::<Vec<(Occur, Box<dyn Query>)>>()
+        };
+        let fields = &self.fields;
+        let mut artist_clauses: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+        let slug = self.transliteration.to_slug(&normalize_query(text, true));
+        if let (false, Some(tokenizer)) = (text.trim().is_empty(), self.tokenizers.get("en_simple"))
+        {
+            let mut token_stream = tokenizer.token_stream(&slug);
+            while token_stream.advance() {
+                let term = Term::from_field_text(fields.artist_slug, &token_stream.token().text);
+                let term_query = TermQuery::new(term, IndexRecordOption::Basic);
+                artist_clauses.push((Occur::Should, Box::new(term_query)));
+            }
+        }
+        let parts = [
+            (
+                "title",
+                term_clauses(&[&fields.name, &fields.slug, &fields.aliases]),
+            ),
+            ("artist", artist_clauses),
+            (
+                "lyrics",
+                term_clauses(&[&fields.body, &fields.body_foreign]),
+            ),
+            ("greeklish", term_clauses(&[&fields.body_greeklish])),
+        ];
+
+        // Hits in document order, so that each scorer only moves forward.
+        let mut order: Vec<usize> = (0..hits.len()).collect();
+        order.sort_by_key(|&i| (hits[i].address.segment_ord(), hits[i].address.doc()));
+        let mut matched = vec![Vec::new(); hits.len()];
+        for (part, clauses) in parts {
+            if clauses.is_empty() {
+                continue;
+            }
+            let weight = BooleanQuery::from(clauses).weight(searcher, false)?;
+            let mut scorer: Option<(u32, Box<dyn Scorer>)> = None;
+            for &i in order.iter().filter(|&&i| !hits[i].scanned) {
+                let address = hits[i].address;
+                let segment_ord = address.segment_ord();
+                let scorer = match &mut scorer {
+                    Some((ord, scorer)) if *ord == segment_ord => scorer,
+                    _ => {
+                        let segment_reader = searcher.segment_reader(segment_ord);
+                        &mut scorer
+                            .insert((segment_ord, weight.scorer(segment_reader, 1.0)?))
+                            .1
+                    }
+                };
+                // Scorers start on their first match, which may be past the hit already.
+                let doc = address.doc();
+                if scorer.doc() == doc || (scorer.doc() < doc && scorer.seek(doc) == doc) {
+                    matched[i].push(part);
+                }
+            }
+        }
+        for (hit, parts) in hits.iter().zip(&mut matched) {
+            if hit.scanned {
+                parts.push("lyrics");
+            }
+            if hit.get("url").and_then(Kind::from_url) == Some(Kind::Artist) {
+                for part in parts.iter_mut().filter(|part| **part == "title") {
+                    *part = "artist";
+                }
+                parts.dedup();
+            }
+        }
+        Ok(matched)
+    }
+
+    /// Return the requested stored fields of the hits, with the highlighted ranges and the
+    /// matching parts if requested.
+    fn hits_json<'a>(
+        &self,
+        searcher: &Searcher,
+        query: &dyn Query,
+        text: &str,
+        hits: &'a [Hit<'a>],
+        options: &'a ResultOptions,
+    ) -> Result<Vec<HitJson<'a>>> {
+        let mut matched = if options.matched {
+            self.matched_parts(searcher, query, text, hits)?
+                .into_iter()
+                .map(Some)
+                .collect()
+        } else {
+            vec![None; hits.len()]
+        };
+        Ok(hits
+            .iter()
+            .zip(matched.drain(..))
+            .map(|(hit, matched)| HitJson {
+                hit,
+                fields: options.fields.as_deref(),
+                highlights: if options.highlight {
+                    Some(self.highlights(query, &hit.doc))
+                } else {
+                    None
+                },
+                matched,
+            })
+            .collect())
+    }
+
+    /// Return the stored fields of the matching documents.
+    pub fn hits(
+        &self,
+        query: &str,
+        mode: Mode,
+        sort: Sort,
+    ) -> Result<Vec<HashMap<String, String>>> {
+        let searcher = self.searcher();
+        let parsed_query = self.parse_with_fallback(&searcher, query, mode, Scope::All)?;
+        let options = ResultOptions::default();
+        let hits = self.collect_hits(
+            &searcher,
+            query,
+            parsed_query.as_ref(),
+            mode,
+            sort,
+            &options,
+        )?;
+        Ok(hits.iter().map(Hit::to_map).collect())
+    }
+
+    /// Like `search`, but wrap the results in an object together with the total number of
+    /// matching documents that pass the score cutoffs, the time the search took and the query
+    /// itself.
+    pub fn search_envelope(
+        &self,
+        query: &str,
+        mode: Mode,
+        sort: Sort,
+        options: &ResultOptions,
+    ) -> Result<String> {
+        let start = Instant::now();
+        let searcher = self.searcher();
+        let (parsed_query, hits) = self.find_hits(&searcher, query, mode, sort, options)?;
+        // Matches below the score cutoffs are never returned, so they aren't counted either,
+        // which takes scoring every match.
+        let scoring = self.scoring.read().unwrap().clone();
+        let cutoff = scoring.min_score.is_some() || scoring.min_relative_score.is_some();
+        let total = if mode == Mode::Full && cutoff {
+            let limit = (searcher.num_docs() as usize).max(1);
+            self.top_docs(
+                &searcher,
+                parsed_query.as_ref(),
+                mode,
+                Some(limit),
+                options.variant,
+                &options.cancel,
+            )?
+            .len()
+        } else {
+            searcher.search(&parsed_query, &Count)?
+        };
+        // Scanned hits don't match the parsed query.
+        let total = total.max(hits.len());
+        let envelope = Envelope {
+            results: self.hits_json(&searcher, parsed_query.as_ref(), query, &hits, options)?,
+            total,
+            took_ms: start.elapsed().as_secs_f64() * 1000.0,
+            query,
+        };
+        to_json(&envelope)
+    }
+
+    pub fn search(
+        &self,
+        query: &str,
+        mode: Mode,
+        sort: Sort,
+        options: &ResultOptions,
+    ) -> Result<String> {
+        let searcher = self.searcher();
+        let (parsed_query, hits) = self.find_hits(&searcher, query, mode, sort, options)?;
+        let results = self.hits_json(&searcher, parsed_query.as_ref(), query, &hits, options)?;
+        to_json(&results)
+    }
+
+    /// Call the callback with every indexed document. Stops early if the callback returns false.
+    fn for_each_document(&self, mut callback: impl FnMut(&Document) -> Result<bool>) -> Result<()> {
+        let searcher = self.searcher();
+        for segment_reader in searcher.segment_readers() {
+            let store_reader = segment_reader.get_store_reader()?;
+            for doc_id in 0..segment_reader.max_doc() {
+                if segment_reader.is_deleted(doc_id) {
+                    continue;
+                }
+                if !callback(&store_reader.get(doc_id)?)? {
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Pass the stored fields of every indexed document, serialized as a JSON line, to the
+    /// callback. Stops early if the callback returns false.
+    pub fn export(&self, mut callback: impl FnMut(String) -> bool) -> Result<()> {
+        self.for_each_document(|doc| {
+            let mut line = serde_json::to_string(&self.stored_fields(doc))?;
+            line.push('\n');
+            Ok(callback(line))
+        })
+    }
+
+    /// Return a sitemap with the urls of all indexed documents, prefixed by the base url.
+    pub fn sitemap(&self, base_url: &str) -> Result<String> {
+        let base_url = base_url.trim_end_matches('/');
+        let mut sitemap = String::from(concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+        ));
+        self.for_each_document(|doc| {
+            if let Some(url) = doc
+                .get_first(self.fields.url)
+                .and_then(|value| value.text())
+            {
+                let loc = format!("{}{}", base_url, url)
+                    .replace('&', "&amp;")
+                    .replace('<', "&lt;")
+                    .replace('>', "&gt;");
+                sitemap.push_str(&format!("  <url><loc>{}</loc></url>\n", loc));
+            }
+            Ok(true)
+        })?;
+        sitemap.push_str("</urlset>\n");
+        Ok(sitemap)
+    }
+
+    /// Describe every field of the schema: its tokenizer, whether it is stored and which query
+    /// parsers search it.
+    pub fn schema_info(&self) -> String {
+        let fields: Vec<_> = self
+            .schema
+            .fields()
+            .map(|(field, entry)| {
+                let tokenizer = match entry.field_type() {
+                    FieldType::Str(options) => options
+                        .get_indexing_options()
+                        .map(|indexing| indexing.tokenizer()),
+                    _ => None,
+                };
+                let mut parsers = Vec::new();
+                if self.full_fields.contains(&field) {
+                    parsers.push("full");
+                }
+                if self.ngram_fields.contains(&field) {
+                    parsers.push("ngram");
+                }
+                if self.exact_fields.contains(&field) {
+                    parsers.push("exact");
+                }
+                json!({
+                    "name": entry.name(),
+                    "tokenizer": tokenizer,
+                    "stored": entry.is_stored(),
+                    "parsers": parsers,
+                })
+            })
+            .collect();
+        serde_json::Value::from(fields).to_string()
+    }
+
+    /// Return the number of indexed documents of each type and the size of the index in bytes,
+    /// in total and per component. "stored" is the size of the stored fields that the results
+    /// are built from, which are listed in "stored_fields". "anomalies" lists the songs whose
+    /// lyrics looked misformatted when they were last indexed. "zero_results" counts the
+    /// searches without hits since startup, by endpoint.
+    pub fn stats(&self) -> Result<String> {
+        let searcher = self.searcher();
+        let mut documents = [0u64; 3];
+        for segment_reader in searcher.segment_readers() {
+            let kinds = segment_reader.fast_fields().u64(self.fields.kind)?;
+            for doc_id in 0..segment_reader.max_doc() {
+                if !segment_reader.is_deleted(doc_id) {
+                    documents[Kind::from(kinds.get(doc_id)) as usize] += 1;
+                }
+            }
+        }
+        let space_usage = searcher.space_usage()?;
+        let mut components = [0; 6];
+        for segment in space_usage.segments() {
+            components[0] += segment.termdict().total();
+            components[1] += segment.postings().total();
+            components[2] += segment.positions().total() + segment.positions_skip_idx().total();
+            components[3] += segment.fast_fields().total();
+            components[4] += segment.fieldnorms().total();
+            components[5] += segment.store().total();
+        }
+        let stored_fields: Vec<&str> = self
+            .schema
+            .fields()
+            .filter(|(_, entry)| entry.is_stored())
+            .map(|(_, entry)| entry.name())
+            .collect();
+        Ok(json!({
+            "documents": {
+                "songs": documents[Kind::Song as usize],
+                "artists": documents[Kind::Artist as usize],
+                "scales": documents[Kind::Scale as usize],
+            },
+            "bytes": {
+                "total": space_usage.total(),
+                "terms": components[0],
+                "postings": components[1],
+                "positions": components[2],
+                "fast_fields": components[3],
+                "fieldnorms": components[4],
+                "stored": components[5],
+            },
+            "stored_fields": stored_fields,
+            "previews": self.previews,
+            "raw_bodies": self.raw_bodies,
+            "greeklish_names": self.greeklish_names,
+            "highlights": self.highlights,
+            "stale": self.is_stale(),
+            "zero_results": self.zero_results.lock().unwrap().clone(),
+            "reindex_failure": self.reindex_failure().map(|failure| json!({
+                "error": failure.error,
+                "attempts": failure.attempts,
+            })),
+            "anomalies": self.anomalies().iter().map(|anomaly| json!({
+                "song": anomaly.song,
+                "problem": anomaly.problem,
+                "lines": anomaly.stats.lines,
+                "words": anomaly.stats.words,
+                "stripped_lines": anomaly.stats.stripped_lines,
+            })).collect::<Vec<_>>(),
+        })
+        .to_string())
+    }
+
+    /// Run the text through the registered tokenizer with the given name and return the
+    /// produced tokens, or None if there is no such tokenizer.
+    pub fn tokenize(&self, text: &str, tokenizer: &str) -> Option<String> {
+        let mut token_stream = self.tokenizers.get(tokenizer)?.token_stream(text);
+        let mut tokens = Vec::new();
+        while token_stream.advance() {
+            let token = token_stream.token();
+            tokens.push(json!({
+                "text": token.text,
+                "offset_from": token.offset_from,
+                "offset_to": token.offset_to,
+                "position": token.position,
+            }));
+        }
+        Some(serde_json::Value::from(tokens).to_string())
+    }
+
+    /// Parse the query with the parser of the mode and return the resulting query tree.
+    /// The indexed text field with the name.
+    fn text_field(&self, name: &str) -> Result<Field> {
+        self.schema
+            .get_field(name)
+            .filter(
+                |&field| match self.schema.get_field_entry(field).field_type() {
+                    FieldType::Str(options) => options.get_indexing_options().is_some(),
+                    _ => false,
+                },
+            )
+            .ok_or_else(|| Error::Query(format!("Unknown text field {}", name)))
+    }
+
+    /// The indexed text fields with the names, or all of them if no names are given.
+    fn text_fields(&self, names: &[String]) -> Result<Vec<Field>> {
+        if names.is_empty() {
+            return Ok(self
+                .schema
+                .fields()
+                .filter_map(|(_, entry)| self.text_field(entry.name()).ok())
+                .collect());
+        }
+        names.iter().map(|name| self.text_field(name)).collect()
+    }
+
+    /// Return the indexed terms of the field that start with the prefix, in dictionary order,
+    /// with the number of documents that contain each and the number of times that it occurs
+    /// in them. Deleted documents are not counted.
+    fn term_counts(&self, field: Field, prefix: &str) -> Result<Vec<TermCount>> {
+        let searcher = self.searcher();
+        let mut counts: BTreeMap<Vec<u8>, (u64, u64)> = BTreeMap::new();
+        for segment_reader in searcher.segment_readers() {
+            let inverted_index = segment_reader.inverted_index(field)?;
+            let mut stream = inverted_index
+                .terms()
+                .range()
+                .ge(prefix.as_bytes())
+                .into_stream()?;
+            while let Some((term, term_info)) = stream.next() {
+                if !term.starts_with(prefix.as_bytes()) {
+                    break;
+                }
+                let mut postings = inverted_index
+                    .read_postings_from_terminfo(term_info, IndexRecordOption::WithFreqs)?;
+                let count = counts.entry(term.to_vec()).or_default();
+                let mut doc = postings.doc();
+                while doc != TERMINATED {
+                    if !segment_reader.is_deleted(doc) {
+                        count.0 += 1;
+                        count.1 += u64::from(postings.term_freq());
+                    }
+                    doc = postings.advance();
+                }
+            }
+        }
+        Ok(counts
+            .into_iter()
+            .filter(|&(_, (documents, _))| documents > 0)
+            .map(|(term, (documents, occurrences))| TermCount {
+                term: String::from_utf8_lossy(&term).into_owned(),
+                documents,
+                occurrences,
+            })
+            .collect())
+    }
+
+    /// Return the indexed terms of the field that start with the prefix, with the number of
+    /// documents that contain each and the number of times that it occurs in them, most common
+    /// first and up to the limit. The prefix is compared with the terms as they were indexed,
+    /// e.g. lowercase and without accents in most fields.
+    pub fn terms(&self, field_name: &str, prefix: &str, limit: usize) -> Result<String> {
+        let field = self.text_field(field_name)?;
+        let mut terms = self.term_counts(field, prefix)?;
+        // Stable, so that terms that are equally common stay in dictionary order.
+        terms.sort_by_key(|count| Reverse(count.documents));
+        terms.truncate(limit);
+        Ok(json!({
+            "field": field_name,
+            "prefix": prefix,
+            "terms": terms,
+        })
+        .to_string())
+    }
+
+    /// Call the callback with the lines of a CSV file that lists every indexed term of the
+    /// fields, with the number of documents that contain it and the number of times that it
+    /// occurs in them, field by field and in dictionary order. All the indexed text fields are
+    /// listed if no fields are given. Stops early if the callback returns false.
+    pub fn export_vocabulary(
+        &self,
+        field_names: &[String],
+        mut callback: impl FnMut(String) -> bool,
+    ) -> Result<()> {
+        let fields = self.text_fields(field_names)?;
+        if !callback(String::from("field,term,documents,occurrences\n")) {
+            return Ok(());
+        }
+        for field in fields {
+            let name = self.schema.get_field_name(field);
+            for count in self.term_counts(field, "")? {
+                let line = format!(
+                    "{},{},{},{}\n",
+                    name,
+                    csv_value(&count.term),
+                    count.documents,
+                    count.occurrences
+                );
+                if !callback(line) {
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Call the callback with the lines of a CSV file that lists the terms of the fields that
+    /// are in a different number of documents in the other engine, such as one that was built
+    /// with other analyzers, field by field and in dictionary order. Terms that are missing
+    /// from an engine are in no documents of it. All the indexed text fields are compared if no
+    /// fields are given. Stops early if the callback returns false.
+    pub fn compare_vocabulary(
+        &self,
+        other: &SearchEngine,
+        field_names: &[String],
+        mut callback: impl FnMut(String) -> bool,
+    ) -> Result<()> {
+        if !callback(String::from("field,term,documents_a,documents_b\n")) {
+            return Ok(());
+        }
+        for field in self.text_fields(field_names)? {
+            let name = self.schema.get_field_name(field);
+            let mut counts = self.term_counts(field, "")?.into_iter().peekable();
+            let mut other_counts = other
+                .term_counts(other.text_field(name)?, "")?
+                .into_iter()
+                .peekable();
+            loop {
+                // Merge the two dictionaries, which are both sorted.
+                let order = match (counts.peek(), other_counts.peek()) {
+                    (Some(count), Some(other_count)) => count.term.cmp(&other_count.term),
+                    (Some(_), None) => std::cmp::Ordering::Less,
+                    (None, Some(_)) => std::cmp::Ordering::Greater,
+                    (None, None) => break,
+                };
+                let (term, documents, other_documents) = match order {
+                    std::cmp::Ordering::Less => {
+                        let count = counts.next().unwrap();
+                        (count.term, count.documents, 0)
+                    }
+                    std::cmp::Ordering::Greater => {
+                        let other_count = other_counts.next().unwrap();
+                        (other_count.term, 0, other_count.documents)
+                    }
+                    std::cmp::Ordering::Equal => {
+                        let count = counts.next().unwrap();
+                        let other_count = other_counts.next().unwrap();
+                        (count.term, count.documents, other_count.documents)
+                    }
+                };
+                if documents == other_documents {
+                    continue;
+                }
+                let line = format!(
+                    "{},{},{},{}\n",
+                    name,
+                    csv_value(&term),
+                    documents,
+                    other_documents
+                );
+                if !callback(line) {
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn parse_query(&self, query: &str, mode: Mode) -> Result<String> {
+        let query = self.parse(query, mode)?;
+        Ok(json!({ "query": describe_query(query.as_ref(), &self.schema) }).to_string())
+    }
+
+    /// Return only the number of documents matching the query in the scope of the options and
+    /// passing their filters, i.e. the number of results of the same search without a limit.
+    pub fn count(&self, query: &str, mode: Mode, options: &ResultOptions) -> Result<String> {
+        let searcher = self.searcher();
+        let query = self.parse_filtered(&searcher, query, mode, options)?;
+        let count = searcher.search(&query, &Count)?;
+        Ok(json!({ "count": count }).to_string())
+    }
+
+    /// Like `search`, but also return the parsed query and the score explanation of every hit.
+    pub fn explain(&self, query: &str, mode: Mode) -> Result<String> {
+        let searcher = self.searcher();
+        let query = self.parse_with_fallback(&searcher, query, mode, Scope::All)?;
+        let top_docs = self.top_docs(
+            &searcher,
+            query.as_ref(),
+            mode,
+            None,
+            Variant::A,
+            &CancelFlag::default(),
+        )?;
+        let mut results = Vec::new();
+        for (score, doc_address) in top_docs {
+            let retrieved_doc = searcher.doc(doc_address)?;
+            let explanation = query.explain(&searcher, doc_address)?;
+            results.push(json!({
+                "fields": self.stored_fields(&retrieved_doc),
+                "score": score,
+                "explanation": explanation,
+            }));
+        }
+        Ok(serde_json::to_string(&json!({
+            "query": describe_query(query.as_ref(), &self.schema),
+            "results": results,
+        }))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use std::time::Duration;
+
+    use proptest::prelude::*;
+    use serde_json::json;
+    use tantivy::query::{BooleanQuery, EmptyQuery, PhraseQuery, Query, TermQuery};
+    use tantivy::schema::{IndexRecordOption, Schema, Term, STORED, TEXT};
+    use tantivy::tokenizer::{
+        AsciiFoldingFilter, Language, LowerCaser, SimpleTokenizer, Stemmer, TextAnalyzer,
+    };
+
+    use crate::config::{Boosts, IndexingLimits, NumberPolicy};
+    use crate::error::Error;
+    use crate::greek_lower_caser::GreekLowerCaser;
+    use crate::homoglyph_filter::HomoglyphFilter;
+    use crate::number_filter::NumberFilter;
+    use crate::phonetic_filter::PhoneticFilter;
+    use crate::ranking::StoredFields;
+    use crate::search_engine::{
+        check_schema, csv_value, describe_query, file_name, to_json, Filters, Mode, Quotas,
+        ResultOptions, Scope, SearchEngine, SearchEngineBuilder, Sort, MAX_RESPONSE_BUFFER,
+        RESPONSE_BUFFER, UNPOSITIONED_PREFIXES,
+    };
+    use crate::shingle_filter::ShingleFilter;
+    use crate::song::{LyricsFilter, Song};
+    use crate::tokenizer::NgramTokenizer;
+    use crate::transliteration::Transliteration;
+
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/songs");
+
+    /// Build a search engine over the songs in tests/fixtures/songs.
+    fn fixture_engine(boosts: Boosts) -> SearchEngine {
+        SearchEngine::builder(FIXTURES)
+            .boosts(boosts)
+            .highlights(true)
+            .build()
+            .unwrap()
+    }
+
+    /// Songs with the given names, artists and lyrics, and no other metadata.
+    fn songs(songs: &[(&str, &str, &str)]) -> Vec<Song> {
+        let transliteration = Transliteration::new(NumberPolicy::Keep);
+        songs
+            .iter()
+            .map(|&(name, artist, body)| Song::new(name, artist, body, &transliteration))
+            .collect()
+    }
+
+    /// Build a search engine over the songs with the given names, artists and lyrics, kept in
+    /// RAM.
+    fn engine_with_songs(songs: &[(&str, &str, &str)]) -> SearchEngine {
+        SearchEngine::from_songs(self::songs(songs)).unwrap()
+    }
+
+    #[test]
+    #[cfg(feature = "mmap")]
+    fn test_builder() {
+        use crate::search_engine::{schema_version, SCHEMA_VERSION};
+
+        let index_dir = tempfile::tempdir().unwrap();
+        let engine = SearchEngine::builder(FIXTURES)
+            .index_dir(index_dir.path())
+            .search_threads(Some(2))
+            .analyzer("el_simple", TextAnalyzer::from(SimpleTokenizer))
+            .build()
+            .unwrap();
+        assert!(index_dir.path().join("meta.json").exists());
+        assert_eq!(schema_version(&engine.index).unwrap(), Some(SCHEMA_VERSION));
+        let tokens = engine.tokenize("Ζεϊμπέκικο", "el_simple").unwrap();
+        assert!(tokens.contains("Ζεϊμπέκικο"));
+        let hits = engine
+            .hits("μαυρα φρυδια", Mode::Full, Sort::Relevance)
+            .unwrap();
+        assert_eq!(hits[0]["url"], "/songs/ta_mple_parathyra_sou/");
+
+        let result = SearchEngine::builder(FIXTURES)
+            .analyzer("el_unknown", TextAnalyzer::from(SimpleTokenizer))
+            .build();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_from_songs() {
+        let engine = engine_with_songs(&[
+            ("Φραγκοσυριανή", "Βαμβακάρης", "Μια φούντωση μια φλόγα"),
+            ("Φραγκοσυριανή", "Άλλος", "Στη Σύρα θα πάμε"),
+        ]);
+        let urls = || {
+            engine
+                .hits("φραγκοσυριανη", Mode::Full, Sort::Relevance)
+                .unwrap()
+                .into_iter()
+                .map(|hit| hit["url"].clone())
+                .collect::<BTreeSet<String>>()
+        };
+        let expected = ["/songs/fragkosyriani/", "/songs/fragkosyriani_1481584f/"];
+        assert_eq!(urls(), expected.iter().map(|url| url.to_string()).collect());
+        assert_eq!(engine.reindex().unwrap(), 2 + 2 + 16);
+        assert_eq!(urls(), expected.iter().map(|url| url.to_string()).collect());
+    }
+
+    #[test]
+    fn test_serialized() {
+        let engine = fixture_engine(Boosts::default());
+        let serialized = engine.serialize().unwrap();
+        let copy = SearchEngineBuilder::from_serialized(&serialized)
+            .highlights(true)
+            .build()
+            .unwrap();
+        for query in &["μαυρα φρυδια", "markos peir", "\"σ' αντικρυζα\""] {
+            assert_eq!(
+                copy.search(
+                    query,
+                    Mode::Full,
+                    Sort::Relevance,
+                    &ResultOptions::default()
+                )
+                .unwrap(),
+                engine
+                    .search(
+                        query,
+                        Mode::Full,
+                        Sort::Relevance,
+                        &ResultOptions::default()
+                    )
+                    .unwrap(),
+            );
+        }
+        assert!(copy.reindex().is_err());
+        assert!(SearchEngine::from_serialized(&serialized[1..]).is_err());
+    }
+
+    #[test]
+    fn test_file_name() {
+        assert_eq!(file_name("/srv/songs/fragkosyriani"), "fragkosyriani");
+        assert_eq!(file_name("songs/fragkosyriani"), "fragkosyriani");
+        assert_eq!(file_name("fragkosyriani"), "fragkosyriani");
+    }
+
+    #[test]
+    fn test_artist_slug_collision() {
+        let engine = engine_with_songs(&[
+            ("Φραγκοσυριανή", "Βαμβακάρης", "Μια φούντωση μια φλόγα"),
+            ("Σκλάβος", "Βαμβακάρης.", "Σκλάβος σου"),
+        ]);
+        let hits = engine
+            .hits("βαμβακαρης", Mode::Full, Sort::Relevance)
+            .unwrap();
+        let artist_slugs: BTreeSet<&str> = hits
+            .iter()
+            .filter_map(|hit| hit["url"].strip_prefix("/artists/"))
+            .map(|url| url.trim_end_matches('/'))
+            .collect();
+        assert_eq!(artist_slugs.len(), 2);
+        assert!(artist_slugs.contains("vamvakaris"));
+        let other = artist_slugs.iter().find(|&&slug| slug != "vamvakaris");
+        let options = ResultOptions {
+            filters: Filters {
+                artist: other.map(|slug| slug.to_string()),
+                ..Filters::default()
+            },
+            ..ResultOptions::default()
+        };
+        let results = engine.search("", Mode::Full, Sort::Name, &options).unwrap();
+        let results: Vec<serde_json::Value> = serde_json::from_str(&results).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0]["url"], "/songs/sklavos/");
+    }
+
+    #[test]
+    fn test_cancelled_search() {
+        let engine = engine_with_songs(&[("Φραγκοσυριανή", "Βαμβακάρης", "Μια φούντωση")]);
+        let options = ResultOptions::default();
+        let search = |options: &ResultOptions| {
+            engine.search("φραγκοσυριανη", Mode::Full, Sort::Relevance, options)
+        };
+        assert!(search(&options).is_ok());
+        options.cancel.cancel();
+        assert!(matches!(search(&options), Err(Error::Cancelled)));
+    }
+
+    #[test]
+    fn test_keyboard_layout_fallback() {
+        let engine = engine_with_songs(&[("Σκλάβος", "Τσιτσάνης", "Σκλάβος σου")]);
+        let urls = |query| {
+            engine
+                .hits(query, Mode::Full, Sort::Relevance)
+                .unwrap()
+                .into_iter()
+                .map(|hit| hit["url"].clone())
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(urls("sklabow"), ["/songs/sklavos/"]);
+        // The operators and field prefixes of the query are kept.
+        assert_eq!(urls("name:sklabow AND soy"), ["/songs/sklavos/"]);
+        assert_eq!(urls("\"sklabow soy\""), ["/songs/sklavos/"]);
+
+        let explanation: serde_json::Value =
+            serde_json::from_str(&engine.explain("sklabow", Mode::Full).unwrap()).unwrap();
+        assert_eq!(explanation["results"].as_array().unwrap().len(), 1);
+
+        // Exact searches match the accents as typed, so they aren't retried.
+        let exact = engine.hits("soy", Mode::Exact, Sort::Relevance);
+        assert!(exact.unwrap().is_empty());
+        assert_eq!(
+            engine
+                .hits("Σκλάβος", Mode::Exact, Sort::Relevance)
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_exact_name() {
+        let engine = engine_with_songs(&[
+            ("Μια φούντωση μια φλόγα", "Βαμβακάρης", "Στη Σύρα θα πάμε"),
+            (
+                "Φλόγα",
+                "Βαμβακάρης",
+                "Μια φούντωση μια φλόγα\nμια φούντωση μια φλόγα\nμια φούντωση μια φλόγα",
+            ),
+        ]);
+        for &(query, mode) in &[
+            ("μια φουντωση μια φλογα", Mode::Full),
+            ("Μια φούντωση μια φλόγα", Mode::Exact),
+            ("mia fountosi mia floga", Mode::Full),
+            ("μια φουντωση μια φλογα", Mode::Ngram),
+        ] {
+            let hits = engine.hits(query, mode, Sort::Relevance).unwrap();
+            assert_eq!(
+                hits[0]["url"], "/songs/mia_fountosi_mia_floga/",
+                "{}",
+                query
+            );
+        }
+    }
+
+    #[test]
+    fn test_greeklish_phrase() {
+        let engine = fixture_engine(Boosts::default());
+        for query in &[
+            "ta mple parathyra",
+            "\"ta mple parathyra\"",
+            "\"τα μπλε παραθυρα\"",
+        ] {
+            let hits = engine.hits(query, Mode::Full, Sort::Relevance).unwrap();
+            assert_eq!(hits[0]["url"], "/songs/ta_mple_parathyra_sou/", "{}", query);
+        }
+    }
+
+    #[test]
+    fn test_foreign_verses() {
+        let transliteration = Transliteration::new(NumberPolicy::Keep);
+        let song = |name: &str, head: &str| {
+            let contents = format!(
+                "{}\nΡόζα Εσκενάζυ{}\n\nD  Ουσάκ\n\nΖεϊμπέκικο\n\nΣτη Σμύρνη σ' αγάπησα\nAman sevdiğim\n",
+                name, head
+            );
+            Song::parse(&contents, &transliteration, &LyricsFilter::default()).unwrap()
+        };
+        let engine = SearchEngine::from_songs(vec![
+            song("Σμυρνέικο", "\nlang: tr"),
+            song("Άλλο σμυρνέικο", ""),
+        ])
+        .unwrap();
+        let urls = |query, mode| {
+            engine
+                .hits(query, mode, Sort::Relevance)
+                .unwrap()
+                .into_iter()
+                .map(|hit| hit["url"].clone())
+                .collect::<Vec<String>>()
+        };
+        // Latin queries search the foreign verses of every song, and stemmed in the language of
+        // the songs that name it.
+        assert_eq!(urls("sevdigim", Mode::Full).len(), 2);
+        assert_eq!(urls("sevdi", Mode::Ngram).len(), 2);
+        assert_eq!(urls("sevdiğimi", Mode::Full), vec!["/songs/smyrneiko/"]);
+    }
+
+    #[test]
+    fn test_name_prefix() {
+        // The shorter name would rank first, since the query is a larger part of it.
+        let engine = engine_with_songs(&[
+            ("Η φλόγα", "Βαμβακάρης", "Στη Σύρα θα πάμε"),
+            (
+                "Φλόγα που καίει μέσα στην καρδιά μου",
+                "Βαμβακάρης",
+                "Στη Σύρα θα πάμε",
+            ),
+        ]);
+        for query in &["φλογ", "φλόγα π", "flog"] {
+            let hits = engine.hits(query, Mode::Ngram, Sort::Relevance).unwrap();
+            let url = "/songs/floga_pou_kaiei_mesa_stin_kardia_mou/";
+            assert_eq!(hits[0]["url"], url, "{}", query);
+        }
+        let hits = engine.hits("η φλ", Mode::Ngram, Sort::Relevance).unwrap();
+        assert_eq!(hits[0]["url"], "/songs/i_floga/");
+    }
+
+    #[test]
+    fn test_short_names() {
+        // Short lyrics that repeat the query would rank first, since long lyrics raise the
+        // average length.
+        const LYRICS: &str = "Ρε Τσιτσάνη, παίξε μου ένα ζεϊμπέκικο να σηκωθώ να το χορέψω. \
+            Παίξε Τσιτσάνη μου, παίξε το μπουζούκι σου κι εγώ θα τραγουδώ ως το πρωί. \
+            Τσιτσάνη, τα χέρια σου είναι μάγια, Τσιτσάνη τα τραγούδια σου φωτιά.";
+        let engine = engine_with_songs(&[
+            ("Κυριακή", "Βασίλης Τσιτσάνης", "Συννεφιασμένη Κυριακή"),
+            ("Ρεφρέν", "Καζαντζίδης", "Τσιτσάνη, Τσιτσάνη μου, Τσιτσάνη"),
+            ("Μια βραδιά", "Καζαντζίδης", LYRICS),
+            ("Μπουζούκι", "Καζαντζίδης", LYRICS),
+        ]);
+        for query in &["τσιτσ", "tsits", "βασιλης τσ"] {
+            let hits = engine.hits(query, Mode::Ngram, Sort::Relevance).unwrap();
+            assert_eq!(hits[0]["url"], "/artists/vasilis_tsitsanis/", "{}", query);
+        }
+        let hits = engine.hits("τσιτσ", Mode::Ngram, Sort::Relevance).unwrap();
+        assert_eq!(hits[1]["url"], "/songs/refren/");
+    }
+
+    #[test]
+    fn test_to_json() {
+        let capacity = || RESPONSE_BUFFER.with(|buffer| buffer.borrow().capacity());
+        assert_eq!(to_json(&vec!["a", "b"]).unwrap(), r#"["a","b"]"#);
+        let reused = capacity();
+        assert!(reused > 0);
+        assert_eq!(to_json(&vec![1]).unwrap(), "[1]");
+        assert_eq!(capacity(), reused);
+
+        let huge = "α".repeat(MAX_RESPONSE_BUFFER);
+        assert_eq!(to_json(&huge).unwrap().len(), huge.len() + 2);
+        assert_eq!(capacity(), 0);
+    }
+
+    #[test]
+    fn test_stats() {
+        let stats = |previews| {
+            let engine = SearchEngine::builder(FIXTURES)
+                .previews(previews)
+                .build()
+                .unwrap();
+            serde_json::from_str::<serde_json::Value>(&engine.stats().unwrap()).unwrap()
+        };
+        let with_previews = stats(true);
+        assert!(with_previews["documents"]["songs"].as_u64().unwrap() > 0);
+        assert_eq!(with_previews["documents"]["scales"], 16);
+        let bytes = &with_previews["bytes"];
+        assert!(bytes["stored"].as_u64().unwrap() > 0);
+        assert!(bytes["total"].as_u64() > bytes["stored"].as_u64());
+        let stored_fields = with_previews["stored_fields"].as_array().unwrap();
+        assert!(!stored_fields.contains(&serde_json::Value::from("slug")));
+
+        let without_previews = stats(false);
+        assert_eq!(without_previews["previews"], false);
+        assert!(without_previews["bytes"]["stored"].as_u64() < bytes["stored"].as_u64());
+        assert_eq!(without_previews["anomalies"], serde_json::json!([]));
+
+        let engine = SearchEngine::builder(FIXTURES)
+            .raw_bodies(true)
+            .build()
+            .unwrap();
+        let with_raw_bodies: serde_json::Value =
+            serde_json::from_str(&engine.stats().unwrap()).unwrap();
+        assert_eq!(with_raw_bodies["raw_bodies"], true);
+        assert!(with_raw_bodies["bytes"]["stored"].as_u64() > bytes["stored"].as_u64());
+        let hits = engine
+            .search(
+                "fragkosyriani",
+                Mode::Full,
+                Sort::Relevance,
+                &ResultOptions::default(),
+            )
+            .unwrap();
+        assert!(!hits.contains("raw_body"));
+    }
+
+    #[test]
+    fn test_anomalies() {
+        let transliteration = Transliteration::new(NumberPolicy::Keep);
+        let lyrics = LyricsFilter::default();
+        let song = |body: &str| {
+            let contents = format!(
+                "Φραγκοσυριανή\nΜάρκος Βαμβακάρης\n\nD\n\nΧασάπικο\n\n{}",
+                body
+            );
+            Song::parse(&contents, &transliteration, &lyrics).unwrap()
+        };
+        let engine = SearchEngine::from_songs(vec![
+            song("Em  F#  Bm\nEm  F#  Bm\n"),
+            song("Μια φούντωση μια φλόγα"),
+        ])
+        .unwrap();
+        let anomalies = engine.anomalies();
+        assert_eq!(anomalies.len(), 2);
+        assert_eq!(anomalies[0].problem, "no lyrics are left after stripping");
+        assert_eq!(anomalies[0].stats.stripped_lines, 2);
+        assert_eq!(anomalies[1].problem, "the lyrics are suspiciously short");
+        assert_eq!(anomalies[1].stats.words, 4);
+    }
+
+    #[test]
+    fn test_search_json() {
+        let engine = fixture_engine(Boosts::default());
+        let options = ResultOptions {
+            fields: Some(vec![String::from("url"), String::from("name")]),
+            highlight: true,
+            ..ResultOptions::default()
+        };
+        let results = engine
+            .search("φραγκοσυριανη", Mode::Full, Sort::Relevance, &options)
+            .unwrap();
+        let results: serde_json::Value = serde_json::from_str(&results).unwrap();
+        let hit = results[0].as_object().unwrap();
+        let mut names: Vec<&String> = hit.keys().collect();
+        names.sort();
+        assert_eq!(names, vec!["highlights", "name", "url"]);
+        assert_eq!(hit["url"], "/songs/fragkosyriani/");
+        assert!(hit["highlights"]["name"].as_array().is_some());
+
+        // The lyrics are only stored for highlighting.
+        let results = engine
+            .search(
+                "φραγκοσυριανη",
+                Mode::Full,
+                Sort::Relevance,
+                &ResultOptions::default(),
+            )
+            .unwrap();
+        let results: serde_json::Value = serde_json::from_str(&results).unwrap();
+        assert!(results[0].get("artist").is_some());
+        assert_eq!(results[0]["slug"], "fragkosyriani");
+        assert_eq!(results[0]["category"], "Τραγούδι");
+        assert!(results[0].get("body").is_none());
+        assert!(results[0].get("highlights").is_none());
+    }
+
+    #[test]
+    fn test_matched() {
+        let options = ResultOptions {
+            matched: true,
+            ..ResultOptions::default()
+        };
+        let matched = |engine: &SearchEngine, query, options: &ResultOptions| {
+            let results = engine
+                .search(query, Mode::Full, Sort::Relevance, options)
+                .unwrap();
+            let results: Vec<serde_json::Value> = serde_json::from_str(&results).unwrap();
+            results
+                .iter()
+                .map(|hit| {
+                    (
+                        hit["url"].as_str().unwrap().to_string(),
+                        hit["matched"].clone(),
+                    )
+                })
+                .collect::<Vec<(String, serde_json::Value)>>()
+        };
+        let engine = fixture_engine(Boosts::default());
+        let results = matched(&engine, "μαρκος", &options);
+        assert_eq!(results.len(), 2);
+        assert!(results.contains(&(
+            String::from("/songs/o_markos_ston_peiraia/"),
+            json!(["title", "lyrics"])
+        )));
+        // Artists match by name.
+        assert!(results.contains(&(
+            String::from("/artists/markos_vamvakaris/"),
+            json!(["artist"])
+        )));
+        assert_eq!(
+            matched(&engine, "floga", &options),
+            vec![(
+                String::from("/songs/fragkosyriani/"),
+                json!(["lyrics", "greeklish"])
+            )]
+        );
+        let substring = ResultOptions {
+            substring: true,
+            ..options.clone()
+        };
+        assert_eq!(
+            matched(&engine, "σ' αντίκρυζα", &substring),
+            vec![(
+                String::from("/songs/ta_mple_parathyra_sou/"),
+                json!(["lyrics"])
+            )]
+        );
+        let results = matched(&engine, "μαρκος", &ResultOptions::default());
+        assert!(results.iter().all(|(_, matched)| matched.is_null()));
+
+        let engine = engine_with_songs(&[(
+            "Μπουζούκι μου",
+            "Μάρκος Βαμβακάρης",
+            "Ο Μάρκος παίζει μπουζούκι",
+        )]);
+        // Songs match by the words of their artist.
+        assert!(matched(&engine, "μαρκος", &options).contains(&(
+            String::from("/songs/mpouzouki_mou/"),
+            json!(["artist", "lyrics"])
+        )));
+    }
+
+    #[test]
+    fn test_zero_results() {
+        let engine = fixture_engine(Boosts::default());
+        let options = ResultOptions {
+            endpoint: Some("search"),
+            ..ResultOptions::default()
+        };
+        let search = |query, mode, options: &ResultOptions| {
+            engine
+                .search(query, mode, Sort::Relevance, options)
+                .unwrap()
+        };
+        assert_eq!(search("xyzzy", Mode::Full, &options), "[]");
+        search("Xyzzy  plugh", Mode::Exact, &options);
+        search("φραγκοσυριανη", Mode::Full, &options);
+        // Searches without an endpoint or a query aren't counted.
+        search("xyzzy", Mode::Full, &ResultOptions::default());
+        let browse = ResultOptions {
+            filters: Filters {
+                artist: Some(String::from("nobody")),
+                ..Filters::default()
+            },
+            ..options.clone()
+        };
+        search("", Mode::Full, &browse);
+        let autocomplete = ResultOptions {
+            endpoint: Some("autocomplete"),
+            ..ResultOptions::default()
+        };
+        search("xyz", Mode::Ngram, &autocomplete);
+
+        let stats: serde_json::Value = serde_json::from_str(&engine.stats().unwrap()).unwrap();
+        assert_eq!(
+            stats["zero_results"],
+            json!({"autocomplete": 1, "search": 2})
+        );
+    }
+
+    #[test]
+    fn test_sort() {
+        let transliteration = Transliteration::new(NumberPolicy::Keep);
+        let song = |name: &str, artist: &str, year| {
+            Song::builder(name, artist, "Καρδιά μου πονεμένη")
+                .year(year)
+                .build(&transliteration)
+        };
+        let engine = SearchEngine::from_songs(vec![
+            song("Ωραία", "Βασίλης Τσιτσάνης", Some(1950)),
+            song("Άνοιξη", "Μάρκος Βαμβακάρης", None),
+            song("βράδυ", "Μάρκος Βαμβακάρης", Some(1936)),
+            song("Αγάπη", "Άγνωστος", Some(1936)),
+        ])
+        .unwrap();
+        let names = |sort| {
+            engine
+                .hits("καρδια", Mode::Full, sort)
+                .unwrap()
+                .into_iter()
+                .map(|hit| hit["name"].clone())
+                .collect::<Vec<String>>()
+        };
+        // Names are compared lowercased and without accents.
+        assert_eq!(names(Sort::Name), ["Αγάπη", "Άνοιξη", "βράδυ", "Ωραία"]);
+        // Songs of the same artist are ordered by name.
+        assert_eq!(names(Sort::Artist), ["Αγάπη", "Ωραία", "Άνοιξη", "βράδυ"]);
+        // Songs of the same year are ordered by name, and songs without a year go last.
+        assert_eq!(names(Sort::Year), ["Αγάπη", "βράδυ", "Ωραία", "Άνοιξη"]);
+        assert_eq!(names(Sort::Relevance).len(), 4);
+    }
+
+    #[test]
+    fn test_score_cutoffs() {
+        let count = |engine: &SearchEngine, query, mode| {
+            engine.hits(query, mode, Sort::Relevance).unwrap().len()
+        };
+        let engine = fixture_engine(Boosts::default());
+        let full = count(&engine, "μαρκος", Mode::Full);
+        let exact = count(&engine, "μάρκος", Mode::Exact);
+        let ngram = count(&engine, "μαρ", Mode::Ngram);
+        assert!(full > 1 && exact > 1 && ngram > 1);
+
+        let engine = SearchEngine::builder(FIXTURES)
+            .min_score(Some(1e9))
+            .build()
+            .unwrap();
+        assert_eq!(count(&engine, "μαρκος", Mode::Full), 0);
+        assert_eq!(count(&engine, "μάρκος", Mode::Exact), exact);
+        assert_eq!(count(&engine, "μαρ", Mode::Ngram), ngram);
+
+        // Only the hits that score as high as the top one are left.
+        let engine = SearchEngine::builder(FIXTURES)
+            .min_relative_score(Some(1.0))
+            .build()
+            .unwrap();
+        let top = count(&engine, "μαρκος", Mode::Full);
+        assert!(top >= 1 && top < full);
+        let envelope = engine
+            .search_envelope(
+                "μαρκος",
+                Mode::Full,
+                Sort::Relevance,
+                &ResultOptions::default(),
+            )
+            .unwrap();
+        let envelope: serde_json::Value = serde_json::from_str(&envelope).unwrap();
+        assert_eq!(envelope["total"], top);
+        assert_eq!(count(&engine, "μάρκος", Mode::Exact), exact);
+        assert_eq!(count(&engine, "μαρ", Mode::Ngram), ngram);
+
+        // The stricter cutoff applies.
+        let engine = SearchEngine::builder(FIXTURES)
+            .min_score(Some(0.0))
+            .min_relative_score(Some(1.0))
+            .build()
+            .unwrap();
+        assert_eq!(count(&engine, "μαρκος", Mode::Full), top);
+    }
+
+    #[test]
+    fn test_unpositioned_fields() {
+        let engine = fixture_engine(Boosts::default());
+        let searcher = engine.searcher();
+        let space_usage = searcher.space_usage().unwrap();
+        let mut unpositioned = 0;
+        for (field, entry) in engine.schema.fields() {
+            let name = entry.name();
+            if !UNPOSITIONED_PREFIXES
+                .iter()
+                .any(|prefix| name.starts_with(prefix))
+            {
+                continue;
+            }
+            unpositioned += 1;
+            assert!(!entry
+                .field_type()
+                .get_index_record_option()
+                .unwrap()
+                .has_positions());
+            for segment in space_usage.segments() {
+                assert!(segment.postings().fields().any(|(used, _)| *used == field));
+                assert!(!segment
+                    .positions()
+                    .fields()
+                    .any(|(used, bytes)| *used == field && bytes.total() > 0));
+            }
+        }
+        assert_eq!(unpositioned, 5);
+
+        // The word pairs still boost the songs with the words of the query next to each other.
+        let engine = engine_with_songs(&[
+            (
+                "Γαϊτάνια",
+                "Βασίλης Τσιτσάνης",
+                "Τα φρύδια σου τα μαύρα και γαϊτάνια",
+            ),
+            (
+                "Σκλάβος",
+                "Βασίλης Τσιτσάνης",
+                "Τα μαύρα φρύδια σου με σκλάβωσαν",
+            ),
+        ]);
+        let hits = engine
+            .hits("μαυρα φρυδια", Mode::Full, Sort::Relevance)
+            .unwrap();
+        assert_eq!(hits[0]["url"], "/songs/sklavos/");
+    }
+
+    #[test]
+    fn test_greeklish_names() {
+        let engine = SearchEngine::builder(FIXTURES)
+            .greeklish_names(true)
+            .build()
+            .unwrap();
+        let hits = engine
+            .hits("μπλε παραθυρα", Mode::Full, Sort::Relevance)
+            .unwrap();
+        assert_eq!(hits[0]["name_greeklish"], "Ta mple parathyra sou");
+        assert_eq!(hits[0]["artist_greeklish"], "Markos Vamvakaris");
+        let hits = engine
+            .hits("βαμβακαρης", Mode::Full, Sort::Relevance)
+            .unwrap();
+        let artist = hits
+            .iter()
+            .find(|hit| hit["url"] == "/artists/markos_vamvakaris/")
+            .unwrap();
+        assert_eq!(artist["name_greeklish"], "Markos Vamvakaris");
+        let hits = engine.hits("ουσακ", Mode::Full, Sort::Relevance).unwrap();
+        assert_eq!(hits[0]["name_greeklish"], "Ousak");
+
+        let engine = fixture_engine(Boosts::default());
+        let hits = engine
+            .hits("μπλε παραθυρα", Mode::Full, Sort::Relevance)
+            .unwrap();
+        assert!(!hits[0].contains_key("name_greeklish"));
+    }
+
+    #[test]
+    fn test_greeklish_highlights() {
+        let (name, body) = (
+            "Θα σπάσω κούπες",
+            "Απόψε θα σπάσω κούπες, θα σπάσω και ποτήρια",
+        );
+        let songs = songs(&[(name, "Βασίλης Τσιτσάνης", body)]);
+        let engine = SearchEngineBuilder::from_songs(songs.clone())
+            .highlights(true)
+            .build()
+            .unwrap();
+        let options = ResultOptions {
+            highlight: true,
+            ..ResultOptions::default()
+        };
+        let highlights = |engine: &SearchEngine, query, mode, field, text: &'static str| {
+            let results = engine
+                .search(query, mode, Sort::Relevance, &options)
+                .unwrap();
+            let results: serde_json::Value = serde_json::from_str(&results).unwrap();
+            results[0]["highlights"][field]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|range| {
+                    let from = range[0].as_u64().unwrap() as usize;
+                    let to = range[1].as_u64().unwrap() as usize;
+                    &text[from..to]
+                })
+                .collect::<Vec<&str>>()
+        };
+        // Latin prefixes only match the greeklish of the lyrics and the slug.
+        assert_eq!(
+            highlights(&engine, "koup", Mode::Ngram, "body", body),
+            vec!["κούπ"]
+        );
+        assert_eq!(
+            highlights(&engine, "koup", Mode::Ngram, "name", name),
+            vec!["κούπ"]
+        );
+        assert_eq!(
+            highlights(&engine, "spaso", Mode::Ngram, "body", body),
+            vec!["σπάσω", "σπάσω"]
+        );
+        assert_eq!(
+            highlights(&engine, "spaso potiria", Mode::Full, "body", body),
+            vec!["σπάσω", "σπάσω", "ποτήρια"]
+        );
+
+        // Without the stored lyrics only the name is highlighted.
+        let engine = SearchEngineBuilder::from_songs(songs).build().unwrap();
+        assert_eq!(
+            highlights(&engine, "koup", Mode::Ngram, "name", name),
+            vec!["κούπ"]
+        );
+        let results = engine
+            .search("koup", Mode::Ngram, Sort::Relevance, &options)
+            .unwrap();
+        let results: serde_json::Value = serde_json::from_str(&results).unwrap();
+        assert!(results[0]["highlights"].get("body").is_none());
+    }
+
+    #[test]
+    fn test_terms() {
+        let engine = fixture_engine(Boosts::default());
+        let terms = |field, prefix, limit| {
+            let terms: serde_json::Value =
+                serde_json::from_str(&engine.terms(field, prefix, limit).unwrap()).unwrap();
+            terms["terms"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|term| {
+                    let documents = term["documents"].as_u64().unwrap();
+                    let occurrences = term["occurrences"].as_u64().unwrap();
+                    (
+                        term["term"].as_str().unwrap().to_string(),
+                        documents,
+                        occurrences,
+                    )
+                })
+                .collect::<Vec<(String, u64, u64)>>()
+        };
+        assert_eq!(terms("body", "μου", 100), vec![(String::from("μου"), 2, 5)]);
+        // Equally common terms are in dictionary order.
+        let common = terms("body", "", 3);
+        assert_eq!(common[0], (String::from("και"), 3, 4));
+        assert_eq!(common[1].0, "καρδια");
+        assert_eq!(common[2].0, "με");
+        assert!(terms("slug", "zzz", 100).is_empty());
+        assert!(engine.terms("kind", "", 100).is_err());
+        assert!(engine.terms("lyrics", "", 100).is_err());
+    }
+
+    #[test]
+    fn test_export_vocabulary() {
+        let engine = fixture_engine(Boosts::default());
+        let export = |fields: &[&str]| {
+            let fields: Vec<String> = fields.iter().map(|&field| String::from(field)).collect();
+            let mut lines = Vec::new();
+            engine
+                .export_vocabulary(&fields, |line| {
+                    lines.push(line);
+                    true
+                })
+                .map(|_| lines)
+        };
+        let lines = export(&["body", "slug"]).unwrap();
+        assert_eq!(lines[0], "field,term,documents,occurrences\n");
+        assert!(lines.contains(&String::from("body,μου,2,5\n")));
+        assert!(lines.contains(&String::from("slug,fragkosyriani,1,1\n")));
+        // Terms are listed field by field, in dictionary order.
+        let body: Vec<&String> = lines[1..]
+            .iter()
+            .take_while(|line| line.starts_with("body,"))
+            .collect();
+        assert!(body.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(lines[body.len() + 1..]
+            .iter()
+            .all(|line| line.starts_with("slug,")));
+        assert!(export(&[])
+            .unwrap()
+            .iter()
+            .any(|line| line.starts_with("ngram_name,")));
+        assert!(export(&["body", "kind"]).is_err());
+        assert_eq!(csv_value("μου"), "μου");
+        assert_eq!(csv_value("a, \"b\""), "\"a, \"\"b\"\"\"");
+    }
+
+    #[test]
+    fn test_compare_vocabulary() {
+        let songs = songs(&[(
+            "Μινόρε του 1936",
+            "Μάρκος Βαμβακάρης",
+            "Το 1936 στον Πειραιά",
+        )]);
+        let engine = |numbers| {
+            SearchEngineBuilder::from_songs(songs.clone())
+                .numbers(numbers)
+                .build()
+                .unwrap()
+        };
+        let (keep, drop) = (engine(NumberPolicy::Keep), engine(NumberPolicy::Drop));
+        let compare = |fields: &[&str]| {
+            let fields: Vec<String> = fields.iter().map(|&field| String::from(field)).collect();
+            let mut lines = Vec::new();
+            keep.compare_vocabulary(&drop, &fields, |line| {
+                lines.push(line);
+                true
+            })
+            .map(|_| lines)
+        };
+        assert_eq!(
+            compare(&["body", "name"]).unwrap(),
+            vec![
+                "field,term,documents_a,documents_b\n",
+                "body,1936,1,0\n",
+                "name,1936,1,0\n",
+            ]
+        );
+        // Only the header if nothing differs.
+        assert_eq!(compare(&["artist_slug"]).unwrap().len(), 1);
+        assert!(compare(&["kind"]).is_err());
+    }
 
-use tempfile::tempdir;
+    #[test]
+    fn test_per_artist() {
+        const LYRICS: &str = "Μάρκο, παίξε μου μπουζούκι";
+        let engine = engine_with_songs(&[
+            ("Ο Μάρκος μάγκας", "Στράτος Παγιουμτζής", LYRICS),
+            ("Ο Μάρκος στον Πειραιά", "Στράτος Παγιουμτζής", LYRICS),
+            ("Μάρκο μου", "Στράτος Παγιουμτζής", LYRICS),
+            ("Φραγκοσυριανή", "Μάρκος Βαμβακάρης", LYRICS),
+            ("Μάρκος", "Στράτος Παγιουμτζής", LYRICS),
+        ]);
+        let hits = |text, mode, per_artist, limit| {
+            let searcher = engine.searcher();
+            let query = engine.parse(text, mode).unwrap();
+            let options = ResultOptions {
+                per_artist,
+                limit,
+                ..ResultOptions::default()
+            };
+            engine
+                .collect_hits(
+                    &searcher,
+                    text,
+                    query.as_ref(),
+                    mode,
+                    Sort::Relevance,
+                    &options,
+                )
+                .unwrap()
+                .into_iter()
+                .map(|hit| hit.get("url").unwrap().to_string())
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(hits("μαρκ", Mode::Ngram, None, None).len(), 6);
+        let urls = hits("μαρκ", Mode::Ngram, Some(2), None);
+        assert_eq!(urls.len(), 4);
+        assert_eq!(urls[0], "/artists/markos_vamvakaris/");
+        assert!(urls.contains(&String::from("/songs/fragkosyriani/")));
+        assert_eq!(
+            hits("μαρκ", Mode::Ngram, Some(2), Some(1)),
+            ["/artists/markos_vamvakaris/"]
+        );
 
-use crate::greek_lower_caser::GreekLowerCaser;
-use crate::song::Song;
-use crate::tokenizer::NgramTokenizer;
-use crate::utils::to_greeklish;
+        // The song named exactly like the query doesn't move above the artist.
+        assert_eq!(hits("μαρκος", Mode::Full, None, None)[0], "/songs/markos/");
+        let urls = hits("μαρκος", Mode::Full, Some(2), None);
+        assert_eq!(urls[0], "/artists/markos_vamvakaris/");
+        assert_eq!(urls[1], "/songs/markos/");
+    }
 
-fn get_options(tokenizer: &str) -> TextOptions {
-    let text_field_indexing = TextFieldIndexing::default()
-        .set_tokenizer(tokenizer)
-        .set_index_option(IndexRecordOption::WithFreqsAndPositions);
+    #[test]
+    fn test_recency() {
+        use std::fs::File;
+        use std::io::Write;
+        use std::time::{Duration, SystemTime};
 
-    TextOptions::default().set_indexing_options(text_field_indexing)
-}
+        let dir = tempfile::tempdir().unwrap();
+        let age = Duration::from_secs(365 * 24 * 60 * 60);
+        for (filename, artist, modified) in [
+            ("a", "Μάρκος Βαμβακάρης", SystemTime::now() - age),
+            ("b", "Βασίλης Τσιτσάνης", SystemTime::now()),
+        ] {
+            let mut file = File::create(dir.path().join(filename)).unwrap();
+            write!(
+                file,
+                "Καρδιά μου\n{}\n\nD  Ματζόρε\n\nΖεϊμπέκικο\n\n",
+                artist
+            )
+            .unwrap();
+            writeln!(file, "Καρδιά μου πονεμένη").unwrap();
+            file.set_modified(modified).unwrap();
+        }
+        let artists = |recency| {
+            let boosts = Boosts {
+                recency,
+                ..Boosts::default()
+            };
+            SearchEngine::builder(dir.path().to_str().unwrap())
+                .boosts(boosts)
+                .build()
+                .unwrap()
+                .hits("καρδια", Mode::Ngram, Sort::Relevance)
+                .unwrap()
+                .into_iter()
+                .map(|hit| hit["artist"].clone())
+                .collect::<Vec<String>>()
+        };
+        // The songs tie otherwise, and the older one was indexed first.
+        assert_eq!(artists(0.0), vec!["Μάρκος Βαμβακάρης", "Βασίλης Τσιτσάνης"]);
+        assert_eq!(artists(0.1), vec!["Βασίλης Τσιτσάνης", "Μάρκος Βαμβακάρης"]);
+    }
 
-#[derive(Clone)]
-pub struct SearchEngine {
-    index: Index,
-    reader: IndexReader,
-    full_query_parser: QueryParser,
-    ngram_query_parser: QueryParser,
-    schema: Schema,
-}
+    #[test]
+    fn test_popularity() {
+        use std::io::Write;
 
-impl SearchEngine {
-    pub fn new(song_dir: &str) -> tantivy::Result<SearchEngine> {
-        // Build tokenizers
-        let greek_ngram_tokenizer = TextAnalyzer::from(NgramTokenizer)
-            .filter(RemoveLongFilter::limit(40))
-            .filter(GreekLowerCaser);
-
-        let english_ngram_tokenizer = TextAnalyzer::from(NgramTokenizer)
-            .filter(RemoveLongFilter::limit(40))
-            .filter(LowerCaser);
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "slug,views\nkardia_mou_2,500").unwrap();
+        let songs = songs(&[
+            ("Καρδιά μου", "Μάρκος Βαμβακάρης", "Καρδιά μου πονεμένη"),
+            ("Καρδιά μου 2", "Βασίλης Τσιτσάνης", "Καρδιά μου πονεμένη"),
+        ]);
+        let artists = |popularity| {
+            let boosts = Boosts {
+                popularity,
+                ..Boosts::default()
+            };
+            SearchEngineBuilder::from_songs(songs.clone())
+                .popularity(Some(file.path().to_path_buf()))
+                .boosts(boosts)
+                .build()
+                .unwrap()
+                .hits("πονεμ", Mode::Ngram, Sort::Relevance)
+                .unwrap()
+                .into_iter()
+                .map(|hit| hit["artist"].clone())
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(artists(0.0), vec!["Μάρκος Βαμβακάρης", "Βασίλης Τσιτσάνης"]);
+        assert_eq!(artists(0.1), vec!["Βασίλης Τσιτσάνης", "Μάρκος Βαμβακάρης"]);
+    }
 
-        let greek_simple_tokenizer = TextAnalyzer::from(SimpleTokenizer)
-            .filter(RemoveLongFilter::limit(40))
-            .filter(GreekLowerCaser);
+    #[test]
+    fn test_filters() {
+        let engine = fixture_engine(Boosts::default());
+        let urls = |query, scale: Option<&str>, rhythm: Option<&str>| {
+            let options = ResultOptions {
+                filters: Filters {
+                    scale: scale.map(String::from),
+                    rhythm: rhythm.map(String::from),
+                    ..Filters::default()
+                },
+                ..ResultOptions::default()
+            };
+            let results = engine
+                .search(query, Mode::Full, Sort::Name, &options)
+                .unwrap();
+            let results: Vec<serde_json::Value> = serde_json::from_str(&results).unwrap();
+            results
+                .iter()
+                .map(|hit| hit["url"].as_str().unwrap().to_string())
+                .collect::<Vec<String>>()
+        };
+        let ousak = vec![
+            "/songs/o_markos_ston_peiraia/",
+            "/songs/synnefiasmeni_kyriaki/",
+        ];
+        assert_eq!(urls("", Some("ousak"), Some("zeimpekiko")), ousak);
+        assert_eq!(urls("", Some("Ουσάκ"), None), ousak);
+        assert_eq!(urls("", Some("usak"), None), ousak);
+        assert_eq!(urls("", None, Some("zeimpekiko")).len(), 3);
+        assert_eq!(
+            urls("", None, Some("zeimpekiko_palio")),
+            vec!["/songs/ta_mple_parathyra_sou/"]
+        );
+        assert_eq!(
+            urls("κυριακη", Some("ousak"), None),
+            vec!["/songs/synnefiasmeni_kyriaki/"]
+        );
+        assert!(urls("κυριακη", Some("matzore"), None).is_empty());
+        assert!(urls("", None, None).is_empty());
+    }
 
-        let english_simple_tokenizer = TextAnalyzer::from(SimpleTokenizer)
-            .filter(RemoveLongFilter::limit(40))
-            .filter(LowerCaser);
+    #[test]
+    fn test_name_scope() {
+        let engine = fixture_engine(Boosts::default());
+        let urls = |query, mode, scope| {
+            let options = ResultOptions {
+                scope,
+                ..ResultOptions::default()
+            };
+            let results = engine
+                .search(query, mode, Sort::Relevance, &options)
+                .unwrap();
+            let results: Vec<serde_json::Value> = serde_json::from_str(&results).unwrap();
+            results
+                .iter()
+                .map(|hit| hit["url"].as_str().unwrap().to_string())
+                .collect::<Vec<String>>()
+        };
+        // Only in the lyrics.
+        assert!(!urls("καμαρωνα", Mode::Full, Scope::All).is_empty());
+        assert!(urls("καμαρωνα", Mode::Full, Scope::Name).is_empty());
+        assert!(urls("καμάρωνα", Mode::Exact, Scope::Name).is_empty());
+        assert!(urls("καμαρ", Mode::Ngram, Scope::Name).is_empty());
 
-        let greek_stem_tokenizer = TextAnalyzer::from(SimpleTokenizer)
-            .filter(RemoveLongFilter::limit(40))
-            .filter(GreekLowerCaser)
-            .filter(Stemmer::new(Language::Greek));
+        let parathyra = vec!["/songs/ta_mple_parathyra_sou/"];
+        assert_eq!(urls("παράθυρά", Mode::Full, Scope::Name), parathyra);
+        assert_eq!(urls("parathyra", Mode::Full, Scope::Name), parathyra);
+        assert_eq!(urls("παράθυρά", Mode::Exact, Scope::Name), parathyra);
+        assert_eq!(urls("μπλε παραθ", Mode::Ngram, Scope::Name), parathyra);
+    }
 
-        // Build schema
-        let mut schema_builder = Schema::builder();
+    #[test]
+    fn test_substring() {
+        let engine = fixture_engine(Boosts::default());
+        let urls = |query, options: &ResultOptions| {
+            let results = engine
+                .search(query, Mode::Full, Sort::Relevance, options)
+                .unwrap();
+            let results: Vec<serde_json::Value> = serde_json::from_str(&results).unwrap();
+            results
+                .iter()
+                .map(|hit| hit["url"].as_str().unwrap().to_string())
+                .collect::<Vec<String>>()
+        };
+        let parathyra = vec!["/songs/ta_mple_parathyra_sou/"];
+        let substring = ResultOptions {
+            substring: true,
+            ..ResultOptions::default()
+        };
+        assert_eq!(urls("Σ' ΑΝΤΙΚΡΥΖΑ", &substring), parathyra);
+        assert_eq!(urls("\"αντίκρυζα  ψηλ\"", &substring), parathyra);
+        assert!(urls("σ αντικρυζα", &substring).is_empty());
+        let filtered = ResultOptions {
+            filters: Filters {
+                rhythm: Some(String::from("xasapiko")),
+                ..Filters::default()
+            },
+            ..substring.clone()
+        };
+        assert!(urls("σ' αντικρυζα", &filtered).is_empty());
 
-        // Full word fields
-        let name = schema_builder.add_text_field("name", get_options("el_simple") | STORED);
-        let slug = schema_builder.add_text_field("slug", get_options("en_simple") | STORED);
-        let body = schema_builder.add_text_field("body", get_options("el_simple"));
-        let body_greeklish =
-            schema_builder.add_text_field("body_greeklish", get_options("en_simple"));
+        // The lyrics are only stored for substring searches if they are highlighted.
+        let engine = SearchEngine::builder(FIXTURES).build().unwrap();
+        let results = engine.search("σ' αντίκρυζα", Mode::Full, Sort::Relevance, &substring);
+        assert!(matches!(results, Err(Error::Query(_))));
 
-        // Ngram fields
-        let ngram_name = schema_builder.add_text_field("ngram_name", get_options("el_ngram"));
-        let ngram_slug = schema_builder.add_text_field("ngram_slug", get_options("en_ngram"));
-        let ngram_body = schema_builder.add_text_field("ngram_body", get_options("el_ngram"));
-        let ngram_body_greeklish =
-            schema_builder.add_text_field("body_greeklish", get_options("en_ngram"));
+        // Quoted phrases that match nothing fall back to the scan.
+        let options = ResultOptions::default();
+        assert_eq!(urls("\"ντίκρυζα ψηλ\"", &options), parathyra);
+        assert!(urls("ντίκρυζα ψηλ", &options).is_empty());
+    }
 
-        // Stemmed fields
-        let stemmed_name = schema_builder.add_text_field("stemmed_name", get_options("el_stem"));
-        let stemmed_body = schema_builder.add_text_field("stemmed_body", get_options("el_stem"));
+    #[test]
+    fn test_quotas() {
+        let engine = fixture_engine(Boosts::default());
+        let urls = |quotas: Option<&str>| {
+            let options = ResultOptions {
+                quotas: quotas.map(|quotas| quotas.parse().unwrap()),
+                ..ResultOptions::default()
+            };
+            let results = engine
+                .search("μα", Mode::Ngram, Sort::Relevance, &options)
+                .unwrap();
+            let results: Vec<serde_json::Value> = serde_json::from_str(&results).unwrap();
+            results
+                .iter()
+                .map(|hit| hit["url"].as_str().unwrap().to_string())
+                .collect::<Vec<String>>()
+        };
+        let all = urls(None);
+        assert!(all.len() > 2);
+        let quoted = urls(Some("songs:1,artists:1,scales:0"));
+        assert_eq!(quoted.len(), 2);
+        assert!(quoted[0].starts_with("/songs/"));
+        assert_eq!(quoted[1], "/artists/markos_vamvakaris/");
+        // Types without a quota fill up to the limit.
+        assert_eq!(urls(Some("artists:0")).len(), all.len() - 1);
+        assert!("songs:1,lyrics:1".parse::<Quotas>().is_err());
+        assert!("songs".parse::<Quotas>().is_err());
+    }
 
-        // Keyword fields
-        let url = schema_builder.add_text_field("url", STORED);
+    #[test]
+    fn test_browse() {
+        use std::io::Write;
 
-        let schema = schema_builder.build();
+        let transliteration = Transliteration::new(NumberPolicy::Keep);
+        let song = |name: &str, artist: &str| {
+            Song::builder(name, artist, "Καρδιά μου πονεμένη")
+                .rhythm(Some(String::from("Ζεϊμπέκικο")))
+                .build(&transliteration)
+        };
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "slug,views\nkardia_mou_2,500\nkardia_mou_3,20").unwrap();
+        let engine = SearchEngineBuilder::from_songs(vec![
+            song("Καρδιά μου", "Μάρκος Βαμβακάρης"),
+            song("Καρδιά μου 2", "Βασίλης Τσιτσάνης"),
+            song("Καρδιά μου 3", "Βασίλης Τσιτσάνης"),
+        ])
+        .popularity(Some(file.path().to_path_buf()))
+        .build()
+        .unwrap();
+        let urls = |sort, filters| {
+            let options = ResultOptions {
+                filters,
+                ..ResultOptions::default()
+            };
+            let results = engine.search("", Mode::Full, sort, &options).unwrap();
+            let results: Vec<serde_json::Value> = serde_json::from_str(&results).unwrap();
+            results
+                .iter()
+                .map(|hit| hit["url"].as_str().unwrap().to_string())
+                .collect::<Vec<String>>()
+        };
+        let zeimpekiko = Filters {
+            rhythm: Some(String::from("zeimpekiko")),
+            ..Filters::default()
+        };
+        assert_eq!(
+            urls(Sort::Popularity, zeimpekiko.clone()),
+            vec![
+                "/songs/kardia_mou_2/",
+                "/songs/kardia_mou_3/",
+                "/songs/kardia_mou/"
+            ]
+        );
+        assert_eq!(
+            urls(Sort::Name, zeimpekiko),
+            vec![
+                "/songs/kardia_mou/",
+                "/songs/kardia_mou_2/",
+                "/songs/kardia_mou_3/"
+            ]
+        );
+        let tsitsanis = Filters {
+            artist: Some(String::from("Τσιτσάνης")),
+            ..Filters::default()
+        };
+        assert_eq!(
+            urls(Sort::Name, tsitsanis),
+            vec!["/songs/kardia_mou_2/", "/songs/kardia_mou_3/"]
+        );
+        assert!(urls(Sort::Name, Filters::default()).is_empty());
+    }
 
-        // Build index
-        let index_path = tempdir()?;
+    #[test]
+    fn test_scale_aliases() {
+        use std::io::Write;
 
-        let index = Index::create_in_dir(&index_path, schema)?;
+        let songs = songs(&[(
+            "Φραγκοσυριανή",
+            "Μάρκος Βαμβακάρης",
+            "Μια φούντωση μια φλόγα",
+        )]);
+        let urls = |engine: &SearchEngine, query, mode| {
+            engine
+                .hits(query, mode, Sort::Relevance)
+                .unwrap()
+                .into_iter()
+                .map(|hit| hit["url"].clone())
+                .collect::<Vec<String>>()
+        };
+        let engine = SearchEngineBuilder::from_songs(songs.clone())
+            .build()
+            .unwrap();
+        assert_eq!(urls(&engine, "hijaz", Mode::Full), vec!["/scales/xitzaz/"]);
+        assert_eq!(urls(&engine, "Χετζαζ", Mode::Full), vec!["/scales/xitzaz/"]);
+        assert_eq!(urls(&engine, "usak", Mode::Ngram), vec!["/scales/ousak/"]);
+        assert_eq!(
+            urls(&engine, "κουρν", Mode::Ngram),
+            vec!["/scales/kiournti/"]
+        );
 
-        let manager = index.tokenizers();
-        manager.register("el_ngram", greek_ngram_tokenizer);
-        manager.register("en_ngram", english_ngram_tokenizer);
-        manager.register("el_simple", greek_simple_tokenizer);
-        manager.register("en_simple", english_simple_tokenizer);
-        manager.register("el_stem", greek_stem_tokenizer);
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "Χιτζάζ = hejaz").unwrap();
+        let engine = SearchEngineBuilder::from_songs(songs)
+            .scale_aliases(Some(file.path().to_path_buf()))
+            .build()
+            .unwrap();
+        assert_eq!(urls(&engine, "hejaz", Mode::Full), vec!["/scales/xitzaz/"]);
+        assert!(urls(&engine, "hijaz", Mode::Full).is_empty());
+    }
 
-        let mut index_writer = index.writer(50_000_000)?;
+    #[test]
+    #[cfg(feature = "mmap")]
+    fn test_snapshots() {
+        use tantivy::Index;
 
-        let mut indexed_artists: Vec<String> = vec![];
+        use crate::config::Distribution;
+        use crate::snapshot::SnapshotDir;
 
-        for path in std::fs::read_dir(song_dir).unwrap() {
-            let filename = path.unwrap().path();
-            let song = Song::from_path(&filename)?;
+        let dir = tempfile::tempdir().unwrap();
+        let follower = SearchEngine::builder("/nonexistent")
+            .distribution(Distribution::Follow(dir.path().to_path_buf()))
+            .build()
+            .unwrap();
+        assert_eq!(follower.searcher().num_docs(), 0);
+        assert!(!follower.sync().unwrap());
 
-            // On songs, we tokenize the name and body with both the simple
-            // and the stemmed tokenizer. This results in including stemmed
-            // results, but giving a higher score to full word results.
-            index_writer.add_document(doc!(
-                name => song.name.as_str(),
-                slug => song.slug.as_str(),
-                body => song.body.as_str(),
-                body_greeklish => song.body_greeklish.as_str(),
-                ngram_name => song.name.as_str(),
-                ngram_slug => song.slug.as_str(),
-                ngram_body => song.body.as_str(),
-                ngram_body_greeklish => song.body_greeklish.as_str(),
-                stemmed_name => song.name.as_str(),
-                stemmed_body => song.body.as_str(),
-                url => format!("/songs/{}/", song.slug.as_str()),
-            ));
+        let publisher = SearchEngine::builder(FIXTURES)
+            .distribution(Distribution::Publish(dir.path().to_path_buf()))
+            .build()
+            .unwrap();
+        assert!(follower.sync().unwrap());
+        assert!(!follower.sync().unwrap());
+        let num_docs = publisher.searcher().num_docs();
+        assert_eq!(follower.searcher().num_docs(), num_docs);
+        let hits = follower
+            .hits("φραγκοσυριανη", Mode::Full, Sort::Relevance)
+            .unwrap();
+        assert_eq!(hits[0]["url"], "/songs/fragkosyriani/");
+        let hits = follower
+            .hits("fragk", Mode::Ngram, Sort::Relevance)
+            .unwrap();
+        assert_eq!(hits[0]["url"], "/songs/fragkosyriani/");
 
-            if !indexed_artists.contains(&song.artist) {
-                index_writer.add_document(doc!(
-                    name => song.artist.as_str(),
-                    slug => song.artist_slug.as_str(),
-                    ngram_name => song.artist.as_str(),
-                    ngram_slug => song.artist_slug.as_str(),
-                    url => format!("/artists/{}/", song.artist_slug.as_str()),
-                ));
-                indexed_artists.push(song.artist);
-            }
-        }
-
-        for &scale in &[
-            "Ματζόρε",
-            "Ραστ",
-            "Φυσικό Μινόρε",
-            "Αρμονικό Μινόρε",
-            "Χιτζάζ",
-            "Χιτζαζκάρ",
-            "Πειραιώτικο",
-            "Ουσάκ",
-            "Καρσιγάρ",
-            "Σαμπάχ",
-            "Νικρίζ",
-            "Νιαβέντ",
-            "Χουζάμ",
-            "Σεγκιάχ",
-            "Σουζινάκ",
-            "Κιουρντί",
-        ] {
-            let scale_slug = to_greeklish(scale);
-            index_writer.add_document(doc!(
-                name => scale,
-                slug => scale_slug.as_str(),
-                ngram_name => scale,
-                ngram_slug => scale_slug.as_str(),
-                url => format!("/scales/{}/", scale_slug.as_str()),
-            ));
+        // Reindexing a follower switches to the latest snapshot.
+        assert_eq!(publisher.reindex().unwrap(), num_docs);
+        assert_eq!(follower.reindex().unwrap(), num_docs);
+        assert!(!follower.sync().unwrap());
+
+        // Snapshots of another schema version are refused, and the current one is still served.
+        let mut schema_builder = Schema::builder();
+        schema_builder.add_text_field("name", TEXT | STORED);
+        let index = Index::create_in_ram(schema_builder.build());
+        index.writer(3_000_000).unwrap().commit().unwrap();
+        SnapshotDir::new(dir.path()).publish(&index).unwrap();
+        let error = follower.sync().unwrap_err().to_string();
+        assert!(error.contains("no schema version"), "{}", error);
+        assert_eq!(follower.searcher().num_docs(), num_docs);
+    }
+
+    #[test]
+    #[cfg(feature = "mmap")]
+    fn test_schema_version() {
+        use tantivy::Index;
+
+        use crate::search_engine::{schema_version, SCHEMA_VERSION};
+
+        let index_dir = tempfile::tempdir().unwrap();
+        let build = || {
+            SearchEngine::builder(FIXTURES)
+                .index_dir(index_dir.path())
+                .build()
+                .unwrap()
+        };
+        let num_docs = build().searcher().num_docs();
+        // The index of the previous run is reused, without duplicating its documents.
+        assert_eq!(build().searcher().num_docs(), num_docs);
+
+        // An index of another schema is rebuilt.
+        for entry in std::fs::read_dir(index_dir.path()).unwrap() {
+            std::fs::remove_file(entry.unwrap().path()).unwrap();
         }
+        let mut schema_builder = Schema::builder();
+        schema_builder.add_text_field("name", TEXT | STORED);
+        let index = Index::create_in_dir(index_dir.path(), schema_builder.build()).unwrap();
+        index.writer(3_000_000).unwrap().commit().unwrap();
+        drop(index);
+        let engine = build();
+        assert_eq!(engine.searcher().num_docs(), num_docs);
+        assert_eq!(schema_version(&engine.index).unwrap(), Some(SCHEMA_VERSION));
+    }
 
-        index_writer.commit()?;
+    #[test]
+    #[cfg(feature = "mmap")]
+    fn test_lazy() {
+        let index_dir = tempfile::tempdir().unwrap();
+        let song = |name| songs(&[(name, "Μάρκος Βαμβακάρης", "")]);
+        let build = |songs, lazy| {
+            SearchEngineBuilder::from_songs(songs)
+                .index_dir(index_dir.path())
+                .lazy(lazy)
+                .build()
+                .unwrap()
+        };
+        let hits = |engine: &SearchEngine| {
+            engine
+                .hits("φραγκοσυριανη", Mode::Full, Sort::Relevance)
+                .unwrap()
+                .len()
+        };
 
-        let reader = index
-            .reader_builder()
-            .reload_policy(ReloadPolicy::Manual) // OnCommit?
-            .try_into()?;
+        // Lazy engines build the index if the directory has none.
+        let engine = build(song("Φραγκοσυριανή"), true);
+        assert!(!engine.is_stale());
+        assert_eq!(hits(&engine), 1);
+        drop(engine);
 
-        let mut full_query_parser = QueryParser::for_index(
-            &index,
-            vec![name, slug, body, body_greeklish, stemmed_name, stemmed_body],
-        );
-        full_query_parser.set_conjunction_by_default();
+        let engine = build(song("Καϊξής"), true);
+        assert!(engine.is_stale());
+        assert_eq!(hits(&engine), 1);
+        engine.reindex().unwrap();
+        assert!(!engine.is_stale());
+        assert_eq!(hits(&engine), 0);
+        drop(engine);
 
-        let mut ngram_query_parser = QueryParser::for_index(
-            &index,
-            vec![ngram_name, ngram_slug, ngram_body, ngram_body_greeklish],
-        );
-        ngram_query_parser.set_conjunction_by_default();
+        let engine = build(song("Φραγκοσυριανή"), false);
+        assert!(!engine.is_stale());
+        assert_eq!(hits(&engine), 1);
+    }
 
-        let schema = index.schema();
+    #[test]
+    fn test_reindex_failure() {
+        let song_dir = tempfile::tempdir().unwrap();
+        let song_file = song_dir.path().join("frangosyriani");
+        std::fs::copy(format!("{}/frangosyriani", FIXTURES), &song_file).unwrap();
+        let engine = SearchEngine::builder(song_dir.path().to_str().unwrap())
+            .build()
+            .unwrap();
+        let num_docs = engine.searcher().num_docs();
 
-        Ok(SearchEngine {
-            index,
-            reader,
-            full_query_parser,
-            ngram_query_parser,
-            schema,
-        })
+        std::fs::write(song_dir.path().join("broken"), "").unwrap();
+        assert!(engine.reindex().is_err());
+        assert!(engine.reindex().is_err());
+        let failure = engine.reindex_failure().unwrap();
+        assert_eq!(failure.attempts, 2);
+        assert!(!failure.error.is_empty());
+        // The previous index is still served.
+        assert_eq!(engine.searcher().num_docs(), num_docs);
+
+        std::fs::remove_file(song_dir.path().join("broken")).unwrap();
+        assert_eq!(engine.reindex().unwrap(), num_docs);
+        assert_eq!(engine.reindex_failure(), None);
     }
 
-    pub fn search(&self, query: &str, full: bool) -> tantivy::Result<String> {
-        let searcher = self.reader.searcher();
-        let (query_parser, limit) = if full {
-            (&self.full_query_parser, 1000)
-        } else {
-            (&self.ngram_query_parser, 15)
+    #[test]
+    fn test_reindex_under_load() {
+        let engine = SearchEngine::builder(FIXTURES)
+            .indexing(IndexingLimits {
+                threads: Some(1),
+                heap_size: 10_000_000,
+                pause: Duration::from_millis(20),
+            })
+            .build()
+            .unwrap();
+        let num_docs = engine.searcher().num_docs();
+        let reindex = {
+            let engine = engine.clone();
+            std::thread::spawn(move || engine.reindex().unwrap())
         };
-        let query = query_parser.parse_query(query)?;
-        let top_docs = searcher.search(&query, &TopDocs::with_limit(limit))?;
-        let mut results = Vec::new();
-        for (_score, doc_address) in top_docs {
-            let retrieved_doc = searcher.doc(doc_address)?;
-            let mut entry = HashMap::new();
-            for field_value in retrieved_doc.field_values() {
-                let field_name = self.schema.get_field_name(field_value.field());
-                let value = field_value.value().text().unwrap();
-                entry.insert(field_name.to_string(), value.to_string());
-            }
-            results.push(entry);
+        // The deleted documents of the running reindex are still served.
+        while !reindex.is_finished() {
+            assert_eq!(engine.searcher().num_docs(), num_docs);
+            let hits = engine
+                .hits("φραγκοσυριανη", Mode::Full, Sort::Relevance)
+                .unwrap();
+            assert_eq!(hits[0]["url"], "/songs/fragkosyriani/");
         }
-        Ok(serde_json::to_string(&results)?)
+        assert_eq!(reindex.join().unwrap(), num_docs);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use tantivy::tokenizer::{Language, LowerCaser, SimpleTokenizer, Stemmer, TextAnalyzer};
+    #[test]
+    fn test_autocomplete() {
+        let engine = SearchEngine::builder(FIXTURES).build().unwrap();
+        let urls = |query| {
+            let hits = engine.hits(query, Mode::Ngram, Sort::Relevance).unwrap();
+            hits.into_iter()
+                .map(|hit| hit["url"].clone())
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(urls("μαρκος πειρ"), vec!["/songs/o_markos_ston_peiraia/"]);
+        assert_eq!(urls("markos peir"), vec!["/songs/o_markos_ston_peiraia/"]);
+        // Only the last word is matched as a prefix.
+        assert!(urls("μαρ πειρ").is_empty());
+        assert!(urls("μαρ").contains(&String::from("/artists/markos_vamvakaris/")));
 
-    use crate::greek_lower_caser::GreekLowerCaser;
-    use crate::tokenizer::NgramTokenizer;
+        // Query syntax around the last word.
+        let vamvakaris = urls("μαρκος βαμβ");
+        assert!(vamvakaris.contains(&String::from("/artists/markos_vamvakaris/")));
+        assert!(!urls("μαρκος -βαμβ").is_empty());
+        assert!(!urls("μαρκος -βαμβ").contains(&String::from("/artists/markos_vamvakaris/")));
+        assert_eq!(urls("μαρκος +βαμβ"), vamvakaris);
+        assert_eq!(urls("(μαρκος βαμβ)"), vamvakaris);
+        assert_eq!(urls("(μαρκος βαμβ"), vamvakaris);
+        assert_eq!(urls("markos AND vamv"), urls("markos vamv"));
+        assert!(!urls("markos AND vamv").is_empty());
+        let either = urls("πειραια OR βαμβακαρ");
+        assert!(either.contains(&String::from("/songs/o_markos_ston_peiraia/")));
+        assert!(either.contains(&String::from("/artists/markos_vamvakaris/")));
+    }
+
+    #[test]
+    fn test_check_schema() {
+        let mut schema_builder = Schema::builder();
+        schema_builder.add_text_field("name", STORED);
+        schema_builder.add_text_field("slug", STORED);
+        assert!(check_schema(&schema_builder.build()).is_ok());
+
+        let mut schema_builder = Schema::builder();
+        schema_builder.add_text_field("name", STORED);
+        schema_builder.add_text_field("name", STORED);
+        assert!(check_schema(&schema_builder.build()).is_err());
+    }
+
+    #[test]
+    fn test_describe_query() {
+        let mut schema_builder = Schema::builder();
+        let name = schema_builder.add_text_field("name", TEXT);
+        let body = schema_builder.add_text_field("body", TEXT);
+        let schema = schema_builder.build();
+
+        let term_query =
+            TermQuery::new(Term::from_field_text(name, "ελα"), IndexRecordOption::Basic);
+        let phrase_query = PhraseQuery::new(vec![
+            Term::from_field_text(body, "μαυρα"),
+            Term::from_field_text(body, "φρυδια"),
+        ]);
+        let subqueries: Vec<Box<dyn Query>> = vec![Box::new(term_query), Box::new(phrase_query)];
+        let query = BooleanQuery::intersection(subqueries);
+        assert_eq!(
+            describe_query(&query, &schema),
+            "(+name:\"ελα\" +body:\"μαυρα φρυδια\")"
+        );
+    }
 
     #[test]
     fn test_simple_tokenizer() {
         let text = "Έλα τι λέει";
         let mut tokens = vec![];
         let mut token_stream = TextAnalyzer::from(SimpleTokenizer)
-            .filter(GreekLowerCaser)
+            .filter(GreekLowerCaser::default())
             .token_stream(text);
         while token_stream.advance() {
             let token_text = token_stream.token().text.clone();
@@ -237,12 +4777,81 @@ mod tests {
         assert_eq!(tokens, vec!["ελα", "τι", "λεει"]);
     }
 
+    #[test]
+    fn test_ascii_folding() {
+        let text = "Café Aman στο Μπαλί";
+        let mut tokens = vec![];
+        let mut token_stream = TextAnalyzer::from(SimpleTokenizer)
+            .filter(GreekLowerCaser::default())
+            .filter(AsciiFoldingFilter)
+            .token_stream(text);
+        while token_stream.advance() {
+            tokens.push(token_stream.token().text.clone());
+        }
+        assert_eq!(tokens, vec!["cafe", "aman", "στο", "μπαλι"]);
+    }
+
+    #[test]
+    fn test_homoglyph_filter() {
+        let text = "Bαμβακάρης Mάρκος Μarkos Café";
+        let mut tokens = vec![];
+        let mut token_stream = TextAnalyzer::from(SimpleTokenizer)
+            .filter(HomoglyphFilter)
+            .token_stream(text);
+        while token_stream.advance() {
+            tokens.push(token_stream.token().text.clone());
+        }
+        assert_eq!(tokens, vec!["Βαμβακάρης", "Μάρκος", "Markos", "Café"]);
+    }
+
+    #[test]
+    fn test_greek_lower_caser_offsets() {
+        let text = "Έλα ΤΙ λέει";
+        let mut tokens = vec![];
+        let mut token_stream = TextAnalyzer::from(SimpleTokenizer)
+            .filter(GreekLowerCaser::default())
+            .token_stream(text);
+        while token_stream.advance() {
+            let token = token_stream.token();
+            tokens.push((
+                token.text.clone(),
+                &text[token.offset_from..token.offset_to],
+            ));
+        }
+        assert_eq!(
+            tokens,
+            vec![
+                (String::from("ελα"), "Έλα"),
+                (String::from("τι"), "ΤΙ"),
+                (String::from("λεει"), "λέει"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_greek_lower_caser_homophones() {
+        let tokens = |text| {
+            let mut tokens = vec![];
+            let mut token_stream = TextAnalyzer::from(SimpleTokenizer)
+                .filter(GreekLowerCaser::folding_homophones())
+                .token_stream(text);
+            while token_stream.advance() {
+                tokens.push(token_stream.token().text.clone());
+            }
+            tokens
+        };
+        assert_eq!(tokens("Ήλιε μου ωραίε"), vec!["ιλιε", "μου", "ορεε"]);
+        assert_eq!(tokens("Καίγομαι"), vec!["κεγομε"]);
+        assert_eq!(tokens("αϊτός ευχή"), vec!["αιτοσ", "ευχι"]);
+        assert_eq!(tokens("κύμα"), tokens("κήμα"));
+    }
+
     #[test]
     fn test_greek_ngram_tokenizer() {
         let text = "Έλα τι λέει";
         let mut tokens = vec![];
-        let mut token_stream = TextAnalyzer::from(NgramTokenizer)
-            .filter(GreekLowerCaser)
+        let mut token_stream = TextAnalyzer::from(NgramTokenizer::limit(40))
+            .filter(GreekLowerCaser::default())
             .token_stream(text);
         while token_stream.advance() {
             let token_text = token_stream.token().text.clone();
@@ -254,11 +4863,103 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_ngram_tokenizer_positions() {
+        let text = "Έλα τι";
+        let mut positions = vec![];
+        let mut token_stream = TextAnalyzer::from(NgramTokenizer::limit(40))
+            .filter(GreekLowerCaser::default())
+            .token_stream(text);
+        while token_stream.advance() {
+            let token = token_stream.token();
+            positions.push((token.text.clone(), token.position));
+        }
+        assert_eq!(
+            positions,
+            vec![
+                (String::from("ε"), 0),
+                (String::from("ελ"), 0),
+                (String::from("ελα"), 0),
+                (String::from("τ"), 1),
+                (String::from("τι"), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ngram_tokenizer_whitespace() {
+        let text = " Έλα τι ";
+        let mut tokens = vec![];
+        let mut token_stream = TextAnalyzer::from(NgramTokenizer::limit(40)).token_stream(text);
+        while token_stream.advance() {
+            let token = token_stream.token();
+            tokens.push((token.text.clone(), token.offset_from, token.offset_to));
+        }
+        assert_eq!(
+            tokens,
+            vec![
+                (String::from("Έ"), 1, 3),
+                (String::from("Έλ"), 1, 5),
+                (String::from("Έλα"), 1, 7),
+                (String::from("τ"), 8, 10),
+                (String::from("τι"), 8, 12),
+            ]
+        );
+
+        let mut token_stream = TextAnalyzer::from(NgramTokenizer::limit(40)).token_stream("  ");
+        assert!(!token_stream.advance());
+    }
+
+    proptest! {
+        #[test]
+        fn test_ngram_tokenizer_offsets(text in "\\PC*") {
+            let mut token_stream = TextAnalyzer::from(NgramTokenizer::limit(40)).token_stream(&text);
+            let mut previous_position = None;
+            while token_stream.advance() {
+                let token = token_stream.token();
+                prop_assert!(!token.text.is_empty());
+                prop_assert_eq!(&token.text, &text[token.offset_from..token.offset_to]);
+                prop_assert!(token.text.chars().all(char::is_alphanumeric));
+                // Every token is a prefix of a word.
+                let before = text[..token.offset_from].chars().next_back();
+                prop_assert!(!matches!(before, Some(c) if c.is_alphanumeric()));
+                // Positions only advance at the first prefix of a word, by more than one if
+                // long words were skipped in between.
+                let is_first_prefix = token.text.chars().count() == 1;
+                match previous_position {
+                    None => {}
+                    Some(position) if is_first_prefix => prop_assert!(token.position > position),
+                    Some(position) => prop_assert_eq!(token.position, position),
+                }
+                previous_position = Some(token.position);
+            }
+        }
+    }
+
+    #[test]
+    fn test_ngram_tokenizer_limit() {
+        let mut tokens = vec![];
+        let mut token_stream =
+            TextAnalyzer::from(NgramTokenizer::limit(7)).token_stream("έλα ρεμπέτη");
+        while token_stream.advance() {
+            let token = token_stream.token();
+            tokens.push((token.text.clone(), token.position));
+        }
+        assert_eq!(
+            tokens,
+            vec![
+                (String::from("έ"), 0),
+                (String::from("έλ"), 0),
+                (String::from("έλα"), 0),
+            ]
+        );
+    }
+
     #[test]
     fn test_english_ngram_tokenizer() {
         let text = "Whazup";
         let mut tokens = vec![];
-        let mut token_stream = TextAnalyzer::from(NgramTokenizer)
+        let mut token_stream = TextAnalyzer::from(NgramTokenizer::limit(40))
             .filter(LowerCaser)
             .token_stream(text);
         while token_stream.advance() {
@@ -268,6 +4969,42 @@ mod tests {
         assert_eq!(tokens, vec!["w", "wh", "wha", "whaz", "whazu", "whazup"]);
     }
 
+    #[test]
+    fn test_number_filter() {
+        let tokens = |numbers| {
+            let mut tokens = vec![];
+            let mut token_stream = TextAnalyzer::from(SimpleTokenizer)
+                .filter(NumberFilter::new(numbers))
+                .token_stream("30 δραχμές 2x");
+            while token_stream.advance() {
+                tokens.push(token_stream.token().text.clone());
+            }
+            tokens
+        };
+        assert_eq!(tokens(NumberPolicy::Keep), vec!["30", "δραχμές", "2x"]);
+        assert_eq!(tokens(NumberPolicy::Drop), vec!["δραχμές", "2x"]);
+    }
+
+    #[test]
+    fn test_phonetic_tokenizer() {
+        let tokens = |text| {
+            let mut tokens = vec![];
+            let mut token_stream = TextAnalyzer::from(SimpleTokenizer)
+                .filter(GreekLowerCaser::default())
+                .filter(PhoneticFilter)
+                .token_stream(text);
+            while token_stream.advance() {
+                tokens.push(token_stream.token().text.clone());
+            }
+            tokens
+        };
+        assert_eq!(tokens("τρέχει"), tokens("τρεχι"));
+        assert_eq!(tokens("καίγομαι"), vec!["κεγομε"]);
+        assert_eq!(tokens("θάλασσα ωραία"), vec!["θαλασα", "ορεα"]);
+        assert_eq!(tokens("πουλί ευχή"), vec!["πουλι", "ευχι"]);
+        assert_eq!(tokens("Τσιτσάνης"), tokens("Τσητσάνοις"));
+    }
+
     #[test]
     fn test_greek_stemmer_tokenizer() {
         let text = "Εφουμέρναμε ένα βράδυ";
@@ -281,4 +5018,111 @@ mod tests {
         }
         assert_eq!(tokens, vec!["εφουμερν", "εν", "βραδ"]);
     }
+
+    #[test]
+    fn test_shingle_tokenizer() {
+        let text = "Τα μαύρα φρύδια";
+        let mut tokens = vec![];
+        let mut token_stream = TextAnalyzer::from(SimpleTokenizer)
+            .filter(GreekLowerCaser::default())
+            .filter(ShingleFilter::adjacent())
+            .token_stream(text);
+        while token_stream.advance() {
+            let token = token_stream.token();
+            tokens.push((
+                token.text.clone(),
+                &text[token.offset_from..token.offset_to],
+            ));
+        }
+        assert_eq!(
+            tokens,
+            vec![
+                (String::from("τα μαυρα"), "Τα μαύρα"),
+                (String::from("μαυρα φρυδια"), "μαύρα φρύδια"),
+            ]
+        );
+
+        let mut token_stream = TextAnalyzer::from(SimpleTokenizer)
+            .filter(ShingleFilter::adjacent())
+            .token_stream("μαύρα");
+        assert!(!token_stream.advance());
+    }
+
+    #[test]
+    fn test_near_tokenizer() {
+        let mut tokens = vec![];
+        let mut token_stream = TextAnalyzer::from(SimpleTokenizer)
+            .filter(GreekLowerCaser::default())
+            .filter(ShingleFilter::near(2))
+            .token_stream("μαύρα τα φρύδια σου");
+        while token_stream.advance() {
+            let token = token_stream.token();
+            tokens.push((token.text.clone(), token.position));
+        }
+        tokens.sort();
+        assert_eq!(
+            tokens,
+            vec![
+                (String::from("μαυρα τα"), 1),
+                (String::from("μαυρα φρυδια"), 2),
+                (String::from("σου τα"), 3),
+                (String::from("σου φρυδια"), 3),
+                (String::from("τα φρυδια"), 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_boost_shingles_syntax() {
+        let engine = fixture_engine(Boosts::default());
+        let boosted = |text| format!("{:?}", engine.boost_shingles(Box::new(EmptyQuery), text));
+        assert_eq!(boosted("μαυρα AND φρυδια"), boosted("μαυρα φρυδια"));
+        assert_eq!(boosted("+name:μαυρα OR φρυδια^2"), boosted("μαυρα φρυδια"));
+        assert_eq!(boosted("μαυρα -σου φρυδια"), boosted("μαυρα φρυδια"));
+        assert_eq!(boosted("μαυρα NOT σου"), boosted("μαυρα"));
+    }
+
+    #[test]
+    fn test_boosts() {
+        let urls = |boosts| {
+            let hits = fixture_engine(boosts)
+                .hits("μαρκος", Mode::Full, Sort::Relevance)
+                .unwrap();
+            hits.into_iter()
+                .map(|hit| hit["url"].clone())
+                .collect::<Vec<String>>()
+        };
+
+        let boosts = Boosts {
+            song: 1.0,
+            artist: 1.0,
+            scale: 1.0,
+            ..Boosts::default()
+        };
+        assert_eq!(
+            urls(boosts),
+            vec![
+                "/songs/o_markos_ston_peiraia/",
+                "/artists/markos_vamvakaris/"
+            ]
+        );
+
+        let boosts = Boosts {
+            song: 0.5,
+            ..boosts
+        };
+        assert_eq!(
+            urls(boosts),
+            vec![
+                "/artists/markos_vamvakaris/",
+                "/songs/o_markos_ston_peiraia/"
+            ]
+        );
+
+        // Boosts can be changed while serving.
+        let engine = fixture_engine(Boosts::default());
+        engine.set_scoring(boosts, None, None, None);
+        let hits = engine.hits("μαρκος", Mode::Full, Sort::Relevance).unwrap();
+        assert_eq!(hits[0]["url"], "/artists/markos_vamvakaris/");
+    }
 }
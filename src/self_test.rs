@@ -0,0 +1,75 @@
+//! Canned queries that check a freshly built index before it takes traffic. The queries are
+//! derived from the indexed songs, so that they hold for any song collection.
+
+use std::collections::HashMap;
+
+use crate::error::{Error, Result};
+use crate::search_engine::{Mode, SearchEngine, Sort};
+
+/// Scale that is always indexed.
+const SCALE: &str = "Χιτζάζ";
+
+/// Outcome of a canned query.
+pub struct Check {
+    pub name: &'static str,
+    pub query: String,
+    pub passed: bool,
+}
+
+/// Search for an indexed song in a few ways, and for a scale, checking that each search finds
+/// it. Fails if no song is indexed.
+pub fn run(engine: &SearchEngine) -> Result<Vec<Check>> {
+    let mut song: Option<HashMap<String, String>> = None;
+    engine.export(|line| {
+        let fields: HashMap<String, String> = serde_json::from_str(&line).unwrap_or_default();
+        if fields
+            .get("url")
+            .is_some_and(|url| url.starts_with("/songs/"))
+        {
+            song = Some(fields);
+        }
+        song.is_none()
+    })?;
+    let song = song.ok_or_else(|| Error::Parse(String::from("No songs are indexed")))?;
+    let (name, url) = (&song["name"], &song["url"]);
+
+    let transliteration = engine.transliteration();
+    let greeklish = transliteration.to_greeklish(name).replace('_', " ");
+    let scale_url = format!("/scales/{}/", transliteration.to_slug(SCALE));
+
+    let mut checks = Vec::new();
+    for (check, query, mode, expected) in [
+        ("known song", name.to_lowercase(), Mode::Full, url),
+        ("greeklish query", greeklish, Mode::Full, url),
+        ("accented query", name.clone(), Mode::Exact, url),
+        ("scale name", SCALE.to_string(), Mode::Full, &scale_url),
+    ] {
+        let hits = engine.hits(&query, mode, Sort::Relevance)?;
+        checks.push(Check {
+            name: check,
+            passed: hits.iter().any(|hit| hit.get("url") == Some(expected)),
+            query,
+        });
+    }
+    Ok(checks)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::search_engine::SearchEngine;
+    use crate::self_test::run;
+
+    #[test]
+    fn test_run() {
+        let fixtures = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/songs");
+        let engine = SearchEngine::builder(fixtures).build().unwrap();
+        let checks = run(&engine).unwrap();
+        assert_eq!(checks.len(), 4);
+        for check in checks {
+            assert!(check.passed, "{}: {}", check.name, check.query);
+        }
+
+        let engine = SearchEngine::from_songs(vec![]).unwrap();
+        assert!(run(&engine).is_err());
+    }
+}
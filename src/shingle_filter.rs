@@ -0,0 +1,105 @@
+use std::collections::VecDeque;
+
+use tantivy::tokenizer::BoxTokenStream;
+use tantivy::tokenizer::{Token, TokenFilter, TokenStream};
+
+impl TokenFilter for ShingleFilter {
+    fn transform<'a>(&self, token_stream: BoxTokenStream<'a>) -> BoxTokenStream<'a> {
+        BoxTokenStream::from(ShingleTokenStream {
+            tail: token_stream,
+            filter: self.clone(),
+            previous: VecDeque::with_capacity(self.window),
+            pending: Vec::new(),
+            token: Token::default(),
+        })
+    }
+}
+
+/// Token filter that joins pairs of terms into a single term, separated by a space, e.g.
+/// "μαυρα φρυδια". Single words produce no terms.
+#[derive(Clone)]
+pub struct ShingleFilter {
+    /// Maximum distance in positions between the two terms of a pair.
+    window: usize,
+    /// Keep the terms of a pair in text order, instead of sorting them.
+    ordered: bool,
+}
+
+impl ShingleFilter {
+    /// Pairs of consecutive terms, in text order.
+    pub fn adjacent() -> ShingleFilter {
+        ShingleFilter {
+            window: 1,
+            ordered: true,
+        }
+    }
+
+    /// Pairs of terms at most `window` positions apart, in any order.
+    pub fn near(window: usize) -> ShingleFilter {
+        ShingleFilter {
+            window,
+            ordered: false,
+        }
+    }
+}
+
+pub struct ShingleTokenStream<'a> {
+    tail: BoxTokenStream<'a>,
+    filter: ShingleFilter,
+    previous: VecDeque<Token>,
+    pending: Vec<Token>,
+    token: Token,
+}
+
+impl<'a> ShingleTokenStream<'a> {
+    /// Queue the pairs of the current term with every previous term in the window. Pairs take
+    /// the position of the current term, so that positions never decrease.
+    fn push_pairs(&mut self) {
+        let current = self.tail.token();
+        for previous in self.previous.iter().rev() {
+            let (first, second) = if self.filter.ordered || previous.text <= current.text {
+                (previous, current)
+            } else {
+                (current, previous)
+            };
+            let text = format!("{} {}", first.text, second.text);
+            if self.pending.iter().any(|token| token.text == text) {
+                continue;
+            }
+            self.pending.push(Token {
+                offset_from: previous.offset_from,
+                offset_to: current.offset_to,
+                position: current.position,
+                text,
+                position_length: 1,
+            });
+        }
+        if self.previous.len() == self.filter.window {
+            self.previous.pop_front();
+        }
+        self.previous.push_back(current.clone());
+    }
+}
+
+impl<'a> TokenStream for ShingleTokenStream<'a> {
+    fn advance(&mut self) -> bool {
+        loop {
+            if let Some(token) = self.pending.pop() {
+                self.token = token;
+                return true;
+            }
+            if !self.tail.advance() {
+                return false;
+            }
+            self.push_pairs();
+        }
+    }
+
+    fn token(&self) -> &Token {
+        &self.token
+    }
+
+    fn token_mut(&mut self) -> &mut Token {
+        &mut self.token
+    }
+}
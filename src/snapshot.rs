@@ -0,0 +1,246 @@
+//! Index snapshots, shared between instances through a directory. A publishing instance copies
+//! its index into a new snapshot after every reindex, and following instances serve the latest
+//! snapshot instead of indexing the songs themselves. Snapshots can also be serialized into a
+//! single buffer, for serving them where indexes can't be built, e.g. in a browser.
+
+use std::convert::TryInto;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::warn;
+use tantivy::directory::{Directory, RAMDirectory};
+use tantivy::{Index, SegmentComponent, TantivyError};
+
+use crate::error::{Error, Result};
+
+/// File holding the name of the latest snapshot.
+const LATEST: &str = "LATEST";
+/// Older snapshots are kept for followers that are still switching to them.
+const KEEP: usize = 3;
+/// Copying is retried, since a merge may remove segment files while they are being copied.
+const ATTEMPTS: usize = 3;
+
+/// Directory with a subdirectory per snapshot, named after the time it was published.
+#[derive(Clone, Debug)]
+pub struct SnapshotDir {
+    path: PathBuf,
+}
+
+impl SnapshotDir {
+    pub fn new(path: impl Into<PathBuf>) -> SnapshotDir {
+        SnapshotDir { path: path.into() }
+    }
+
+    /// Copy the committed segments of the index into a new snapshot, make it the latest and
+    /// return its name.
+    pub fn publish(&self, index: &Index) -> Result<String> {
+        fs::create_dir_all(&self.path)?;
+        let name = self.next_name();
+        let snapshot = self.path.join(&name);
+        for attempt in 1..=ATTEMPTS {
+            match copy_index(index, &snapshot) {
+                Ok(()) => break,
+                Err(e) => {
+                    let _ = fs::remove_dir_all(&snapshot);
+                    if attempt == ATTEMPTS {
+                        return Err(e);
+                    }
+                    warn!("snapshot {} error: {}, retrying", name, e);
+                }
+            }
+        }
+
+        // Renamed into place, so that followers never read a partially written name.
+        let temp_path = self.path.join(format!("{}.tmp", LATEST));
+        fs::write(&temp_path, &name)?;
+        fs::rename(&temp_path, self.path.join(LATEST))?;
+        self.prune()?;
+        Ok(name)
+    }
+
+    /// Name of the latest snapshot, if one has been published.
+    pub fn latest(&self) -> Result<Option<String>> {
+        match fs::read_to_string(self.path.join(LATEST)) {
+            Ok(name) => Ok(Some(name.trim().to_string())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    #[cfg(feature = "mmap")]
+    pub fn open(&self, name: &str) -> Result<Index> {
+        Ok(Index::open_in_dir(self.path.join(name))?)
+    }
+
+    #[cfg(not(feature = "mmap"))]
+    pub fn open(&self, _name: &str) -> Result<Index> {
+        let message = "Following snapshots requires the mmap feature";
+        Err(crate::error::Error::Parse(String::from(message)))
+    }
+
+    /// Milliseconds since the epoch, zero-padded so that names sort by age.
+    fn next_name(&self) -> String {
+        let mut millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        loop {
+            let name = format!("{:020}", millis);
+            if !self.path.join(&name).exists() {
+                return name;
+            }
+            millis += 1;
+        }
+    }
+
+    /// Names of the snapshots, oldest first.
+    fn snapshots(&self) -> Result<Vec<String>> {
+        let mut names = Vec::new();
+        for entry in fs::read_dir(&self.path)? {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if name.bytes().all(|byte| byte.is_ascii_digit()) {
+                names.push(name);
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    fn prune(&self) -> Result<()> {
+        let snapshots = self.snapshots()?;
+        let old = snapshots.len().saturating_sub(KEEP);
+        for name in &snapshots[..old] {
+            fs::remove_dir_all(self.path.join(name))?;
+        }
+        Ok(())
+    }
+}
+
+fn copy_index(index: &Index, snapshot: &Path) -> Result<()> {
+    fs::create_dir_all(snapshot)?;
+    for (path, bytes) in index_files(index)? {
+        fs::write(snapshot.join(&path), bytes)?;
+    }
+    Ok(())
+}
+
+/// The files of the committed segments of the index and their contents, and last meta.json,
+/// which lists the segments.
+fn index_files(index: &Index) -> Result<Vec<(PathBuf, Vec<u8>)>> {
+    let metas = index.load_metas()?;
+    let directory = index.directory();
+    let mut files = Vec::new();
+    for segment in &metas.segments {
+        let deletes = segment.relative_path(SegmentComponent::DELETE);
+        for path in segment.list_files() {
+            if path == deletes && !segment.has_deletes() {
+                continue;
+            }
+            // Read as is, since opening it for reading strips its footer.
+            let bytes = directory.atomic_read(&path).map_err(TantivyError::from)?;
+            files.push((path, bytes));
+        }
+    }
+    let metas = serde_json::to_string(&metas)?;
+    files.push((PathBuf::from("meta.json"), metas.into_bytes()));
+    Ok(files)
+}
+
+/// Serialize the committed segments of the index into a buffer that `deserialize` reads back.
+/// Every file is written as the length of its name, its name, the length of its contents and
+/// its contents, with the lengths as little-endian u32 and u64.
+pub fn serialize(index: &Index) -> Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    for (path, bytes) in index_files(index)? {
+        let name = path.to_string_lossy();
+        buffer.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        buffer.extend_from_slice(name.as_bytes());
+        buffer.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+        buffer.extend_from_slice(&bytes);
+    }
+    Ok(buffer)
+}
+
+/// Read the files of a serialized index into a directory in RAM.
+pub fn deserialize(mut buffer: &[u8]) -> Result<RAMDirectory> {
+    fn take<'a>(buffer: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+        if buffer.len() < len {
+            return Err(Error::Parse(String::from(
+                "The serialized index is truncated",
+            )));
+        }
+        let (taken, rest) = buffer.split_at(len);
+        *buffer = rest;
+        Ok(taken)
+    }
+    let directory = RAMDirectory::create();
+    while !buffer.is_empty() {
+        let name_len = u32::from_le_bytes(take(&mut buffer, 4)?.try_into().unwrap());
+        let name = std::str::from_utf8(take(&mut buffer, name_len as usize)?).map_err(|e| {
+            Error::Parse(format!("Invalid file name in the serialized index: {}", e))
+        })?;
+        let len = u64::from_le_bytes(take(&mut buffer, 8)?.try_into().unwrap());
+        let bytes = take(&mut buffer, len as usize)?;
+        directory.atomic_write(Path::new(name), bytes)?;
+    }
+    Ok(directory)
+}
+
+// Opening the published snapshots requires mmap.
+#[cfg(all(test, feature = "mmap"))]
+mod tests {
+    use tantivy::doc;
+    use tantivy::schema::{Schema, STORED, TEXT};
+    use tantivy::Index;
+
+    use crate::snapshot::{deserialize, serialize, SnapshotDir, KEEP};
+
+    #[test]
+    fn test_publish() {
+        let mut schema_builder = Schema::builder();
+        let name = schema_builder.add_text_field("name", TEXT | STORED);
+        let index = Index::create_in_ram(schema_builder.build());
+        let mut index_writer = index.writer(3_000_000).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let snapshots = SnapshotDir::new(dir.path().join("snapshots"));
+        assert_eq!(snapshots.latest().unwrap(), None);
+
+        let mut names = Vec::new();
+        for song in &[
+            "Φραγκοσυριανή",
+            "Συννεφιασμένη Κυριακή",
+            "Τα μπλε παράθυρά σου",
+            "Καρδιά",
+        ] {
+            index_writer.add_document(doc!(name => *song));
+            index_writer.commit().unwrap();
+            names.push(snapshots.publish(&index).unwrap());
+        }
+        assert_eq!(snapshots.latest().unwrap().as_ref(), names.last());
+        assert_eq!(snapshots.snapshots().unwrap(), names[names.len() - KEEP..]);
+
+        let snapshot = snapshots.open(names.last().unwrap()).unwrap();
+        let searcher = snapshot.reader().unwrap().searcher();
+        assert_eq!(searcher.num_docs(), 4);
+    }
+
+    #[test]
+    fn test_serialize() {
+        let mut schema_builder = Schema::builder();
+        let name = schema_builder.add_text_field("name", TEXT | STORED);
+        let index = Index::create_in_ram(schema_builder.build());
+        let mut index_writer = index.writer(3_000_000).unwrap();
+        index_writer.add_document(doc!(name => "Φραγκοσυριανή"));
+        index_writer.add_document(doc!(name => "Καρδιά"));
+        index_writer.commit().unwrap();
+
+        let buffer = serialize(&index).unwrap();
+        let copy = Index::open(deserialize(&buffer).unwrap()).unwrap();
+        assert!(copy.schema() == index.schema());
+        assert_eq!(copy.reader().unwrap().searcher().num_docs(), 2);
+        assert!(deserialize(&buffer[..buffer.len() - 1]).is_err());
+    }
+}
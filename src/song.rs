@@ -1,71 +1,563 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::time::UNIX_EPOCH;
+
 use lazy_static::lazy_static;
-use log::error;
-use regex::Regex;
+use regex::{Captures, Regex};
 
-use crate::utils::to_greeklish;
+use crate::error::{Error, Result};
+use crate::greek_lower_caser::to_greek_lowercase;
+use crate::transliteration::Transliteration;
+use crate::utils::{detect_script, Script};
 
-/// Remove lines that contain only chords and symbols and trim unneeded characters.
-pub fn strip_metadata(string: &str) -> String {
-    // We are interested in Greek lyrics so we can skip every line that only contains ASCII.
+/// Which lines of the lyrics are dropped as chords and other metadata.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MetadataLines {
+    /// Every line that only contains ASCII, which also drops lyrics written in the Latin
+    /// alphabet.
+    Ascii,
+    /// Lines whose words are all chords, repetition marks like "2x" or symbols, so that lyrics
+    /// in other languages, e.g. the Turkish verses of smyrneika, are indexed.
+    Chords,
+}
+
+impl FromStr for MetadataLines {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<MetadataLines, String> {
+        match s {
+            "ascii" => Ok(MetadataLines::Ascii),
+            "chords" => Ok(MetadataLines::Chords),
+            _ => Err(format!("Unknown metadata lines {}", s)),
+        }
+    }
+}
+
+/// Language of the verses of a song that are not in Greek, e.g. the Turkish verses of
+/// smyrneika. They are stemmed with its stemmer instead of the Greek one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ForeignLanguage {
+    Turkish,
+    English,
+}
+
+impl FromStr for ForeignLanguage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ForeignLanguage, String> {
+        match s {
+            "tr" => Ok(ForeignLanguage::Turkish),
+            "en" => Ok(ForeignLanguage::English),
+            _ => Err(format!("Unknown language {}", s)),
+        }
+    }
+}
+
+/// Whether the line of the lyrics is written in the Latin alphabet.
+fn is_foreign(line: &str) -> bool {
+    detect_script(line) == Script::Latin
+}
+
+/// Chords like "Bm", "F#7", "Gsus4" or "D/F#".
+pub const CHORD_PATTERN: &str =
+    r"[A-G][#b]?(m|maj|min|dim|aug|sus|add|M|\+|°|ø)?\d*((#|b|add|sus|maj)\d+)*(/[A-G][#b]?)?";
+
+/// What happens to the dashes of the lyrics.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dashes {
+    /// Dashes that stretch the syllables of a word, e.g. "πα-ρά-θυ-ρα", are removed, and those
+    /// of hyphenated words and names, e.g. "Αγια-Σοφιά", are kept.
+    Syllables,
+    /// Every dash is removed.
+    Remove,
+    /// Every dash is kept.
+    Keep,
+}
+
+impl FromStr for Dashes {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Dashes, String> {
+        match s {
+            "syllables" => Ok(Dashes::Syllables),
+            "remove" => Ok(Dashes::Remove),
+            "keep" => Ok(Dashes::Keep),
+            _ => Err(format!("Unknown dash policy {}", s)),
+        }
+    }
+}
+
+/// Length in letters of the longest syllable, apart from the last one of a word.
+const MAX_SYLLABLE_LEN: usize = 3;
+
+/// Join the words that are split into syllables by dashes. A word is only joined if all of its
+/// pieces but the last are short enough to be syllables, and none after the first is
+/// capitalized like the parts of a hyphenated name.
+fn join_syllables(text: &str) -> Cow<'_, str> {
     lazy_static! {
-        static ref RE: Regex = Regex::new(r"^[[:ascii:]]*$").unwrap();
+        static ref DASHED: Regex = Regex::new(r"[\p{L}\p{M}]+(-+[\p{L}\p{M}]+)+").unwrap();
     }
 
-    let lines: Vec<&str> = string
-        .split('\n')
-        .filter(|line| !RE.is_match(line))
-        .map(|line| {
-            // Trim any symbols that indicate lyric repetition (e.g. "| 2x")
-            line.trim_end_matches(|c: char| c == ' ' || c == '|' || c.is_ascii_digit() || c == 'x')
+    DASHED.replace_all(text, |captures: &Captures| {
+        let word = &captures[0];
+        let pieces: Vec<&str> = word.split('-').filter(|piece| !piece.is_empty()).collect();
+        let syllables = pieces[..pieces.len() - 1]
+            .iter()
+            .all(|piece| piece.chars().filter(|c| c.is_alphabetic()).count() <= MAX_SYLLABLE_LEN)
+            && pieces[1..]
+                .iter()
+                .all(|piece| !piece.starts_with(char::is_uppercase));
+        if syllables {
+            pieces.concat()
+        } else {
+            word.to_string()
+        }
+    })
+}
+
+/// Marks at the end of a line that tell how many times it's sung, like "2x", "x2", "×2", "2χ",
+/// "(x3)" or "(δις)".
+pub const REPETITION_PATTERN: &str =
+    r"\d+\s*[xX×χΧ]|[xX×χΧ]\s*\d+|\(\s*(\d+\s*[xX×χΧ]|[xX×χΧ]\s*\d+|(?i:δ[ιί]ς))\s*\)";
+
+/// Words of the notes in parentheses that tell how the lyrics are performed, e.g. "(ρεφραίν)".
+pub const PERFORMANCE_NOTES: &[&str] = &[
+    "ρεφραίν",
+    "ρεφρέν",
+    "οργανικό",
+    "οργανικά",
+    "σόλο",
+    "ταξίμι",
+    "εισαγωγή",
+    "γέφυρα",
+    "επωδός",
+    "δις",
+    "τρις",
+    "φορές",
+    "solo",
+    "intro",
+    "chorus",
+    "refrain",
+    "instrumental",
+];
+
+/// How the metadata is stripped from the lyrics of the songs.
+#[derive(Clone, Debug)]
+pub struct LyricsFilter {
+    pub metadata: MetadataLines,
+    pub dashes: Dashes,
+    /// Matches the whole of a chord, see `CHORD_PATTERN`.
+    chord: Regex,
+    /// Matches a repetition mark at the end of a line, along with the bars and the whitespace
+    /// around it, see `REPETITION_PATTERN`.
+    repetition: Regex,
+    /// Words of the performance notes, lowercased and without accents.
+    notes: HashSet<String>,
+}
+
+impl Default for LyricsFilter {
+    fn default() -> LyricsFilter {
+        LyricsFilter::new(MetadataLines::Chords, CHORD_PATTERN).unwrap()
+    }
+}
+
+impl LyricsFilter {
+    /// Strips the default repetition marks and joins syllables. Fails if the chord pattern is not a valid regex.
+    pub fn new(metadata: MetadataLines, chord_pattern: &str) -> Result<LyricsFilter> {
+        let chord = Regex::new(&format!("^(?:{})$", chord_pattern))
+            .map_err(|e| Error::Parse(format!("Invalid chord pattern: {}", e)))?;
+        let repetition = repetition_regex(REPETITION_PATTERN)?;
+        Ok(LyricsFilter {
+            metadata,
+            dashes: Dashes::Syllables,
+            chord,
+            repetition,
+            notes: HashSet::new(),
+        }
+        .with_notes(PERFORMANCE_NOTES))
+    }
+
+    /// Remove the notes in parentheses that only hold the given words, e.g. "(ρεφραίν x2)",
+    /// instead of those of `PERFORMANCE_NOTES`. Words are compared lowercased and without
+    /// accents, and no notes are removed if there are no words.
+    pub fn with_notes(mut self, words: &[&str]) -> LyricsFilter {
+        self.notes = words.iter().map(|word| to_greek_lowercase(word)).collect();
+        self
+    }
+
+    /// Whether the text in parentheses is a performance note rather than lyrics. Numbers and
+    /// repetition counts, e.g. "2" or "x2", may accompany the words.
+    fn is_note(&self, text: &str) -> bool {
+        let mut words = text
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .peekable();
+        words.peek().is_some()
+            && words.all(|word| {
+                let count = word.trim_matches(|c| matches!(c, 'x' | 'X' | 'χ' | 'Χ'));
+                self.notes.contains(&to_greek_lowercase(word))
+                    || !count.is_empty() && count.chars().all(|c| c.is_ascii_digit())
+            })
+    }
+
+    /// Remove the performance notes of the text, along with the whitespace that separates them
+    /// from the lyrics.
+    fn strip_notes<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        lazy_static! {
+            static ref PARENTHESES: Regex =
+                Regex::new(r"(?m)(^)?[ \t]*\(([^()\n]*)\)([ \t]*)").unwrap();
+        }
+
+        if self.notes.is_empty() {
+            return Cow::Borrowed(text);
+        }
+        PARENTHESES.replace_all(text, |captures: &Captures| {
+            if !self.is_note(&captures[2]) {
+                captures[0].to_string()
+            } else if captures.get(1).is_some() {
+                // At the start of the line, so the whitespace after it goes too.
+                String::new()
+            } else {
+                captures[3].to_string()
+            }
         })
-        .collect();
+    }
 
-    // Remove dashes that represent syllable repetition
-    lines.join("\n").chars().filter(|&c| c != '-').collect()
+    /// Strip the repetition marks of the given regex instead of the default ones.
+    pub fn with_repetition_pattern(mut self, pattern: &str) -> Result<LyricsFilter> {
+        self.repetition = repetition_regex(pattern)?;
+        Ok(self)
+    }
+
+    /// Whether the line holds chords and symbols rather than lyrics.
+    fn is_metadata(&self, line: &str) -> bool {
+        lazy_static! {
+            static ref ASCII: Regex = Regex::new(r"^[[:ascii:]]*$").unwrap();
+        }
+
+        match self.metadata {
+            MetadataLines::Ascii => ASCII.is_match(line),
+            MetadataLines::Chords => line
+                .split_whitespace()
+                .all(|word| self.chord.is_match(word) || !word.chars().any(char::is_alphabetic)),
+        }
+    }
+
+    /// Remove the repetition mark and the trailing bars of the line.
+    fn trim_repetition<'a>(&self, line: &'a str) -> &'a str {
+        let line = match self.repetition.find(line) {
+            Some(mark) => &line[..mark.start()],
+            None => line,
+        };
+        line.trim_end_matches(|c: char| c.is_whitespace() || c == '|')
+    }
+
+    /// Remove lines that contain only chords and symbols and trim unneeded characters.
+    pub fn strip(&self, string: &str) -> String {
+        let string = self.strip_notes(string);
+        let lines: Vec<&str> = string
+            .split('\n')
+            .map(|line| self.trim_repetition(line))
+            .filter(|line| !self.is_metadata(line))
+            .collect();
+
+        let text = lines.join("\n");
+        match self.dashes {
+            Dashes::Syllables => join_syllables(&text).into_owned(),
+            Dashes::Remove => text.chars().filter(|&c| c != '-').collect(),
+            Dashes::Keep => text,
+        }
+    }
+}
+
+/// Match the repetition mark at the end of a line, only if it's a word of its own.
+fn repetition_regex(pattern: &str) -> Result<Regex> {
+    Regex::new(&format!(r"(^|[\s|])[\s|]*(?:{})[\s|]*$", pattern))
+        .map_err(|e| Error::Parse(format!("Invalid repetition pattern: {}", e)))
+}
+
+/// Strip the metadata of the lyrics with the default filter.
+pub fn strip_metadata(string: &str) -> String {
+    lazy_static! {
+        static ref DEFAULT: LyricsFilter = LyricsFilter::default();
+    }
+
+    DEFAULT.strip(string)
+}
+
+/// Songs with fewer words of lyrics are reported as suspiciously short.
+const MIN_LYRICS_WORDS: usize = 10;
+
+/// Songs that had a larger fraction of their lines stripped are reported, since their lyrics
+/// may have been taken for chords.
+const MAX_STRIPPED_RATIO: f32 = 0.8;
+
+/// Size of the lyrics of a song, and how much of the song file was stripped from them.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LyricsStats {
+    pub lines: usize,
+    pub words: usize,
+    /// Non-empty lines of the song file that were stripped, e.g. chords.
+    pub stripped_lines: usize,
 }
 
+impl LyricsStats {
+    /// Fraction of the non-empty lines of the song file that were stripped.
+    pub fn stripped_ratio(&self) -> f32 {
+        match self.lines + self.stripped_lines {
+            0 => 0.0,
+            total => self.stripped_lines as f32 / total as f32,
+        }
+    }
+
+    /// Describe what looks wrong with the lyrics, which usually points to a formatting error of
+    /// the song file.
+    pub fn anomaly(&self) -> Option<&'static str> {
+        if self.lines == 0 {
+            Some("no lyrics are left after stripping")
+        } else if self.words < MIN_LYRICS_WORDS {
+            Some("the lyrics are suspiciously short")
+        } else if self.stripped_ratio() > MAX_STRIPPED_RATIO {
+            Some("most lines were stripped")
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Song {
     pub name: String,
     pub slug: String,
     pub artist: String,
     pub artist_slug: String,
+    pub year: Option<u64>,
+    /// Name of the scale, without the tonic, e.g. "Φυσικό Μινόρε".
+    pub scale: Option<String>,
+    /// Name of the rhythm, e.g. "Ζεϊμπέκικο Παλιό".
+    pub rhythm: Option<String>,
     pub body: String,
     pub body_greeklish: String,
+    /// The lyrics as written in the song file, with the chords and other metadata.
+    pub raw_body: Option<String>,
+    /// Language of the verses in the Latin alphabet, if the song file names it.
+    pub language: Option<ForeignLanguage>,
+    /// When the song file was last modified, in seconds since the Unix epoch.
+    pub modified: Option<u64>,
+    /// Number of non-empty lines of the lyrics of the song file that were stripped.
+    pub stripped_lines: usize,
 }
 
 impl Song {
-    pub fn from_path(path: &std::path::Path) -> tantivy::Result<Song> {
+    /// Create a song from its name, artist and lyrics, without any other metadata.
+    pub fn new(
+        name: impl Into<String>,
+        artist: impl Into<String>,
+        body: impl Into<String>,
+        transliteration: &Transliteration,
+    ) -> Song {
+        Song::builder(name, artist, body).build(transliteration)
+    }
+
+    pub fn builder(
+        name: impl Into<String>,
+        artist: impl Into<String>,
+        body: impl Into<String>,
+    ) -> SongBuilder {
+        SongBuilder {
+            name: name.into(),
+            artist: artist.into(),
+            body: body.into(),
+            year: None,
+            scale: None,
+            rhythm: None,
+            language: None,
+            modified: None,
+            raw_body: None,
+        }
+    }
+
+    /// Return the first two lines of the lyrics, to be shown as a teaser.
+    pub fn preview(&self) -> String {
+        self.body.lines().take(2).collect::<Vec<&str>>().join("\n")
+    }
+
+    pub fn lyrics_stats(&self) -> LyricsStats {
+        LyricsStats {
+            lines: self.body.lines().count(),
+            words: self.body.split_whitespace().count(),
+            stripped_lines: self.stripped_lines,
+        }
+    }
+
+    /// Return the lines of the lyrics that are written in the Latin alphabet.
+    pub fn foreign_lines(&self) -> String {
+        let lines: Vec<&str> = self.body.lines().filter(|line| is_foreign(line)).collect();
+        lines.join("\n")
+    }
+
+    pub fn from_path(
+        path: &std::path::Path,
+        transliteration: &Transliteration,
+        lyrics: &LyricsFilter,
+    ) -> Result<Song> {
         let contents = std::fs::read_to_string(path)?;
+        let mut song = Song::parse(&contents, transliteration, lyrics)
+            .map_err(|e| Error::Parse(format!("{}: {}", path.display(), e)))?;
+        song.modified = std::fs::metadata(path)?
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|age| age.as_secs());
+        Ok(song)
+    }
+
+    /// Parse the contents of a song file: the name, the artist, an optional url and an optional
+    /// language of the foreign verses, e.g. "lang: tr", on separate lines, followed by the
+    /// scale, the rhythm and the lyrics, separated by empty lines.
+    pub fn parse(
+        contents: &str,
+        transliteration: &Transliteration,
+        lyrics: &LyricsFilter,
+    ) -> Result<Song> {
         let mut parts = contents.splitn(4, "\n\n");
-        let head = parts.next().unwrap();
-        let _song_scale = parts.next().unwrap();
-        let _song_rhythm = parts.next().unwrap();
-        let song_body = parts.next().unwrap();
+        let (head, song_scale, song_rhythm, song_body) =
+            match (parts.next(), parts.next(), parts.next(), parts.next()) {
+                (Some(head), Some(song_scale), Some(song_rhythm), Some(song_body)) => {
+                    (head, song_scale, song_rhythm, song_body)
+                }
+                _ => return Err(Error::Parse(String::from("Invalid song format"))),
+            };
 
         let head_parts: Vec<&str> = head.split('\n').collect();
-        let (song_name, song_artist) = match head_parts[..] {
-            [song_name, song_artist, _song_url] => (song_name, song_artist),
-            [song_name, song_artist] => (song_name, song_artist),
-            _ => {
-                error!("Invalid song format");
-                std::process::exit(1);
+        let (song_name, song_artist, song_language) = match head_parts[..] {
+            [song_name, song_artist, language] | [song_name, song_artist, _, language]
+                if language.starts_with("lang:") =>
+            {
+                let language = language["lang:".len()..]
+                    .trim()
+                    .parse()
+                    .map_err(Error::Parse)?;
+                (song_name, song_artist, Some(language))
             }
+            [song_name, song_artist, _song_url] => (song_name, song_artist, None),
+            [song_name, song_artist] => (song_name, song_artist, None),
+            _ => return Err(Error::Parse(String::from("Invalid song head"))),
         };
 
-        // Remove possible year in parentheses at the end of the song_name.
-        let song_name = song_name.split(" (").next().unwrap();
+        // Split possible year in parentheses at the end of the song_name.
+        let mut name_parts = song_name.splitn(2, " (");
+        let song_name = name_parts.next().unwrap();
+        let song_year = name_parts
+            .next()
+            .and_then(|year| year.trim_end_matches(')').parse().ok());
 
-        let song_body = strip_metadata(song_body);
-        let song_body_greeklish = to_greeklish(song_body.as_str());
+        // The scale follows the tonic, e.g. "B  Φυσικό Μινόρε".
+        let song_scale = song_scale
+            .trim()
+            .split_once(char::is_whitespace)
+            .map(|(_tonic, scale)| scale.trim().to_string());
+        let song_rhythm = Some(song_rhythm.trim())
+            .filter(|rhythm| !rhythm.is_empty())
+            .map(String::from);
 
-        Ok(Song {
-            name: String::from(song_name),
-            slug: to_greeklish(song_name),
-            artist: String::from(song_artist),
-            artist_slug: to_greeklish(song_artist),
-            body: song_body,
-            body_greeklish: song_body_greeklish,
-        })
+        let source_lines = song_body
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .count();
+        let raw_body = song_body.trim_end().to_string();
+        let song_body = lyrics.strip(song_body);
+        let stripped_lines = source_lines.saturating_sub(song_body.lines().count());
+
+        let mut song = Song::builder(song_name, song_artist, song_body)
+            .year(song_year)
+            .scale(song_scale)
+            .rhythm(song_rhythm)
+            .language(song_language)
+            .raw_body(Some(raw_body))
+            .build(transliteration);
+        song.stripped_lines = stripped_lines;
+        Ok(song)
+    }
+}
+
+/// Builds a song from its fields instead of a song file, e.g. when the songs are kept in
+/// another store. The slugs and the greeklish are derived from the name, the artist and the
+/// lyrics, which are taken as they are.
+pub struct SongBuilder {
+    name: String,
+    artist: String,
+    body: String,
+    year: Option<u64>,
+    scale: Option<String>,
+    rhythm: Option<String>,
+    language: Option<ForeignLanguage>,
+    modified: Option<u64>,
+    raw_body: Option<String>,
+}
+
+impl SongBuilder {
+    pub fn year(mut self, year: Option<u64>) -> SongBuilder {
+        self.year = year;
+        self
+    }
+
+    /// Name of the scale, without the tonic.
+    pub fn scale(mut self, scale: Option<String>) -> SongBuilder {
+        self.scale = scale;
+        self
+    }
+
+    pub fn rhythm(mut self, rhythm: Option<String>) -> SongBuilder {
+        self.rhythm = rhythm;
+        self
+    }
+
+    /// Language of the verses in the Latin alphabet, which are then left out of the greeklish.
+    pub fn language(mut self, language: Option<ForeignLanguage>) -> SongBuilder {
+        self.language = language;
+        self
+    }
+
+    /// When the song was last modified, in seconds since the Unix epoch.
+    pub fn modified(mut self, modified: Option<u64>) -> SongBuilder {
+        self.modified = modified;
+        self
+    }
+
+    /// The lyrics with the chords and other metadata, if they are known.
+    pub fn raw_body(mut self, raw_body: Option<String>) -> SongBuilder {
+        self.raw_body = raw_body;
+        self
+    }
+
+    pub fn build(self, transliteration: &Transliteration) -> Song {
+        // The greeklish of Latin verses would only mangle them, so they are left out if their
+        // language is known.
+        let body_greeklish = match self.language {
+            Some(_) => {
+                let lines: Vec<&str> = self.body.lines().filter(|line| !is_foreign(line)).collect();
+                transliteration.to_greeklish(&lines.join("\n"))
+            }
+            None => transliteration.to_greeklish(&self.body),
+        };
+
+        Song {
+            slug: transliteration.to_slug(&self.name),
+            artist_slug: transliteration.to_slug(&self.artist),
+            name: self.name,
+            artist: self.artist,
+            year: self.year,
+            scale: self.scale,
+            rhythm: self.rhythm,
+            body: self.body,
+            body_greeklish,
+            raw_body: self.raw_body,
+            language: self.language,
+            modified: self.modified,
+            stripped_lines: 0,
+        }
     }
 }
 
@@ -75,7 +567,11 @@ mod tests {
 
     use tempfile::NamedTempFile;
 
-    use crate::song::Song;
+    use crate::config::NumberPolicy;
+    use crate::song::{
+        Dashes, ForeignLanguage, LyricsFilter, LyricsStats, MetadataLines, Song, CHORD_PATTERN,
+    };
+    use crate::transliteration::Transliteration;
 
     #[test]
     fn test_song() {
@@ -109,12 +605,20 @@ mod tests {
         );
         file.write_all(file_content.as_bytes()).unwrap();
 
-        let song = Song::from_path(&file.path()).unwrap();
+        let transliteration = Transliteration::new(NumberPolicy::Keep);
+        let song =
+            Song::from_path(file.path(), &transliteration, &LyricsFilter::default()).unwrap();
 
         assert_eq!(song.name, "Τα μπλε παράθυρά σου");
         assert_eq!(song.slug, "ta_mple_parathyra_sou");
         assert_eq!(song.artist, "Μάρκος Βαμβακάρης");
         assert_eq!(song.artist_slug, "markos_vamvakaris");
+        assert_eq!(song.scale.as_deref(), Some("Φυσικό Μινόρε"));
+        assert_eq!(song.rhythm.as_deref(), Some("Ζεϊμπέκικο Παλιό"));
+        assert!(song.modified.is_some());
+        let stats = song.lyrics_stats();
+        assert_eq!((stats.lines, stats.stripped_lines), (9, 4));
+        assert_eq!(stats.anomaly(), None);
         assert_eq!(
             song.body,
             concat!(
@@ -129,6 +633,16 @@ mod tests {
                 "όπως και πριν να σε θωρώ απ' τα παράθυρά σου",
             )
         );
+        let raw_body = song.raw_body.as_deref().unwrap();
+        assert!(raw_body.starts_with("Bm  Bm  F#  Bm   | 4x\n\nD\n"));
+        assert!(raw_body.ends_with("απ' τα παράθυρά σου"));
+        assert_eq!(
+            song.preview(),
+            concat!(
+                "Περνούσα και σ' αντίκρυζα ψηλά στα παραθύρια\n",
+                "και τότες πια καμάρωνα τα δυο σου μαύρα φρύδια",
+            )
+        );
         assert_eq!(
             song.body_greeklish,
             concat!(
@@ -144,4 +658,225 @@ mod tests {
             )
         );
     }
+
+    #[test]
+    fn test_song_builder() {
+        let transliteration = Transliteration::new(NumberPolicy::Keep);
+        let song = Song::new(
+            "Φραγκοσυριανή",
+            "Μάρκος Βαμβακάρης",
+            "Μια φούντωση μια φλόγα",
+            &transliteration,
+        );
+        assert_eq!(song.slug, "fragkosyriani");
+        assert_eq!(song.artist_slug, "markos_vamvakaris");
+        assert_eq!(song.body_greeklish, "mia_fountosi_mia_floga");
+        assert_eq!(
+            (song.year, song.modified, song.language),
+            (None, None, None)
+        );
+
+        let song = Song::builder("Σμυρνέικο", "Ρόζα Εσκενάζυ", "Στη Σμύρνη\nAman sevdiğim")
+            .year(Some(1934))
+            .language(Some(ForeignLanguage::Turkish))
+            .modified(Some(1_600_000_000))
+            .build(&transliteration);
+        assert_eq!(song.year, Some(1934));
+        assert_eq!(song.modified, Some(1_600_000_000));
+        assert_eq!(song.body, "Στη Σμύρνη\nAman sevdiğim");
+        assert_eq!(song.body_greeklish, "sti_smyrni");
+        assert_eq!(song.raw_body, None);
+    }
+
+    #[test]
+    fn test_lyrics_stats() {
+        let stats = |lines, words, stripped_lines| LyricsStats {
+            lines,
+            words,
+            stripped_lines,
+        };
+        assert_eq!(stats(0, 0, 0).stripped_ratio(), 0.0);
+        assert_eq!(stats(1, 30, 3).stripped_ratio(), 0.75);
+        assert_eq!(stats(1, 30, 3).anomaly(), None);
+        assert!(stats(1, 30, 5).anomaly().is_some());
+        assert!(stats(2, 9, 0).anomaly().is_some());
+        assert!(stats(0, 0, 4).anomaly().is_some());
+    }
+
+    #[test]
+    fn test_song_invalid() {
+        let transliteration = Transliteration::new(NumberPolicy::Keep);
+        let lyrics = LyricsFilter::default();
+        assert!(Song::parse("", &transliteration, &lyrics).is_err());
+        assert!(Song::parse("Φραγκοσυριανή\n\nΧασάπικο", &transliteration, &lyrics).is_err());
+        assert!(Song::parse(
+            "Φραγκοσυριανή\n\nD\n\nΧασάπικο\n\n",
+            &transliteration,
+            &lyrics
+        )
+        .is_err());
+        let song = Song::parse(
+            "Φραγκοσυριανή\nΜάρκος\n\nD\n\nΧασάπικο\n\n",
+            &transliteration,
+            &lyrics,
+        )
+        .unwrap();
+        assert_eq!(song.scale, None);
+        assert_eq!(song.rhythm.as_deref(), Some("Χασάπικο"));
+    }
+
+    #[test]
+    fn test_song_year() {
+        let transliteration = Transliteration::new(NumberPolicy::Keep);
+        let lyrics = LyricsFilter::default();
+        let parse = |title: &str| {
+            let contents = format!("{}\nΜάρκος Βαμβακάρης\n\nD\n\nΖεϊμπέκικο\n\nΑμάν\n", title);
+            Song::parse(&contents, &transliteration, &lyrics).unwrap()
+        };
+        let song = parse("Τα μπλε παράθυρά σου (1936)");
+        assert_eq!(song.name, "Τα μπλε παράθυρά σου");
+        assert_eq!(song.slug, "ta_mple_parathyra_sou");
+        assert_eq!(song.year, Some(1936));
+
+        let song = parse("Τα μπλε παράθυρά σου (παλιό)");
+        assert_eq!(song.name, "Τα μπλε παράθυρά σου");
+        assert_eq!(song.year, None);
+        assert_eq!(parse("Τα μπλε παράθυρά σου").year, None);
+    }
+
+    #[test]
+    fn test_song_language() {
+        let transliteration = Transliteration::new(NumberPolicy::Keep);
+        let lyrics = LyricsFilter::default();
+        let contents = |head: &str| {
+            format!(
+                "Σμυρνέικο\nΡόζα Εσκενάζυ{}\n\nD\n\nΖεϊμπέκικο\n\nΣτη Σμύρνη\nAman sevdiğim\n",
+                head
+            )
+        };
+        let song = Song::parse(&contents(""), &transliteration, &lyrics).unwrap();
+        assert_eq!(song.language, None);
+        assert_eq!(song.foreign_lines(), "Aman sevdiğim");
+        assert!(song.body_greeklish.contains("aman"));
+
+        let head = "\nhttps://www.youtube.com/watch?v=CPYwCdRL8GU\nlang: tr";
+        let song = Song::parse(&contents(head), &transliteration, &lyrics).unwrap();
+        assert_eq!(song.language, Some(ForeignLanguage::Turkish));
+        assert_eq!(song.body, "Στη Σμύρνη\nAman sevdiğim");
+        assert_eq!(song.body_greeklish, "sti_smyrni");
+
+        assert!(Song::parse(&contents("\nlang: xx"), &transliteration, &lyrics).is_err());
+    }
+
+    #[test]
+    fn test_lyrics_filter() {
+        let lyrics = concat!(
+            "Am    Dm  | 2x\n",
+            "Αμάν αμάν\n",
+            "E7/G#  Gsus4  Am  x2\n",
+            "Yanayım yanayım\n",
+            "Ah aman, aman\n",
+        );
+        assert_eq!(
+            LyricsFilter::default().strip(lyrics),
+            "Αμάν αμάν\nYanayım yanayım\nAh aman, aman"
+        );
+        let ascii = LyricsFilter::new(MetadataLines::Ascii, CHORD_PATTERN).unwrap();
+        assert_eq!(ascii.strip(lyrics), "Αμάν αμάν\nYanayım yanayım");
+        let custom = LyricsFilter::new(MetadataLines::Chords, "[A-G]m?").unwrap();
+        assert_eq!(
+            custom.strip(lyrics),
+            "Αμάν αμάν\nE7/G#  Gsus4  Am\nYanayım yanayım\nAh aman, aman"
+        );
+        assert!(LyricsFilter::new(MetadataLines::Chords, "(").is_err());
+    }
+
+    #[test]
+    fn test_repetition_marks() {
+        let filter = LyricsFilter::default();
+        for line in &[
+            "Σαν τα μάρμαρα του Αλγερίου   | 2x",
+            "Σαν τα μάρμαρα του Αλγερίου x2",
+            "Σαν τα μάρμαρα του Αλγερίου ×2",
+            "Σαν τα μάρμαρα του Αλγερίου 2χ",
+            "Σαν τα μάρμαρα του Αλγερίου (δις)",
+            "Σαν τα μάρμαρα του Αλγερίου  (Δίς) |",
+            "Σαν τα μάρμαρα του Αλγερίου (x 3)",
+            "Σαν τα μάρμαρα του Αλγερίου |",
+        ] {
+            assert_eq!(
+                filter.strip(line),
+                "Σαν τα μάρμαρα του Αλγερίου",
+                "{}",
+                line
+            );
+        }
+        // Trailing letters and numbers of the lyrics are kept.
+        assert_eq!(filter.strip("Μάνα μου, τα 22"), "Μάνα μου, τα 22");
+        assert_eq!(filter.strip("Ο Φοίνιξ"), "Ο Φοίνιξ");
+        assert_eq!(filter.strip("Boom box"), "Boom box");
+        assert_eq!(filter.strip("Ο Max2"), "Ο Max2");
+        // Lines of marks alone are dropped.
+        assert_eq!(filter.strip("Αμάν\n(δις)\nαμάν"), "Αμάν\nαμάν");
+
+        let custom = filter.with_repetition_pattern(r"\[\d\]").unwrap();
+        assert_eq!(custom.strip("Αμάν αμάν [2]"), "Αμάν αμάν");
+        assert_eq!(custom.strip("Αμάν αμάν x2"), "Αμάν αμάν x2");
+        assert!(LyricsFilter::default()
+            .with_repetition_pattern("(")
+            .is_err());
+    }
+
+    #[test]
+    fn test_dashes() {
+        let mut filter = LyricsFilter::default();
+        let lyrics = concat!(
+            "Ψη-λά στα πα-ρα-θύ-ρια\n",
+            "Στην Αγια-Σοφιά, στον Χατζη-Μανώλη\n",
+            "Αμάν - αμάν, ντερβίσης--μάγκας\n",
+        );
+        assert_eq!(
+            filter.strip(lyrics),
+            concat!(
+                "Ψηλά στα παραθύρια\n",
+                "Στην Αγια-Σοφιά, στον Χατζη-Μανώλη\n",
+                "Αμάν - αμάν, ντερβίσης--μάγκας",
+            )
+        );
+        filter.dashes = Dashes::Remove;
+        assert_eq!(
+            filter.strip(lyrics),
+            concat!(
+                "Ψηλά στα παραθύρια\n",
+                "Στην ΑγιαΣοφιά, στον ΧατζηΜανώλη\n",
+                "Αμάν  αμάν, ντερβίσηςμάγκας",
+            )
+        );
+        filter.dashes = Dashes::Keep;
+        assert_eq!(filter.strip(lyrics), lyrics.trim_end());
+    }
+
+    #[test]
+    fn test_performance_notes() {
+        let filter = LyricsFilter::default();
+        let lyrics = concat!(
+            "(Ρεφραίν)\n",
+            "Σαν τα μάρμαρα (οργανικό x2) του Αλγερίου\n",
+            "(ρεφρέν) Αμάν αμάν (σόλο)\n",
+            "Κι αν με ρωτάς (πού πας;) δεν ξέρω\n",
+        );
+        assert_eq!(
+            filter.strip(lyrics),
+            concat!(
+                "Σαν τα μάρμαρα του Αλγερίου\n",
+                "Αμάν αμάν\n",
+                "Κι αν με ρωτάς (πού πας;) δεν ξέρω",
+            )
+        );
+        let custom = filter.with_notes(&["πού", "πας"]);
+        assert_eq!(custom.strip("Κι αν με ρωτάς (πού πας;)"), "Κι αν με ρωτάς");
+        assert_eq!(custom.strip("Αμάν (σόλο)"), "Αμάν (σόλο)");
+        let none = LyricsFilter::default().with_notes(&[]);
+        assert_eq!(none.strip("Αμάν (σόλο)"), "Αμάν (σόλο)");
+    }
 }
@@ -5,46 +5,77 @@ use tantivy::tokenizer::{BoxTokenStream, Token, TokenStream, Tokenizer};
 /// Tokenize the text by splitting on whitespace and punctuation and finding all ngrams for each
 /// word. Based on the built-in tantivy SimpleTokenizer.
 #[derive(Clone)]
-pub struct NgramTokenizer;
+pub struct NgramTokenizer {
+    /// Words of this many bytes or longer are skipped without generating any ngrams, like
+    /// RemoveLongFilter does for whole words.
+    max_word_len: usize,
+}
+
+impl NgramTokenizer {
+    pub fn limit(max_word_len: usize) -> NgramTokenizer {
+        NgramTokenizer { max_word_len }
+    }
+}
 
 pub struct SimpleTokenStream<'a> {
     text: &'a str,
     chars: CharIndices<'a>,
     token: Token,
 
+    /// Byte offset in text of the first character of the current word.
     offset_from: usize,
+    /// Whether we are between words, i.e. the next alphanumeric character starts a new word.
     first: bool,
-    last: bool,
+    max_word_len: usize,
+    /// Byte offset in text of the end of a word that is too long and is being skipped.
+    skip_until: usize,
 }
 
 impl Tokenizer for NgramTokenizer {
     fn token_stream<'a>(&self, text: &'a str) -> BoxTokenStream<'a> {
         BoxTokenStream::from(SimpleTokenStream {
-            text: text.trim(),
+            text,
             chars: text.char_indices(),
             token: Token::default(),
 
             offset_from: 0,
             first: true,
-            last: false,
+            max_word_len: self.max_word_len,
+            skip_until: 0,
         })
     }
 }
 
+impl<'a> SimpleTokenStream<'a> {
+    /// Set the token to the current word up to the given byte offset.
+    fn emit(&mut self, offset_to: usize) {
+        self.token.offset_from = self.offset_from;
+        self.token.offset_to = offset_to;
+        self.token
+            .text
+            .push_str(&self.text[self.offset_from..offset_to]);
+    }
+}
+
 impl<'a> TokenStream for SimpleTokenStream<'a> {
     fn advance(&mut self) -> bool {
         self.token.text.clear();
-        self.token.position = self.token.position.wrapping_add(1);
-
-        if self.last {
-            return false;
-        }
 
         loop {
             match self.chars.next() {
                 Some((offset, c)) => {
                     if self.first {
-                        if !c.is_alphanumeric() {
+                        if offset < self.skip_until || !c.is_alphanumeric() {
+                            continue;
+                        }
+                        // All the prefixes of a word share its position, so that phrase
+                        // queries work.
+                        self.token.position = self.token.position.wrapping_add(1);
+                        let word_len = self.text[offset..]
+                            .find(|c: char| !c.is_alphanumeric())
+                            .unwrap_or(self.text.len() - offset);
+                        if word_len >= self.max_word_len {
+                            self.skip_until = offset + word_len;
                             continue;
                         }
                         self.offset_from = offset;
@@ -52,11 +83,7 @@ impl<'a> TokenStream for SimpleTokenStream<'a> {
                         continue;
                     }
 
-                    self.token.offset_from = self.offset_from;
-                    self.token.offset_to = offset;
-                    self.token
-                        .text
-                        .push_str(&self.text[self.offset_from..offset]);
+                    self.emit(offset);
 
                     if !c.is_alphanumeric() {
                         self.first = true;
@@ -65,16 +92,11 @@ impl<'a> TokenStream for SimpleTokenStream<'a> {
                     return true;
                 }
                 None => {
-                    self.last = true;
-
-                    let offset_to = self.text.len();
-
-                    self.token.offset_from = self.offset_from;
-                    self.token.offset_to = offset_to;
-                    self.token
-                        .text
-                        .push_str(&self.text[self.offset_from..offset_to]);
-
+                    if self.first {
+                        return false;
+                    }
+                    self.first = true;
+                    self.emit(self.text.len());
                     return true;
                 }
             }
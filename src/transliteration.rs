@@ -0,0 +1,588 @@
+use std::collections::HashMap;
+
+use lazy_static::lazy_static;
+use log::warn;
+use regex::Regex;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::config::NumberPolicy;
+use crate::error::{Error, Result};
+
+/// Greek candidates are generated for each word up to this many.
+const MAX_CANDIDATES: usize = 8;
+
+/// The default romanization of Greek letters and digraphs.
+const GREEKLISH: &[(&str, &str)] = &[
+    ("ψ", "ps"),
+    ("ξ", "ks"),
+    ("θ", "th"),
+    ("ου", "ou"),
+    ("ού", "ou"),
+    ("αυ", "au"),
+    ("αύ", "au"),
+    ("ευ", "eu"),
+    ("εύ", "eu"),
+    ("α", "a"),
+    ("ά", "a"),
+    ("β", "v"),
+    ("γ", "g"),
+    ("δ", "d"),
+    ("ε", "e"),
+    ("έ", "e"),
+    ("ζ", "z"),
+    ("η", "i"),
+    ("ή", "i"),
+    ("ι", "i"),
+    ("ί", "i"),
+    ("ϊ", "i"),
+    ("ΐ", "i"),
+    ("κ", "k"),
+    ("λ", "l"),
+    ("μ", "m"),
+    ("ν", "n"),
+    ("ο", "o"),
+    ("ό", "o"),
+    ("π", "p"),
+    ("ρ", "r"),
+    ("σ", "s"),
+    ("ς", "s"),
+    ("τ", "t"),
+    ("υ", "y"),
+    ("ύ", "y"),
+    ("φ", "f"),
+    ("χ", "x"),
+    ("ω", "o"),
+    ("ώ", "o"),
+    // We always replace spaces with underscores. We don't need that for searching, only for
+    // storing the slug, but it also works for searching so we leave it like that for simplicity.
+    (" ", "_"),
+];
+
+/// Greek letters from the most to the least frequent, used to try the likelier Greek candidates
+/// of greeklish words first, e.g. "ι" before "η" for "i".
+const LETTER_FREQUENCY: &str = "αοιετσνηυρπκμλωγδθχφβξζψ";
+
+/// Return the rank of the first letter of the text in `LETTER_FREQUENCY`, and the text itself
+/// to break ties.
+fn letter_rank(text: &str) -> (usize, String) {
+    let first = text.chars().next().unwrap_or_default();
+    let rank = LETTER_FREQUENCY
+        .chars()
+        .position(|letter| letter == first)
+        .unwrap_or(usize::MAX);
+    (rank, text.to_string())
+}
+
+/// Romanization of Greek text into greeklish, used for slugs and for searching the lyrics with
+/// Latin queries, and the reverse, used for searching the Greek fields with Latin queries.
+#[derive(Clone)]
+pub struct Transliteration {
+    /// Lowercase letters and digraphs and their romanization.
+    table: HashMap<String, String>,
+    /// Length in characters of the longest key of the table.
+    max_key_len: usize,
+    /// Romanizations and the unaccented letters and digraphs they may come from.
+    reverse_table: HashMap<String, Vec<String>>,
+    /// Length in characters of the longest key of the reverse table.
+    max_reverse_key_len: usize,
+    /// Matches the characters that are removed from the greeklish.
+    disallowed: Regex,
+    numbers: NumberPolicy,
+}
+
+fn default_table() -> HashMap<String, String> {
+    GREEKLISH
+        .iter()
+        .map(|&(greek, latin)| (greek.to_string(), latin.to_string()))
+        .collect()
+}
+
+impl Transliteration {
+    /// Use the default romanization table.
+    pub fn new(numbers: NumberPolicy) -> Transliteration {
+        Transliteration::with_table(default_table(), numbers)
+    }
+
+    /// Read romanizations from a JSON file holding an object that maps letters and digraphs to
+    /// their romanization, e.g. `{"θ": "th", "ου": "u"}`. The entries override the ones of the
+    /// default table, and the letters are lowercased, since the text is looked up lowercased.
+    pub fn from_file(path: &str, numbers: NumberPolicy) -> Result<Transliteration> {
+        let contents = std::fs::read_to_string(path)?;
+        let custom: HashMap<String, String> = serde_json::from_str(&contents)
+            .map_err(|e| Error::Parse(format!("Invalid transliteration table {}: {}", path, e)))?;
+        let mut table = default_table();
+        table.extend(
+            custom
+                .into_iter()
+                .map(|(greek, latin)| (greek.to_lowercase(), latin)),
+        );
+        Ok(Transliteration::with_table(table, numbers))
+    }
+
+    fn with_table(table: HashMap<String, String>, numbers: NumberPolicy) -> Transliteration {
+        let mut reverse_table: HashMap<String, Vec<String>> = HashMap::new();
+        for (greek, latin) in &table {
+            // Only the plain letters are generated, since the analyzers fold accents and the
+            // final sigma anyway.
+            let is_plain = greek.chars().all(|c| ('α'..='ω').contains(&c) && c != 'ς');
+            if is_plain && !latin.is_empty() {
+                reverse_table
+                    .entry(latin.clone())
+                    .or_default()
+                    .push(greek.clone());
+            }
+        }
+        for greeks in reverse_table.values_mut() {
+            greeks.sort_unstable_by_key(|greek| (greek.chars().count(), letter_rank(greek)));
+        }
+        let max_key_len = table.keys().map(|key| key.chars().count()).max();
+        let max_reverse_key_len = reverse_table.keys().map(|key| key.chars().count()).max();
+        Transliteration {
+            table,
+            max_key_len: max_key_len.unwrap_or(1),
+            reverse_table,
+            max_reverse_key_len: max_reverse_key_len.unwrap_or(1),
+            disallowed: Regex::new(r"[^a-z0-9_\n]").unwrap(),
+            numbers,
+        }
+    }
+
+    /// Keep only the given characters in the greeklish, e.g. `a-z_` to drop digits, instead of
+    /// lowercase Latin letters, digits and underscores. `x-y` is a range of characters, and
+    /// every other character stands for itself, including `]`, `\` and `^`.
+    pub fn with_allowed_chars(mut self, chars: &str) -> Result<Transliteration> {
+        let invalid = |reason: &str| {
+            Error::Parse(format!("Invalid allowed characters {}: {}", chars, reason))
+        };
+        let allowed: Vec<char> = chars.chars().collect();
+        let escape = |c: char| regex::escape(c.encode_utf8(&mut [0; 4]));
+        let mut class = String::new();
+        let mut i = 0;
+        while i < allowed.len() {
+            if i + 2 < allowed.len() && allowed[i + 1] == '-' {
+                let (first, last) = (allowed[i], allowed[i + 2]);
+                if first > last {
+                    return Err(invalid(&format!("{} comes after {}", first, last)));
+                }
+                class.push_str(&format!("{}-{}", escape(first), escape(last)));
+                i += 3;
+            } else {
+                class.push_str(&escape(allowed[i]));
+                i += 1;
+            }
+        }
+        if class.is_empty() {
+            return Err(invalid("no characters"));
+        }
+        self.disallowed =
+            Regex::new(&format!("[^{}]", class)).map_err(|e| invalid(&e.to_string()))?;
+        Ok(self)
+    }
+
+    /// Return greek string in greeklish. The longest key of the table that matches at each
+    /// position is replaced, and Latin letters lose their diacritics. Characters that are not
+    /// allowed are removed, and so are digits if numbers are dropped.
+    pub fn to_greeklish(&self, string: &str) -> String {
+        let chars: Vec<char> = string.to_lowercase().chars().collect();
+        let mut output = String::with_capacity(string.len());
+        self.romanize(&chars, |_, _, latin| output.push_str(latin));
+        self.disallowed
+            .replace_all(&output, "")
+            .chars()
+            .filter(|c| !(self.numbers == NumberPolicy::Drop && c.is_ascii_digit()))
+            .collect()
+    }
+
+    /// Like `to_greeklish`, but also return the byte range of the string that each byte of the
+    /// greeklish comes from, e.g. the range of "θ" for both bytes of "th", so that matches in
+    /// the greeklish can be mapped back to the Greek text. Letters are lowercased one at a
+    /// time, so a final "Σ" is looked up as "σ".
+    pub fn to_greeklish_aligned(&self, string: &str) -> (String, Vec<(usize, usize)>) {
+        let mut chars = Vec::with_capacity(string.len());
+        let mut ranges = Vec::with_capacity(string.len());
+        for (offset, c) in string.char_indices() {
+            for lower in c.to_lowercase() {
+                chars.push(lower);
+                ranges.push((offset, offset + c.len_utf8()));
+            }
+        }
+        let mut output = String::with_capacity(string.len());
+        let mut alignment = Vec::with_capacity(string.len());
+        self.romanize(&chars, |i, len, latin| {
+            let range = (ranges[i].0, ranges[i + len - 1].1);
+            for c in latin.chars() {
+                let dropped = self.disallowed.is_match(c.encode_utf8(&mut [0; 4]))
+                    || (self.numbers == NumberPolicy::Drop && c.is_ascii_digit());
+                if !dropped {
+                    output.push(c);
+                    alignment.extend(std::iter::repeat_n(range, c.len_utf8()));
+                }
+            }
+        });
+        (output, alignment)
+    }
+
+    /// Return the Greek text romanized for display, e.g. "Ta mple parathyra sou" for "Τα μπλε
+    /// παράθυρα σου". Unlike `to_greeklish`, the case of the letters is kept, and spaces,
+    /// punctuation, digits and Latin letters are left as they are.
+    pub fn to_display_greeklish(&self, string: &str) -> String {
+        let mut chars = Vec::with_capacity(string.len());
+        let mut originals = Vec::with_capacity(string.len());
+        for c in string.chars() {
+            for lower in c.to_lowercase() {
+                chars.push(lower);
+                originals.push(c);
+            }
+        }
+        let mut output = String::with_capacity(string.len());
+        let mut key = String::new();
+        self.romanize(&chars, |i, len, latin| {
+            let originals = &originals[i..i + len];
+            key.clear();
+            key.extend(&chars[i..i + len]);
+            if !self.table.contains_key(&key) || originals.iter().all(|c| c.is_whitespace()) {
+                output.extend(originals);
+            } else if len > 1 && originals.iter().all(|c| c.is_uppercase()) {
+                // A capitalized digraph, e.g. "ΟΥ" of "ΟΥΣΑΚ".
+                output.push_str(&latin.to_uppercase());
+            } else if originals[0].is_uppercase() {
+                let mut latin_chars = latin.chars();
+                output.extend(latin_chars.next().into_iter().flat_map(char::to_uppercase));
+                output.extend(latin_chars);
+            } else {
+                output.push_str(latin);
+            }
+        });
+        output
+    }
+
+    /// Replace the longest key of the table that matches at each position of the lowercase
+    /// characters, calling `emit` with the position and length of every replaced letter or
+    /// digraph and its romanization. Characters that are not in the table are emitted
+    /// decomposed, so that "é" keeps its "e" and loses the combining accent.
+    fn romanize(&self, chars: &[char], mut emit: impl FnMut(usize, usize, &str)) {
+        let mut key = String::new();
+        let mut decomposed = String::new();
+        let mut i = 0;
+        'chars: while i < chars.len() {
+            for len in (1..=self.max_key_len.min(chars.len() - i)).rev() {
+                key.clear();
+                key.extend(&chars[i..i + len]);
+                if let Some(latin) = self.table.get(&key) {
+                    emit(i, len, latin);
+                    i += len;
+                    continue 'chars;
+                }
+            }
+            decomposed.clear();
+            decomposed.extend(chars[i..=i].iter().copied().nfd());
+            emit(i, 1, &decomposed);
+            i += 1;
+        }
+    }
+
+    /// Return the slug of the name: its greeklish with runs of underscores collapsed and
+    /// without underscores at the edges. Names that leave nothing behind, e.g. ones made of
+    /// punctuation only, get a hash of the name instead.
+    pub fn to_slug(&self, name: &str) -> String {
+        let greeklish = self.to_greeklish(name);
+        let slug = greeklish
+            .split('_')
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<&str>>()
+            .join("_");
+        if slug.is_empty() {
+            return short_hash(name);
+        }
+        slug
+    }
+
+    /// Return the Greek words, lowercase and without accents, that the greeklish word may have
+    /// been transliterated from, e.g. "μαρκοσ" and "μαρκωσ" for "markos". The longest
+    /// romanization that matches at each position is used. Candidates made of more frequent
+    /// letters come first, and only a few of them are kept.
+    pub fn to_greek_candidates(&self, word: &str) -> Vec<String> {
+        let chars: Vec<char> = word.to_lowercase().chars().collect();
+        // Candidates with the sum of the ranks of their letters among the alternatives of
+        // their position. They are pruned at every position, so that the best candidates of
+        // long words aren't crowded out by the alternatives of their first letters.
+        let mut candidates = vec![(0, String::new())];
+        let mut key = String::new();
+        let mut i = 0;
+        while i < chars.len() {
+            let mut alternatives = None;
+            for len in (1..=self.max_reverse_key_len.min(chars.len() - i)).rev() {
+                key.clear();
+                key.extend(&chars[i..i + len]);
+                if let Some(greeks) = self.reverse_table.get(&key) {
+                    alternatives = Some((greeks.as_slice(), len));
+                    break;
+                }
+            }
+            let (greeks, len) = match alternatives {
+                Some(alternatives) => alternatives,
+                None => return Vec::new(),
+            };
+            candidates = candidates
+                .iter()
+                .flat_map(|(rank, candidate)| {
+                    greeks
+                        .iter()
+                        .enumerate()
+                        .map(move |(j, greek)| (rank + j, candidate.clone() + greek))
+                })
+                .collect();
+            candidates.sort_by_key(|&(rank, _)| rank);
+            candidates.truncate(MAX_CANDIDATES);
+            i += len;
+        }
+        candidates
+            .into_iter()
+            .map(|(_, candidate)| candidate)
+            .collect()
+    }
+
+    /// Add the Greek candidates of every plain greeklish word of the query as alternatives,
+    /// e.g. "(markos OR μαρκοσ OR μαρκωσ)" for "markos", so that Latin queries also match the
+    /// Greek fields. Operators, words with query syntax and phrases are left untouched.
+    pub fn expand_query(&self, query: &str) -> String {
+        let mut in_phrase = false;
+        query
+            .split(' ')
+            .map(|word| {
+                let is_quoted = in_phrase || word.contains('"');
+                if word.matches('"').count() % 2 == 1 {
+                    in_phrase = !in_phrase;
+                }
+                let is_plain =
+                    !is_quoted && !word.is_empty() && word.chars().all(|c| c.is_ascii_lowercase());
+                let candidates = if is_plain {
+                    self.to_greek_candidates(word)
+                } else {
+                    Vec::new()
+                };
+                if candidates.is_empty() {
+                    return word.to_string();
+                }
+                format!("({} OR {})", word, candidates.join(" OR "))
+            })
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+}
+
+/// Return the 32-bit FNV-1a hash of the text, which unlike the hashers of the standard
+/// library is the same across runs, platforms and Rust releases.
+pub fn fnv1a(text: &str) -> u32 {
+    text.bytes().fold(0x811c_9dc5_u32, |hash, byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+    })
+}
+
+/// Return a short hexadecimal hash of the text, stable across runs and platforms.
+fn short_hash(text: &str) -> String {
+    format!("{:08x}", fnv1a(text))
+}
+
+/// The slugs given out so far, used to keep them unique.
+#[derive(Default)]
+pub struct Slugs {
+    /// Slugs and the names they were given to.
+    taken: HashMap<String, String>,
+}
+
+impl Slugs {
+    /// Return the slug if it is free, or else warn about the collision and return the slug
+    /// with a hash of the name appended, and a counter if that is taken too.
+    pub fn claim(&mut self, slug: &str, name: &str) -> String {
+        let mut unique_slug = slug.to_string();
+        if let Some(other) = self.taken.get(slug) {
+            warn!(
+                "Slug {} of \"{}\" is already taken by \"{}\"",
+                slug, name, other
+            );
+            unique_slug = format!("{}_{}", slug, short_hash(name));
+            let mut counter = 2;
+            while self.taken.contains_key(&unique_slug) {
+                unique_slug = format!("{}_{}_{}", slug, short_hash(name), counter);
+                counter += 1;
+            }
+        }
+        self.taken.insert(unique_slug.clone(), name.to_string());
+        unique_slug
+    }
+}
+
+/// Return the slug without the hash and counter that `Slugs::claim` appends to the slugs that
+/// collide, e.g. "xasapiko" for "xasapiko_1b2c3d4e_2".
+pub fn strip_disambiguation(slug: &str) -> &str {
+    lazy_static! {
+        static ref SUFFIX: Regex = Regex::new(r"_[0-9a-f]{8}(_[0-9]+)?$").unwrap();
+    }
+    match SUFFIX.find(slug) {
+        Some(suffix) if suffix.start() > 0 => &slug[..suffix.start()],
+        _ => slug,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use tempfile::NamedTempFile;
+
+    use crate::config::NumberPolicy;
+    use crate::transliteration::{fnv1a, strip_disambiguation, Slugs, Transliteration};
+
+    #[test]
+    fn test_fnv1a() {
+        assert_eq!(fnv1a(""), 0x811c_9dc5);
+        assert_eq!(fnv1a("a"), 0xe40c_292c);
+    }
+
+    #[test]
+    fn test_to_greeklish() {
+        let transliteration = Transliteration::new(NumberPolicy::Keep);
+        assert_eq!(
+            transliteration.to_greeklish("Ξενοίκιασε το σπίτι σου, ψυχή μου"),
+            "ksenoikiase_to_spiti_sou_psyxi_mou"
+        );
+        assert_eq!(
+            transliteration.to_greeklish("Αύγουστος Θεσσαλονίκη"),
+            "augoustos_thessaloniki"
+        );
+    }
+
+    #[test]
+    fn test_to_greeklish_numbers() {
+        let transliteration = Transliteration::new(NumberPolicy::Keep);
+        assert_eq!(transliteration.to_greeklish("30 δραχμές"), "30_draxmes");
+        let transliteration = Transliteration::new(NumberPolicy::Drop);
+        assert_eq!(transliteration.to_greeklish("30 δραχμές"), "_draxmes");
+    }
+
+    #[test]
+    fn test_to_greeklish_latin() {
+        let transliteration = Transliteration::new(NumberPolicy::Keep);
+        assert_eq!(
+            transliteration.to_greeklish("Μινόρε του 1936"),
+            "minore_tou_1936"
+        );
+        assert_eq!(
+            transliteration.to_greeklish("Café Aman Αμερική"),
+            "cafe_aman_ameriki"
+        );
+        let transliteration = transliteration.with_allowed_chars("a-z_").unwrap();
+        assert_eq!(
+            transliteration.to_greeklish("Μινόρε του 1936"),
+            "minore_tou_"
+        );
+        let transliteration = transliteration.with_allowed_chars("^a-z]\\").unwrap();
+        assert_eq!(transliteration.to_greeklish("a^b] \\c_[d"), "a^b]\\cd");
+        assert!(transliteration.clone().with_allowed_chars("z-a").is_err());
+        assert!(transliteration.with_allowed_chars("").is_err());
+    }
+
+    #[test]
+    fn test_to_greeklish_aligned() {
+        let transliteration = Transliteration::new(NumberPolicy::Drop);
+        let text = "Θα πάω, Café 1936";
+        let (greeklish, alignment) = transliteration.to_greeklish_aligned(text);
+        assert_eq!(greeklish, transliteration.to_greeklish(text));
+        assert_eq!(alignment.len(), greeklish.len());
+        let source = |from: usize, to: usize| &text[alignment[from].0..alignment[to - 1].1];
+        // "th" comes from "Θ", "pao" from "πάω" and "e" from the decomposed "é".
+        assert_eq!(&greeklish[..2], "th");
+        assert_eq!(source(0, 2), "Θ");
+        assert_eq!(&greeklish[4..7], "pao");
+        assert_eq!(source(4, 7), "πάω");
+        assert_eq!(source(8, 12), "Café");
+    }
+
+    #[test]
+    fn test_to_display_greeklish() {
+        let transliteration = Transliteration::new(NumberPolicy::Drop);
+        let display = |name| transliteration.to_display_greeklish(name);
+        assert_eq!(display("Τα μπλε παράθυρα σου"), "Ta mple parathyra sou");
+        assert_eq!(display("Μάρκος Βαμβακάρης"), "Markos Vamvakaris");
+        assert_eq!(display("Θεσσαλονίκη, 1936"), "Thessaloniki, 1936");
+        assert_eq!(display("ΟΥΣΑΚ"), "OUSAK");
+        assert_eq!(display("Café Αμάν"), "Café Aman");
+    }
+
+    #[test]
+    fn test_to_greeklish_from_file() {
+        let mut file = NamedTempFile::new().unwrap();
+        write!(file, r#"{{"Χ": "ch", "ου": "u"}}"#).unwrap();
+        let path = file.path().to_str().unwrap();
+        let transliteration = Transliteration::from_file(path, NumberPolicy::Keep).unwrap();
+        // The entries of the file override the default ones, and the rest are kept.
+        assert_eq!(transliteration.to_greeklish("Χανιά ου"), "chania_u");
+        assert_eq!(transliteration.to_greeklish("Θεός"), "theos");
+    }
+
+    #[test]
+    fn test_to_greek_candidates() {
+        let transliteration = Transliteration::new(NumberPolicy::Keep);
+        assert_eq!(
+            transliteration.to_greek_candidates("Markos"),
+            vec!["μαρκοσ", "μαρκωσ"]
+        );
+        assert_eq!(
+            transliteration.to_greek_candidates("thalassa"),
+            vec!["θαλασσα"]
+        );
+        assert!(transliteration.to_greek_candidates("b52").is_empty());
+        // Seven ambiguous letters make 128 candidates, but the likeliest ones are kept.
+        let candidates = transliteration.to_greek_candidates("oikonomikotita");
+        assert_eq!(candidates.len(), 8);
+        assert_eq!(candidates[0], "οικονομικοτιτα");
+        assert!(candidates.contains(&String::from("οικονομικοτητα")));
+        for word in &["ksenoikiase", "psyxi", "tsitsanis"] {
+            let candidates = transliteration.to_greek_candidates(word);
+            assert!(!candidates.is_empty());
+            for candidate in candidates {
+                assert_eq!(transliteration.to_greeklish(&candidate), *word);
+            }
+        }
+    }
+
+    #[test]
+    fn test_expand_query() {
+        let transliteration = Transliteration::new(NumberPolicy::Keep);
+        assert_eq!(
+            transliteration.expand_query("markos AND name:vamvakaris"),
+            "(markos OR μαρκοσ OR μαρκωσ) AND name:vamvakaris"
+        );
+        assert_eq!(
+            transliteration.expand_query("\"ta mple parathyra\" sou"),
+            "\"ta mple parathyra\" (sou OR σου)"
+        );
+    }
+
+    #[test]
+    fn test_to_slug() {
+        let transliteration = Transliteration::new(NumberPolicy::Keep);
+        assert_eq!(
+            transliteration.to_slug("Τα μπλε παράθυρά σου"),
+            "ta_mple_parathyra_sou"
+        );
+        assert_eq!(transliteration.to_slug(" Ήρθες - ήρθες! "), "irthes_irthes");
+        assert_eq!(transliteration.to_slug("?!"), "10fa237d");
+        assert_eq!(transliteration.to_slug("?!"), transliteration.to_slug("?!"));
+        assert_ne!(transliteration.to_slug("?!"), transliteration.to_slug("!?"));
+    }
+
+    #[test]
+    fn test_slugs() {
+        let mut slugs = Slugs::default();
+        assert_eq!(slugs.claim("xasapiko", "Χασάπικο"), "xasapiko");
+        let other = slugs.claim("xasapiko", "Χασάπικο (Τσιτσάνης)");
+        assert!(other.starts_with("xasapiko_"));
+        let third = slugs.claim("xasapiko", "Χασάπικο (Τσιτσάνης)");
+        assert_eq!(third, format!("{}_2", other));
+        assert_eq!(strip_disambiguation(&other), "xasapiko");
+        assert_eq!(strip_disambiguation(&third), "xasapiko");
+        assert_eq!(strip_disambiguation("xasapiko"), "xasapiko");
+    }
+}
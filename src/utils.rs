@@ -1,50 +1,257 @@
-use lazy_static::lazy_static;
-use regex::Regex;
-
-/// Return greek string in greeklish.
-pub fn to_greeklish(string: &str) -> String {
-    // We always replace spaces with underscores. We don't need that for searching, only for
-    // storing the slug, but it also works for searching so we leave it like that for simplicity.
-    lazy_static! {
-        static ref RE: Regex = Regex::new(r"[^a-z_\n]").unwrap();
-    }
-    string
-        .to_lowercase()
-        .replace("ψ", "ps")
-        .replace("ξ", "ks")
-        .replace("θ", "th")
-        .replace("ου", "ou")
-        .replace("ού", "ou")
-        .replace("αυ", "au")
-        .replace("αύ", "au")
-        .replace("ευ", "eu")
-        .replace("εύ", "eu")
-        .chars()
-        .map(|c| match c {
-            'α' | 'ά' => 'a',
-            'β' => 'v',
-            'γ' => 'g',
-            'δ' => 'd',
-            'ε' | 'έ' => 'e',
-            'ζ' => 'z',
-            'η' | 'ή' => 'i',
-            'ι' | 'ί' | 'ϊ' | 'ΐ' => 'i',
-            'κ' => 'k',
-            'λ' => 'l',
-            'μ' => 'm',
-            'ν' => 'n',
-            'ο' | 'ό' => 'o',
-            'π' => 'p',
-            'ρ' => 'r',
-            'σ' | 'ς' => 's',
-            'τ' => 't',
-            'υ' | 'ύ' => 'y',
-            'φ' => 'f',
-            'χ' => 'x',
-            'ω' | 'ώ' => 'o',
-            ' ' => '_',
-            x => x,
+use unicode_normalization::UnicodeNormalization;
+
+use crate::greek_lower_caser::to_greek_lowercase;
+use crate::homoglyph_filter::replace_homoglyphs;
+
+/// Pairs of Latin and Greek characters that share a key in the QWERTY and Greek layouts.
+const KEYBOARD_LAYOUT: &[(char, char)] = &[
+    ('a', 'α'),
+    ('b', 'β'),
+    ('c', 'ψ'),
+    ('d', 'δ'),
+    ('e', 'ε'),
+    ('f', 'φ'),
+    ('g', 'γ'),
+    ('h', 'η'),
+    ('i', 'ι'),
+    ('j', 'ξ'),
+    ('k', 'κ'),
+    ('l', 'λ'),
+    ('m', 'μ'),
+    ('n', 'ν'),
+    ('o', 'ο'),
+    ('p', 'π'),
+    ('r', 'ρ'),
+    ('s', 'σ'),
+    ('t', 'τ'),
+    ('u', 'θ'),
+    ('v', 'ω'),
+    ('w', 'ς'),
+    ('x', 'χ'),
+    ('y', 'υ'),
+    ('z', 'ζ'),
+];
+
+/// Return the query as it would have been typed with the other keyboard layout active, mapping
+/// Latin letters to the Greek letters on the same key and vice versa. The ';' key is the
+/// accent dead key on the Greek layout, so it is dropped before letters. The query grammar
+/// operators, field prefixes like "name:" and quotes are left untouched.
+pub fn swap_keyboard_layout(query: &str) -> String {
+    query
+        .split(' ')
+        .map(|word| match word {
+            "AND" | "OR" | "NOT" | "TO" => word.to_string(),
+            word => {
+                let (field, term) = split_field(word);
+                format!("{}{}", field, swap_word(term))
+            }
         })
-        .filter(|&c| !RE.is_match(c.to_string().as_str()))
-        .collect()
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Split a word of a query after its field prefix, e.g. "+name:" of "+name:markos". Words
+/// without a prefix are returned whole as the second part.
+fn split_field(word: &str) -> (&str, &str) {
+    if let Some(colon) = word.find(':') {
+        let name = word[..colon].trim_start_matches(['+', '-', '(']);
+        if !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return word.split_at(colon + 1);
+        }
+    }
+    ("", word)
+}
+
+/// Return the words of the query that the documents should contain, without the query grammar.
+/// Operators, field prefixes, `+` prefixes, boosts and parentheses are dropped, and so are the
+/// words excluded with `-` or NOT, including whole excluded groups and phrases.
+pub fn strip_query_syntax(query: &str) -> String {
+    let mut words = Vec::new();
+    let (mut depth, mut in_phrase, mut negated) = (0, false, false);
+    // The depth of the parentheses where the excluded words started.
+    let mut excluded: Option<usize> = None;
+    for word in query.split_whitespace() {
+        if excluded.is_none() && !in_phrase {
+            match word {
+                "AND" | "OR" | "TO" => continue,
+                "NOT" => {
+                    negated = true;
+                    continue;
+                }
+                _ => {}
+            }
+            if negated || word.starts_with('-') {
+                excluded = Some(depth);
+            }
+            negated = false;
+        }
+        for c in word.chars() {
+            match c {
+                '"' => in_phrase = !in_phrase,
+                '(' if !in_phrase => depth += 1,
+                ')' if !in_phrase => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+        }
+        match excluded {
+            Some(start) if depth <= start && !in_phrase => excluded = None,
+            Some(_) => {}
+            None => {
+                let (_, term) = split_field(word);
+                let term = term.split('^').next().unwrap();
+                let term = term.trim_matches(['+', '-', '(', ')', '"']);
+                if !term.is_empty() {
+                    words.push(term);
+                }
+            }
+        }
+    }
+    words.join(" ")
+}
+
+fn swap_word(word: &str) -> String {
+    let word = to_greek_lowercase(&word.to_lowercase());
+    let mut output = String::with_capacity(word.len());
+    let mut chars = word.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == ';' && chars.peek().is_some_and(|c| c.is_alphabetic()) {
+            continue;
+        }
+        let swapped = KEYBOARD_LAYOUT.iter().find_map(|&(latin, greek)| {
+            if c == latin {
+                Some(greek)
+            } else if c == greek {
+                Some(latin)
+            } else {
+                None
+            }
+        });
+        output.push(swapped.unwrap_or(c));
+    }
+    output
+}
+
+/// The script a text is written in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Script {
+    Greek,
+    Latin,
+    /// Both Greek and Latin letters, or no letters at all.
+    Mixed,
+}
+
+/// Whether the character is a Greek letter, with or without accents.
+pub fn is_greek(c: char) -> bool {
+    matches!(c, '\u{0370}'..='\u{03ff}' | '\u{1f00}'..='\u{1fff}')
+}
+
+/// Detect whether the letters of the text are all Greek, all Latin, or mixed. The query grammar
+/// operators are ignored.
+pub fn detect_script(text: &str) -> Script {
+    let mut greek = false;
+    let mut latin = false;
+    for word in text.split_whitespace() {
+        if let "AND" | "OR" | "NOT" | "TO" = word {
+            continue;
+        }
+        for c in word.chars().filter(|c| c.is_alphabetic()) {
+            if is_greek(c) {
+                greek = true;
+            } else {
+                latin = true;
+            }
+        }
+    }
+    match (greek, latin) {
+        (true, false) => Script::Greek,
+        (false, true) => Script::Latin,
+        _ => Script::Mixed,
+    }
+}
+
+/// Return the query in NFC, lowercased and, if fold_accents is set, without accents, so that
+/// every field sees the same terms regardless of its tokenizer. Words mixing Greek and Latin
+/// letters get their look-alike letters replaced before they are lowercased, since lowercase
+/// letters like b and β don't look alike. The query grammar operators are left untouched.
+pub fn normalize_query(query: &str, fold_accents: bool) -> String {
+    let query: String = query.nfc().collect();
+    let mut buffer = String::new();
+    query
+        .split(' ')
+        .map(|word| {
+            let word = match word {
+                "AND" | "OR" | "NOT" | "TO" => return word.to_string(),
+                word if replace_homoglyphs(word, &mut buffer) => buffer.as_str(),
+                word => word,
+            };
+            if fold_accents {
+                to_greek_lowercase(word)
+            } else {
+                word.to_lowercase()
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::{
+        detect_script, normalize_query, strip_query_syntax, swap_keyboard_layout, Script,
+    };
+
+    #[test]
+    fn test_swap_keyboard_layout() {
+        assert_eq!(swap_keyboard_layout("tsitsanhs"), "τσιτσανησ");
+        assert_eq!(swap_keyboard_layout("M;arkow"), "μαρκος");
+        assert_eq!(swap_keyboard_layout("Μάρκος"), "markos");
+        assert_eq!(swap_keyboard_layout("ΤΣΙΤΣΆΝΗΣ"), "tsitsanhs");
+        assert_eq!(
+            swap_keyboard_layout("name:tsitsanhs AND \"M;arkow bamvakarhs\""),
+            "name:τσιτσανησ AND \"μαρκος βαμωακαρησ\""
+        );
+        assert_eq!(
+            swap_keyboard_layout("+artist_name:Μάρκος -(name:σκλάβος OR ζεϊμπέκικο)"),
+            "+artist_name:markos -(name:sklabos OR zeimpekiko)"
+        );
+    }
+
+    #[test]
+    fn test_strip_query_syntax() {
+        assert_eq!(strip_query_syntax("μαυρα φρυδια"), "μαυρα φρυδια");
+        assert_eq!(strip_query_syntax("μαυρα AND φρυδια"), "μαυρα φρυδια");
+        assert_eq!(strip_query_syntax("+name:μπλε^2 παραθυρα"), "μπλε παραθυρα");
+        assert_eq!(strip_query_syntax("μαυρα -φρυδια σου"), "μαυρα σου");
+        assert_eq!(strip_query_syntax("μαυρα NOT φρυδια σου"), "μαυρα σου");
+        assert_eq!(
+            strip_query_syntax("(μαυρα OR ασπρα) -(name:φρυδια σου) ματια"),
+            "μαυρα ασπρα ματια"
+        );
+        assert_eq!(
+            strip_query_syntax("ματια -\"μαυρα φρυδια\" \"τα μπλε\""),
+            "ματια τα μπλε"
+        );
+    }
+
+    #[test]
+    fn test_detect_script() {
+        assert_eq!(detect_script("Μάρκος Βαμβακάρης"), Script::Greek);
+        assert_eq!(detect_script("markos AND vamvakaris"), Script::Latin);
+        assert_eq!(detect_script("Μάρκος AND vamvakaris"), Script::Mixed);
+        assert_eq!(detect_script("1936"), Script::Mixed);
+    }
+
+    #[test]
+    fn test_normalize_query() {
+        assert_eq!(normalize_query("Πού ΠΆΣ", true), "που πασ");
+        assert_eq!(normalize_query("Πού ΠΆΣ", false), "πού πάς");
+        // Decomposed accents are composed before being stripped.
+        assert_eq!(normalize_query("Πο\u{0301}υ", true), "που");
+        assert_eq!(normalize_query("Που\u{0301}", false), "πού");
+        assert_eq!(
+            normalize_query("Μάρκος AND Βαμβακάρης", true),
+            "μαρκοσ AND βαμβακαρησ"
+        );
+        assert_eq!(normalize_query("Bαμβακάρης", true), "βαμβακαρησ");
+    }
 }
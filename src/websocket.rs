@@ -0,0 +1,108 @@
+use futures::{SinkExt, StreamExt};
+use hyper::header::{HeaderMap, HeaderName};
+use hyper::upgrade::Upgraded;
+use hyper::{header, Body, Request, Response, StatusCode};
+use log::warn;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::protocol::{Message, Role};
+use tokio_tungstenite::WebSocketStream;
+
+use crate::cancellation::CancelFlag;
+use crate::error::{Error, Result};
+use crate::search_engine::{Mode, ResultOptions, SearchEngine, Sort};
+
+/// Upgrade the connection to a WebSocket, where every text message is treated as an
+/// autocomplete query and answered with the results array. Requests that aren't a version 13
+/// WebSocket handshake are rejected.
+pub fn upgrade(request: Request<Body>, search_engine: SearchEngine) -> Response<Body> {
+    let headers = request.headers();
+    let key = match headers.get(header::SEC_WEBSOCKET_KEY) {
+        Some(key)
+            if has_token(headers, header::UPGRADE, "websocket")
+                && has_token(headers, header::CONNECTION, "upgrade")
+                && has_token(headers, header::SEC_WEBSOCKET_VERSION, "13") =>
+        {
+            derive_accept_key(key.as_bytes())
+        }
+        _ => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from("[]"))
+                .unwrap()
+        }
+    };
+
+    tokio::spawn(async move {
+        match hyper::upgrade::on(request).await {
+            Ok(upgraded) => {
+                let stream = WebSocketStream::from_raw_socket(upgraded, Role::Server, None).await;
+                live_search(stream, search_engine).await;
+            }
+            Err(e) => warn!("websocket upgrade error: {}", e),
+        }
+    });
+
+    Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header(header::CONNECTION, "upgrade")
+        .header(header::UPGRADE, "websocket")
+        .header(header::SEC_WEBSOCKET_ACCEPT, key)
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// Whether one of the comma separated values of the header is the token, ignoring case, e.g.
+/// "keep-alive, Upgrade" has "upgrade".
+fn has_token(headers: &HeaderMap, name: HeaderName, token: &str) -> bool {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|value| value.trim().eq_ignore_ascii_case(token))
+}
+
+/// Answer queries until the client goes away. Only the latest query is answered: the search of
+/// the previous query is cancelled when a new query arrives, whether it is still waiting for a
+/// thread or already running.
+async fn live_search(stream: WebSocketStream<Upgraded>, search_engine: SearchEngine) {
+    let (mut sink, mut stream) = stream.split();
+    let mut pending: Option<(JoinHandle<Result<String>>, CancelFlag)> = None;
+
+    loop {
+        tokio::select! {
+            message = stream.next() => match message {
+                Some(Ok(Message::Text(query))) => {
+                    if let Some((handle, cancel)) = pending.take() {
+                        cancel.cancel();
+                        handle.abort();
+                    }
+                    let search_engine = search_engine.clone();
+                    let options = ResultOptions {
+                        endpoint: Some("live"),
+                        ..ResultOptions::default()
+                    };
+                    let cancel = options.cancel.clone();
+                    let handle = tokio::task::spawn_blocking(move || {
+                        search_engine.search(&query, Mode::Ngram, Sort::Relevance, &options)
+                    });
+                    pending = Some((handle, cancel));
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+            result = async { (&mut pending.as_mut().unwrap().0).await }, if pending.is_some() => {
+                pending = None;
+                let response = match result {
+                    Ok(Ok(response)) => response,
+                    Ok(Err(Error::Cancelled)) | Err(_) => continue,
+                    Ok(Err(e)) => e.to_json(),
+                };
+                if sink.send(Message::Text(response)).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
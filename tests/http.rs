@@ -0,0 +1,482 @@
+use std::net::{SocketAddr, TcpListener};
+use std::sync::Arc;
+
+use futures::{SinkExt, StreamExt};
+use hyper::{header, Body, Client, Method, Request, StatusCode};
+use serde_json::{json, Value};
+use tokio::sync::{broadcast, watch};
+use tokio_tungstenite::tungstenite::Message;
+use url::form_urlencoded;
+
+use buzuki_search::config::{Config, Vars};
+use buzuki_search::http;
+use buzuki_search::search_engine::SearchEngine;
+
+const ADMIN_TOKEN: &str = "secret";
+
+/// Variables of the server over the songs in tests/fixtures/songs.
+fn test_vars() -> Vars {
+    [
+        (
+            "BUZUKI_SONGDIR",
+            concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/songs"),
+        ),
+        ("BUZUKI_ADMIN_TOKEN", ADMIN_TOKEN),
+        ("BUZUKI_NUMBERS", "keep"),
+        ("BUZUKI_GREEKLISH_NAMES", "true"),
+        ("BUZUKI_HIGHLIGHTS", "true"),
+        ("BUZUKI_BASE_URL", "https://buzuki.gr"),
+        ("BUZUKI_SEARCH_MAX_QUERY_CHARS", "100"),
+        ("BUZUKI_SEARCH_QUERY_LENGTH_POLICY", "reject"),
+        ("BUZUKI_AUTOCOMPLETE_MAX_QUERY_CHARS", "20"),
+        ("BUZUKI_AUTOCOMPLETE_QUERY_LENGTH_POLICY", "reject"),
+        ("BUZUKI_AUTOCOMPLETE_MAX_LIMIT", "30"),
+        ("BUZUKI_AUTOCOMPLETE_FIELDS", "name,url"),
+    ]
+    .iter()
+    .map(|&(key, value)| (key.to_string(), value.to_string()))
+    .collect()
+}
+
+/// Start the server on a free port and return its address.
+fn start_server() -> SocketAddr {
+    start_server_with(&test_vars()).0
+}
+
+/// Start the server with the given variables on a free port and return its address and the
+/// channel that reloads its configuration.
+fn start_server_with(vars: &Vars) -> (SocketAddr, watch::Sender<Arc<Config>>) {
+    let config = Config::from_vars(vars).unwrap();
+    let search_engine = SearchEngine::new(&config).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (events, _) = broadcast::channel(16);
+    let (reload, config) = watch::channel(Arc::new(config));
+    tokio::spawn(http::serve(listener, search_engine, config, events));
+    (addr, reload)
+}
+
+struct TestResponse {
+    status: StatusCode,
+    content_type: String,
+    allow_origin: Option<String>,
+    body: String,
+}
+
+impl TestResponse {
+    fn json(&self) -> Value {
+        serde_json::from_str(&self.body).unwrap()
+    }
+
+    fn urls(&self) -> Vec<String> {
+        self.json()
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|hit| hit["url"].as_str().unwrap().to_string())
+            .collect()
+    }
+}
+
+async fn request(addr: SocketAddr, method: Method, path: &str, admin: bool) -> TestResponse {
+    let mut builder = Request::builder()
+        .method(method)
+        .uri(format!("http://{}{}", addr, path));
+    if admin {
+        builder = builder.header(header::AUTHORIZATION, format!("Bearer {}", ADMIN_TOKEN));
+    }
+    let response = Client::new()
+        .request(builder.body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .map(|value: &header::HeaderValue| value.to_str().unwrap().to_string())
+    };
+    let content_type = header(header::CONTENT_TYPE).unwrap_or_default();
+    let allow_origin = header(header::ACCESS_CONTROL_ALLOW_ORIGIN);
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    TestResponse {
+        status,
+        content_type,
+        allow_origin,
+        body: String::from_utf8(body.to_vec()).unwrap(),
+    }
+}
+
+fn encode(query: &str) -> String {
+    form_urlencoded::byte_serialize(query.as_bytes()).collect()
+}
+
+async fn get(addr: SocketAddr, path: &str) -> TestResponse {
+    request(addr, Method::GET, path, false).await
+}
+
+#[tokio::test]
+async fn test_search() {
+    let addr = start_server();
+
+    let response = get(addr, &format!("/?q={}", encode("φραγκοσυριανη"))).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.content_type, "application/json");
+    assert_eq!(response.allow_origin.as_deref(), Some("*"));
+    assert_eq!(response.urls()[0], "/songs/fragkosyriani/");
+
+    // Greeklish queries find the same song.
+    let response = get(addr, "/?q=fragkosyriani").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.urls()[0], "/songs/fragkosyriani/");
+
+    let response = get(addr, "/?q=fragkosyriani&v=2").await;
+    assert_eq!(response.status, StatusCode::OK);
+    let envelope = response.json();
+    assert_eq!(envelope["query"], "fragkosyriani");
+    assert!(envelope["total"].as_u64().unwrap() >= 1);
+    assert_eq!(envelope["results"][0]["url"], "/songs/fragkosyriani/");
+    assert_eq!(envelope["results"][0]["name_greeklish"], "Fragkosyriani");
+
+    let response = get(addr, "/?q=fragkosyriani&count=true").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(response.json()["count"].as_u64().unwrap() >= 1);
+
+    // Words of the lyrics don't match titles.
+    let response = get(addr, &format!("/?q={}&in=name", encode("καμαρωνα"))).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.json(), Value::Array(vec![]));
+    let response = get(
+        addr,
+        &format!("/?q={}&in=name&count=true", encode("καμαρωνα")),
+    )
+    .await;
+    assert_eq!(response.json()["count"], 0);
+    let response = get(addr, "/?q=fragkosyriani&in=name").await;
+    assert_eq!(response.urls()[0], "/songs/fragkosyriani/");
+    let response = get(addr, "/?q=fragkosyriani&in=lyrics").await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+
+    let response = get(addr, "/?q=floga&matched=true").await;
+    assert_eq!(
+        response.json()[0]["matched"],
+        json!(["lyrics", "greeklish"])
+    );
+}
+
+#[tokio::test]
+async fn test_cors_origins() {
+    let mut vars = test_vars();
+    let (addr, reload) = start_server_with(&vars);
+    let allow_origin = |origin: &'static str| async move {
+        let request = Request::get(format!("http://{}/?q=fragkosyriani", addr))
+            .header(header::ORIGIN, origin)
+            .body(Body::empty())
+            .unwrap();
+        let response = Client::new().request(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        response
+            .headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .map(|value| value.to_str().unwrap().to_string())
+    };
+    assert_eq!(
+        allow_origin("https://example.com").await.as_deref(),
+        Some("*")
+    );
+
+    // Reloading the config restricts the origins of the following requests.
+    vars.insert(
+        String::from("BUZUKI_CORS_ORIGINS"),
+        String::from("https://buzuki.gr/, https://m.buzuki.gr"),
+    );
+    reload
+        .send(Arc::new(Config::from_vars(&vars).unwrap()))
+        .unwrap();
+    assert_eq!(
+        allow_origin("https://m.buzuki.gr").await.as_deref(),
+        Some("https://m.buzuki.gr")
+    );
+    assert_eq!(
+        allow_origin("https://buzuki.gr").await.as_deref(),
+        Some("https://buzuki.gr")
+    );
+    assert_eq!(allow_origin("https://example.com").await, None);
+}
+
+#[tokio::test]
+async fn test_filters() {
+    let addr = start_server();
+
+    // Browsing sorts by name by default.
+    let response = get(addr, "/?q=&scale=ousak&rhythm=zeimpekiko").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(
+        response.urls(),
+        vec![
+            "/songs/o_markos_ston_peiraia/",
+            "/songs/synnefiasmeni_kyriaki/"
+        ]
+    );
+
+    let response = get(addr, &format!("/?q={}&scale=ousak", encode("κυριακη"))).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.urls(), vec!["/songs/synnefiasmeni_kyriaki/"]);
+
+    let response = get(addr, "/?q=fragkosyriani&rhythm=zeimpekiko").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.json(), Value::Array(vec![]));
+
+    let response = get(addr, "/?artist=vamvakaris&sort=popularity").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.urls().len(), 2);
+
+    // Counts agree with the filtered results.
+    let response = get(addr, "/?q=&scale=ousak&rhythm=zeimpekiko&count=true").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.json()["count"], 2);
+    let response = get(addr, "/?q=fragkosyriani&rhythm=zeimpekiko&count=true").await;
+    assert_eq!(response.json()["count"], 0);
+}
+
+#[tokio::test]
+async fn test_autocomplete() {
+    let addr = start_server();
+
+    let response = get(addr, "/autocomplete/?q=fragk").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.content_type, "application/json");
+    assert!(response
+        .urls()
+        .contains(&"/songs/fragkosyriani/".to_string()));
+
+    let response = get(addr, "/autocomplete/?q=zzzzzz").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.json(), Value::Array(vec![]));
+
+    // Only the configured fields are returned, and up to the maximum limit of results.
+    let response = get(addr, "/autocomplete/?q=a&limit=1").await;
+    assert_eq!(response.status, StatusCode::OK);
+    let hits = response.json();
+    assert_eq!(hits.as_array().unwrap().len(), 1);
+    let mut fields: Vec<&String> = hits[0].as_object().unwrap().keys().collect();
+    fields.sort();
+    assert_eq!(fields, vec!["name", "url"]);
+    let response = get(addr, "/autocomplete/?q=a&limit=31").await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+
+    // Consecutive requests of the same client are all answered once the previous one is done.
+    for query in &["fr", "fra", "fragk"] {
+        let response = get(addr, &format!("/autocomplete/?q={}&client=abc", query)).await;
+        assert_eq!(response.status, StatusCode::OK);
+    }
+}
+
+#[tokio::test]
+async fn test_errors() {
+    let addr = start_server();
+
+    let response = get(addr, "/").await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    assert_eq!(response.body, "[]");
+
+    let response = get(addr, "/unknown/").await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    assert_eq!(response.content_type, "application/json");
+
+    let response = get(addr, "/?q=fragkosyriani&sort=random").await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    assert!(response.json()["error"].is_string());
+    assert_eq!(response.json()["kind"], "query");
+    let response = get(addr, "/autocomplete/?q=fragk&sort=name").await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    assert_eq!(response.json()["kind"], "query");
+
+    let response = get(addr, "/?q=unknown:fragkosyriani").await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    assert_eq!(response.json()["kind"], "query");
+
+    let response = get(addr, "/autocomplete/?q=fragkosyriani+fragkosyriani").await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    assert!(response.json()["error"].is_string());
+
+    let response = get(addr, "/?q=fragkosyriani&highlight=maybe").await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    assert_eq!(response.json()["kind"], "query");
+
+    let response = get(addr, "/?q=fragkosyriani&variant=c").await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    assert_eq!(response.json()["kind"], "query");
+
+    let response = request(addr, Method::POST, "/?q=fragkosyriani", false).await;
+    assert_eq!(response.status, StatusCode::METHOD_NOT_ALLOWED);
+}
+
+#[tokio::test]
+async fn test_admin() {
+    let addr = start_server();
+
+    for (method, path) in &[
+        (Method::GET, "/?q=fragkosyriani&explain=true"),
+        (
+            Method::GET,
+            "/debug/tokenize/?text=fragkosyriani&tokenizer=en_simple",
+        ),
+        (Method::GET, "/debug/parse-query/?q=fragkosyriani"),
+        (Method::GET, "/admin/export/"),
+        (Method::GET, "/admin/terms/?field=slug"),
+        (Method::POST, "/admin/reindex/"),
+    ] {
+        let response = request(addr, method.clone(), path, false).await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN, "{}", path);
+        let response = request(addr, method.clone(), path, true).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", path);
+    }
+
+    let response = request(addr, Method::POST, "/admin/reindex/", true).await;
+    assert!(response.json()["documents"].as_u64().unwrap() > 0);
+
+    let response = request(addr, Method::GET, "/admin/export/", true).await;
+    assert_eq!(response.content_type, "application/x-ndjson");
+    assert!(response.body.lines().count() > 0);
+
+    let path = format!("/admin/terms/?field=body&prefix={}", encode("μου"));
+    let response = request(addr, Method::GET, &path, true).await;
+    assert_eq!(response.json()["terms"][0]["term"], "μου");
+    let response = request(addr, Method::GET, "/admin/terms/?field=kind", true).await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    let response = request(addr, Method::GET, "/admin/terms/", true).await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_compression() {
+    let addr = start_server();
+
+    let request = Request::get(format!("http://{}/schema/", addr))
+        .header(header::ACCEPT_ENCODING, "gzip")
+        .body(Body::empty())
+        .unwrap();
+    let response = Client::new().request(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+
+    // Events are sent as they happen.
+    let request = Request::get(format!("http://{}/events/", addr))
+        .header(header::ACCEPT_ENCODING, "gzip")
+        .body(Body::empty())
+        .unwrap();
+    let response = Client::new().request(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+}
+
+#[tokio::test]
+async fn test_websocket() {
+    let addr = start_server();
+
+    let url = format!("ws://{}/ws/", addr);
+    let (mut stream, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+    stream
+        .send(Message::Text(String::from("fragk")))
+        .await
+        .unwrap();
+    let response = match stream.next().await {
+        Some(Ok(Message::Text(response))) => response,
+        message => panic!("Unexpected message {:?}", message),
+    };
+    let hits: Value = serde_json::from_str(&response).unwrap();
+    assert_eq!(hits[0]["url"], "/songs/fragkosyriani/");
+
+    // Requests without the upgrade headers or of another version aren't handshakes.
+    let handshake = |upgrade: &str, version: &str| {
+        let request = Request::builder()
+            .uri(format!("http://{}/ws/", addr))
+            .header(header::SEC_WEBSOCKET_KEY, "dGhlIHNhbXBsZSBub25jZQ==")
+            .header(header::SEC_WEBSOCKET_VERSION, version)
+            .header(header::CONNECTION, upgrade)
+            .header(header::UPGRADE, "websocket")
+            .body(Body::empty())
+            .unwrap();
+        Client::new().request(request)
+    };
+    let response = handshake("keep-alive", "13").await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = handshake("Upgrade", "8").await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = handshake("keep-alive, Upgrade", "13").await.unwrap();
+    assert_eq!(response.status(), StatusCode::SWITCHING_PROTOCOLS);
+}
+
+#[tokio::test]
+async fn test_ui() {
+    let addr = start_server();
+
+    let response = get(addr, "/ui").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.content_type, "text/html; charset=utf-8");
+    assert!(response.body.contains("/autocomplete/"));
+}
+
+#[tokio::test]
+async fn test_healthcheck() {
+    let addr = start_server();
+
+    let healthcheck = |addr: String| {
+        tokio::process::Command::new(env!("CARGO_BIN_EXE_buzuki-search"))
+            .arg("healthcheck")
+            .env("BUZUKI_SONGDIR", "/nonexistent")
+            .env("BUZUKI_HTTP_ADDR", addr)
+            .env("RUST_LOG", "off")
+            .status()
+    };
+    assert!(healthcheck(addr.to_string()).await.unwrap().success());
+
+    // Nothing listens on the port of a dropped listener.
+    let closed_addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    assert!(!healthcheck(closed_addr.to_string())
+        .await
+        .unwrap()
+        .success());
+}
+
+#[tokio::test]
+async fn test_sitemap() {
+    let addr = start_server();
+
+    let response = get(addr, "/sitemap.xml").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.content_type, "application/xml");
+    assert!(response
+        .body
+        .contains("<loc>https://buzuki.gr/songs/fragkosyriani/</loc>"));
+}
+
+#[tokio::test]
+async fn test_stats() {
+    let addr = start_server();
+
+    let response = get(addr, "/stats/").await;
+    assert_eq!(response.status, StatusCode::OK);
+    let stats = response.json();
+    assert!(stats["documents"]["songs"].as_u64().unwrap() > 0);
+    assert!(stats["bytes"]["stored"].as_u64().unwrap() > 0);
+    assert_eq!(stats["previews"], true);
+    assert_eq!(stats["reindex_failure"], Value::Null);
+    assert_eq!(stats["zero_results"], json!({}));
+
+    get(addr, "/?q=xyzzy").await;
+    get(addr, "/autocomplete/?q=xyz").await;
+    let stats = get(addr, "/stats/").await.json();
+    assert_eq!(
+        stats["zero_results"],
+        json!({"autocomplete": 1, "search": 1})
+    );
+
+    let response = get(addr, "/health/").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.json()["status"], "ok");
+}
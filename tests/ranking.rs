@@ -0,0 +1,100 @@
+//! Ranking regression tests. The top results of the canonical queries of
+//! tests/fixtures/ranking/queries.txt over the songs of tests/fixtures/ranking/songs are compared
+//! with the golden files of tests/fixtures/ranking/golden, one per query, so that a change to
+//! the analyzers or the ranking shows exactly which orderings it changes. Run with
+//! `BUZUKI_UPDATE_GOLDEN=1` to write the current results to the golden files instead, and
+//! review their diff.
+
+use std::fs;
+use std::path::Path;
+
+use buzuki_search::config::{Boosts, NumberPolicy};
+use buzuki_search::search_engine::{Mode, SearchEngine, Sort};
+use buzuki_search::transliteration::Transliteration;
+use buzuki_search::utils::is_greek;
+
+const RANKING: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/ranking");
+
+/// Number of results that are compared.
+const TOP_N: usize = 5;
+
+/// The parser and the text of each canonical query.
+fn read_queries() -> Vec<(String, String)> {
+    let queries = fs::read_to_string(Path::new(RANKING).join("queries.txt")).unwrap();
+    queries
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let (mode, query) = line.split_once(' ').expect("Expected parser and query");
+            (mode.to_string(), query.trim().to_string())
+        })
+        .collect()
+}
+
+/// The query followed by the urls of its top results, one per line.
+fn render(engine: &SearchEngine, mode: &str, query: &str) -> String {
+    let parsed_mode: Mode = mode.parse().unwrap();
+    let hits = engine.hits(query, parsed_mode, Sort::Relevance).unwrap();
+    let mut rendered = format!("{} {}\n", mode, query);
+    for hit in hits.iter().take(TOP_N) {
+        rendered.push_str(&hit["url"]);
+        rendered.push('\n');
+    }
+    rendered
+}
+
+#[test]
+fn test_ranking() {
+    let engine = SearchEngine::builder(format!("{}/songs", RANKING))
+        .boosts(Boosts::default())
+        .build()
+        .unwrap();
+    let transliteration = Transliteration::new(NumberPolicy::Keep);
+    let golden_dir = Path::new(RANKING).join("golden");
+    let update = std::env::var_os("BUZUKI_UPDATE_GOLDEN").is_some();
+
+    let mut names = Vec::new();
+    let mut changed = Vec::new();
+    for (mode, query) in read_queries() {
+        // Greek queries are named after their greeklish too, so they are told apart from it.
+        let script = if query.chars().any(is_greek) {
+            "el_"
+        } else {
+            ""
+        };
+        let name = format!("{}_{}{}.txt", mode, script, transliteration.to_slug(&query));
+        assert!(!names.contains(&name), "Duplicate golden file {}", name);
+        let path = golden_dir.join(&name);
+        let actual = render(&engine, &mode, &query);
+        let expected = fs::read_to_string(&path).unwrap_or_default();
+        if actual != expected {
+            if update {
+                fs::write(&path, &actual).unwrap();
+            }
+            changed.push(format!(
+                "{}:\n--- expected\n{}+++ actual\n{}",
+                name, expected, actual
+            ));
+        }
+        names.push(name);
+    }
+    // Golden files of queries that were removed.
+    for entry in fs::read_dir(&golden_dir).unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        if !names.contains(&name) {
+            if update {
+                fs::remove_file(&path).unwrap();
+            }
+            changed.push(format!("{}: no such query", name));
+        }
+    }
+    assert!(
+        update || changed.is_empty(),
+        "The rankings of {} queries changed, rerun with BUZUKI_UPDATE_GOLDEN=1 if that is \
+         intended:\n\n{}",
+        changed.len(),
+        changed.join("\n")
+    );
+}
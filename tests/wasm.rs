@@ -0,0 +1,75 @@
+//! What runs in the browser. Songs are parsed and transliterated and text is analyzed like on
+//! the server. Tantivy's index writer runs on threads, which wasm32 doesn't have, so indexes
+//! can't be built there, but an index serialized natively is searched like on the server. Run
+//! with `make wasm-test`, which serializes the index of the fixture songs first, and needs
+//! wasm-bindgen-test-runner of the same version as wasm-bindgen.
+#![cfg(target_arch = "wasm32")]
+
+use tantivy::tokenizer::{SimpleTokenizer, TextAnalyzer};
+use wasm_bindgen_test::wasm_bindgen_test;
+
+use buzuki_search::config::NumberPolicy;
+use buzuki_search::greek_lower_caser::GreekLowerCaser;
+use buzuki_search::search_engine::{Mode, ResultOptions, SearchEngine, Sort};
+use buzuki_search::song::Song;
+use buzuki_search::transliteration::Transliteration;
+use buzuki_search::utils::normalize_query;
+
+fn songs() -> Vec<Song> {
+    let transliteration = Transliteration::new(NumberPolicy::Drop);
+    vec![Song::new(
+        "Φραγκοσυριανή",
+        "Μάρκος Βαμβακάρης",
+        "Μια φούντωση, μια φλόγα έχω μες στην καρδιά μου",
+        &transliteration,
+    )]
+}
+
+#[wasm_bindgen_test]
+fn test_song() {
+    let song = &songs()[0];
+    assert_eq!(song.slug, "fragkosyriani");
+    assert_eq!(song.artist_slug, "markos_vamvakaris");
+    assert!(song.body_greeklish.contains("floga"));
+}
+
+#[wasm_bindgen_test]
+fn test_analysis() {
+    assert_eq!(
+        normalize_query("Φλόγα AND Καρδιά", true),
+        "φλογα AND καρδια"
+    );
+    let analyzer = TextAnalyzer::from(SimpleTokenizer).filter(GreekLowerCaser::default());
+    let mut tokens = Vec::new();
+    analyzer
+        .token_stream("Μες στην ΚΑΡΔΙΆ")
+        .process(&mut |token| tokens.push(token.text.clone()));
+    assert_eq!(tokens, vec!["μεσ", "στην", "καρδια"]);
+}
+
+#[wasm_bindgen_test]
+fn test_indexing_fails() {
+    assert!(SearchEngine::from_songs(songs()).is_err());
+}
+
+#[wasm_bindgen_test]
+fn test_serialized() {
+    let serialized = include_bytes!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/target/wasm-test/index"
+    ));
+    let engine = SearchEngine::from_serialized(serialized).unwrap();
+    let options = ResultOptions::default();
+    for &(query, mode) in &[
+        ("μαυρα φρυδια", Mode::Full),
+        ("maura frydia", Mode::Full),
+        ("μπλε παραθ", Mode::Ngram),
+    ] {
+        let hits = engine
+            .search(query, mode, Sort::Relevance, &options)
+            .unwrap();
+        let hits: Vec<serde_json::Value> = serde_json::from_str(&hits).unwrap();
+        assert_eq!(hits[0]["url"], "/songs/ta_mple_parathyra_sou/", "{}", query);
+    }
+    assert!(engine.reindex().is_err());
+}